@@ -0,0 +1,196 @@
+//! Conversion between the text form of POSIX ACLs stored in `SCHILY.acl.*`
+//! pax records and the binary form of the `system.posix_acl_*` extended
+//! attributes of Linux.
+
+use std::io;
+use std::str;
+
+use crate::other;
+use crate::owner::{lookup_id, lookup_name};
+
+/// The extended attribute holding the access ACL of a file.
+pub(crate) const ACCESS_XATTR: &str = "system.posix_acl_access";
+/// The extended attribute holding the default ACL of a directory.
+pub(crate) const DEFAULT_XATTR: &str = "system.posix_acl_default";
+
+const ACL_VERSION: u32 = 2;
+const ACL_UNDEFINED_ID: u32 = u32::MAX;
+
+const ACL_USER_OBJ: u16 = 0x01;
+const ACL_USER: u16 = 0x02;
+const ACL_GROUP_OBJ: u16 = 0x04;
+const ACL_GROUP: u16 = 0x08;
+const ACL_MASK: u16 = 0x10;
+const ACL_OTHER: u16 = 0x20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct AclEntry {
+    tag: u16,
+    id: u32,
+    perm: u16,
+}
+
+/// Converts an ACL in the text form written by GNU tar and star, such as
+/// `user::rw-,user:lisa:r--:1001,group::r--,mask::r--,other::---`, to the
+/// value of a `system.posix_acl_*` extended attribute.
+///
+/// Named users and groups are looked up on this system unless star recorded
+/// their numeric ids as well.
+pub(crate) fn text_to_xattr(text: &[u8]) -> io::Result<Vec<u8>> {
+    let text = str::from_utf8(text).map_err(|_| other("ACL is not valid UTF-8"))?;
+    let mut entries = Vec::new();
+    for entry in text.split([',', '\n']) {
+        let entry = match entry.find('#') {
+            Some(i) => &entry[..i],
+            None => entry,
+        };
+        let entry = entry.trim();
+        if entry.is_empty() {
+            continue;
+        }
+        entries.push(parse_entry(entry)?);
+    }
+    if entries.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    // A mask is required as soon as there are named entries, compute it like
+    // `setfacl` does if it's missing.
+    let named = entries
+        .iter()
+        .any(|e| e.tag == ACL_USER || e.tag == ACL_GROUP);
+    if named && !entries.iter().any(|e| e.tag == ACL_MASK) {
+        let perm = entries
+            .iter()
+            .filter(|e| matches!(e.tag, ACL_USER | ACL_GROUP_OBJ | ACL_GROUP))
+            .fold(0, |perm, e| perm | e.perm);
+        entries.push(AclEntry {
+            tag: ACL_MASK,
+            id: ACL_UNDEFINED_ID,
+            perm,
+        });
+    }
+    // The kernel expects the entries sorted by tag and id.
+    entries.sort();
+    for &tag in &[ACL_USER_OBJ, ACL_GROUP_OBJ, ACL_OTHER] {
+        if entries.iter().filter(|e| e.tag == tag).count() != 1 {
+            return Err(other(&format!("invalid ACL `{}`", text)));
+        }
+    }
+
+    let mut value = Vec::with_capacity(4 + entries.len() * 8);
+    value.extend_from_slice(&ACL_VERSION.to_le_bytes());
+    for entry in entries {
+        value.extend_from_slice(&entry.tag.to_le_bytes());
+        value.extend_from_slice(&entry.perm.to_le_bytes());
+        value.extend_from_slice(&entry.id.to_le_bytes());
+    }
+    Ok(value)
+}
+
+/// Converts the value of a `system.posix_acl_*` extended attribute to the
+/// text form written by star, which records the numeric ids of named users
+/// and groups along with their names.
+pub(crate) fn xattr_to_text(value: &[u8]) -> io::Result<String> {
+    if value.len() < 4 || (value.len() - 4) & 7 != 0 {
+        return Err(other("malformed ACL extended attribute"));
+    }
+    let version = u32::from_le_bytes([value[0], value[1], value[2], value[3]]);
+    if version != ACL_VERSION {
+        return Err(other(&format!("unsupported ACL version {}", version)));
+    }
+    let entries = value[4..]
+        .chunks(8)
+        .map(|e| AclEntry {
+            tag: u16::from_le_bytes([e[0], e[1]]),
+            perm: u16::from_le_bytes([e[2], e[3]]),
+            id: u32::from_le_bytes([e[4], e[5], e[6], e[7]]),
+        })
+        .collect::<Vec<_>>();
+
+    let mut text = String::new();
+    for entry in entries {
+        if !text.is_empty() {
+            text.push(',');
+        }
+        let (tag, user) = match entry.tag {
+            ACL_USER_OBJ | ACL_USER => ("user", true),
+            ACL_GROUP_OBJ | ACL_GROUP => ("group", false),
+            ACL_MASK => ("mask", false),
+            ACL_OTHER => ("other", false),
+            tag => return Err(other(&format!("unknown ACL tag {:#x}", tag))),
+        };
+        let perm = [(4, 'r'), (2, 'w'), (1, 'x')]
+            .iter()
+            .map(|&(bit, c)| if entry.perm & bit != 0 { c } else { '-' })
+            .collect::<String>();
+        if entry.tag == ACL_USER || entry.tag == ACL_GROUP {
+            match lookup_name(entry.id, user) {
+                Some(name) => text.push_str(&format!("{}:{}:{}:{}", tag, name, perm, entry.id)),
+                None => text.push_str(&format!("{}:{}:{}", tag, entry.id, perm)),
+            }
+        } else {
+            text.push_str(&format!("{}::{}", tag, perm));
+        }
+    }
+    Ok(text)
+}
+
+/// Returns whether the value of a `system.posix_acl_access` extended
+/// attribute only holds the entries which are equivalent to the permission
+/// bits of the file, so that there's no need to archive it.
+pub(crate) fn is_minimal(value: &[u8]) -> bool {
+    value.len() <= 4 + 3 * 8
+}
+
+fn parse_entry(entry: &str) -> io::Result<AclEntry> {
+    let invalid = || other(&format!("invalid ACL entry `{}`", entry));
+    let mut fields = entry.split(':');
+    let tag = fields.next().ok_or_else(invalid)?;
+    let qualifier = fields.next().ok_or_else(invalid)?;
+    let perm = fields.next().ok_or_else(invalid)?;
+    let id = fields.next();
+    if fields.next().is_some() {
+        return Err(invalid());
+    }
+
+    let named = !qualifier.is_empty();
+    let (tag, user) = match (tag, named) {
+        ("user", false) | ("u", false) => (ACL_USER_OBJ, true),
+        ("user", true) | ("u", true) => (ACL_USER, true),
+        ("group", false) | ("g", false) => (ACL_GROUP_OBJ, false),
+        ("group", true) | ("g", true) => (ACL_GROUP, false),
+        ("mask", false) | ("m", false) => (ACL_MASK, false),
+        ("other", false) | ("o", false) => (ACL_OTHER, false),
+        _ => return Err(invalid()),
+    };
+
+    let mut bits = 0;
+    for c in perm.chars() {
+        bits |= match c {
+            'r' => 4,
+            'w' => 2,
+            'x' => 1,
+            '-' => 0,
+            _ => return Err(invalid()),
+        };
+    }
+
+    let id = if !named {
+        ACL_UNDEFINED_ID
+    } else if let Some(id) = id.and_then(|id| id.parse().ok()) {
+        id
+    } else if let Ok(id) = qualifier.parse() {
+        id
+    } else {
+        lookup_id(qualifier, user).ok_or_else(|| {
+            let what = if user { "user" } else { "group" };
+            other(&format!("unknown {} `{}` in ACL", what, qualifier))
+        })?
+    };
+    Ok(AclEntry {
+        tag,
+        id,
+        perm: bits,
+    })
+}
@@ -5,13 +5,28 @@ use std::fs;
 use std::io::prelude::*;
 use std::io::{self, SeekFrom};
 use std::marker;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
+use crate::compare::Difference;
+use crate::diff::{ArchiveChange, Diff, Summary};
 use crate::entry::{EntryFields, EntryIo};
 use crate::error::TarError;
+use crate::idmap::IdMap;
+use crate::limits::{LimitError, LimitKind, Limits};
 use crate::other;
-use crate::pax::pax_extensions_size;
-use crate::{Entry, GnuExtSparseHeader, GnuSparseHeader, Header};
+use crate::overwrite::{temp_name, OverwritePolicy};
+use crate::owner::OwnerResolver;
+use crate::pax::{parse_pax_num, pax_extensions_size};
+use crate::plan::UnpackPlan;
+use crate::rewrite::PathRewrite;
+use crate::target::UnpackTarget;
+use crate::validate::validate;
+use crate::volume::is_end_of_volume;
+use crate::whiteout::{Layer, Whiteouts};
+use crate::{
+    Entry, GnuExtSparseHeader, GnuHeader, GnuSparseHeader, Header, PaxExtensions, ValidationReport,
+};
 
 /// A top-level representation of an archive file.
 ///
@@ -23,14 +38,54 @@ pub struct Archive<R: ?Sized + Read> {
 pub struct ArchiveInner<R: ?Sized> {
     pos: Cell<u64>,
     unpack_xattrs: bool,
+    unpack_acls: bool,
     preserve_permissions: bool,
     preserve_ownerships: bool,
+    numeric_owner: bool,
+    owner_resolver: Option<Arc<dyn OwnerResolver>>,
+    uid_map: Option<Arc<IdMap>>,
+    gid_map: Option<Arc<IdMap>>,
+    ownership_xattr: Option<Arc<str>>,
     preserve_mtime: bool,
-    overwrite: bool,
+    overwrite: OverwritePolicy,
+    unpack_beneath: bool,
+    transactional: bool,
+    whiteouts: Whiteouts,
+    rewrite: Option<Arc<PathRewrite>>,
     ignore_zeros: bool,
+    recover: bool,
+    limits: Limits,
+    skipped: RefCell<Vec<SkippedRegion>>,
+    volume: RefCell<VolumeState>,
+    /// The bytes read while recording, see `Archive::delete_into`.
+    record: RefCell<Option<Vec<u8>>>,
     obj: RefCell<R>,
 }
 
+/// State needed to read GNU multi-volume archives, see `MultiVolume`.
+#[derive(Default)]
+struct VolumeState {
+    /// Labels of all volumes read so far.
+    labels: Vec<Vec<u8>>,
+    /// A header read at the start of a volume which is part of the archive.
+    pending: Vec<u8>,
+    /// Position of the data of the last member read.
+    member_start: u64,
+    /// Size of the data of the last member read.
+    member_size: u64,
+    /// Position just past the data of the last member read.
+    member_end: u64,
+}
+
+/// A region of a damaged archive which was skipped while recovering, see
+/// `Archive::set_recover`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SkippedRegion {
+    offset: u64,
+    length: u64,
+    reason: String,
+}
+
 /// An iterator over the entries of an archive.
 pub struct Entries<'a, R: 'a + Read> {
     fields: EntriesFields<'a>,
@@ -46,6 +101,10 @@ struct EntriesFields<'a> {
     next: u64,
     done: bool,
     raw: bool,
+    /// Number of entries returned so far.
+    count: u64,
+    /// Total size of the data of the entries returned so far.
+    total_size: u64,
 }
 
 impl<R: Read> Archive<R> {
@@ -54,11 +113,26 @@ impl<R: Read> Archive<R> {
         Archive {
             inner: ArchiveInner {
                 unpack_xattrs: false,
+                unpack_acls: false,
                 preserve_permissions: false,
                 preserve_ownerships: false,
+                numeric_owner: false,
+                owner_resolver: None,
+                uid_map: None,
+                gid_map: None,
+                ownership_xattr: None,
                 preserve_mtime: true,
-                overwrite: true,
+                overwrite: OverwritePolicy::Replace,
+                unpack_beneath: false,
+                transactional: false,
+                whiteouts: Whiteouts::Keep,
+                rewrite: None,
                 ignore_zeros: false,
+                recover: false,
+                limits: Limits::default(),
+                skipped: RefCell::new(Vec::new()),
+                volume: RefCell::new(VolumeState::default()),
+                record: RefCell::new(None),
                 obj: RefCell::new(obj),
                 pos: Cell::new(0),
             },
@@ -108,6 +182,180 @@ impl<R: Read> Archive<R> {
         me._unpack(dst.as_ref())
     }
 
+    /// Unpacks the contents tarball into the given `UnpackTarget` rather than
+    /// a directory on the filesystem.
+    ///
+    /// Entries are unpacked with `Entry::unpack_into`, so the same checks
+    /// keeping them inside the destination apply as for `unpack`. Directories
+    /// are created last, like `unpack` does, so that their permissions don't
+    /// interfere with unpacking their contents.
+    pub fn unpack_into<T: UnpackTarget>(&mut self, target: &mut T) -> io::Result<()> {
+        let me: &mut Archive<dyn Read> = self;
+        me._unpack_into(target)
+    }
+
+    /// Returns what `unpack` would do when unpacking this archive into `dst`,
+    /// without writing anything.
+    ///
+    /// Entries are checked in the order `unpack` would unpack them, with the
+    /// same path rewriting, safety checks and overwrite policy. The plan
+    /// lists each entry with what would be done with it, see `UnpackPlan`.
+    ///
+    /// This reads the whole archive, so the entries of the archive need to be
+    /// read from a new `Archive` to actually unpack it.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use tar::{Archive, PlannedAction};
+    ///
+    /// let mut ar = Archive::new(File::open("foo.tar").unwrap());
+    /// let plan = ar.plan_unpack("foo").unwrap();
+    /// for entry in plan.entries() {
+    ///     if entry.action() == PlannedAction::Overwrite {
+    ///         println!("overwrites {}", entry.dst().unwrap().display());
+    ///     }
+    /// }
+    /// ```
+    pub fn plan_unpack<P: AsRef<Path>>(&mut self, dst: P) -> io::Result<UnpackPlan> {
+        let me: &mut Archive<dyn Read> = self;
+        me._plan_unpack(dst.as_ref())
+    }
+
+    /// Compares the entries of this archive against the files below `root`,
+    /// like `tar --diff`, returning how the files differ from the entries.
+    ///
+    /// Each entry is resolved like `unpack` does and checked for existence,
+    /// type, size, permissions, ownership and the target of links. The
+    /// modification time is compared for regular files, the only ones whose
+    /// time `unpack` restores. Ownerships are compared against what unpacking would
+    /// restore, taking `set_numeric_owner`, `set_owner_resolver` and the id
+    /// maps into account. The contents of regular files are only compared if
+    /// `contents` is set. Files below `root` which aren't in the archive
+    /// aren't reported.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use tar::Archive;
+    ///
+    /// let mut ar = Archive::new(File::open("release.tar").unwrap());
+    /// let differences = ar.compare("/srv/release", false).unwrap();
+    /// if !differences.is_empty() {
+    ///     println!("{} files were changed", differences.len());
+    /// }
+    /// ```
+    pub fn compare<P: AsRef<Path>>(
+        &mut self,
+        root: P,
+        contents: bool,
+    ) -> io::Result<Vec<Difference>> {
+        let me: &mut Archive<dyn Read> = self;
+        me._compare(root.as_ref(), contents)
+    }
+
+    /// Compares the entries of this archive against those of `other`,
+    /// returning the paths which were added, removed or modified in `other`,
+    /// sorted by path.
+    ///
+    /// A modified path is reported as a content change if the entry type,
+    /// the size, the link target or the data differs, and as a metadata
+    /// change if only the mode, the owner, the mtime or the extended
+    /// attributes and ACLs recorded in pax records differ. If an archive has
+    /// several entries for a path, the last one is compared.
+    ///
+    /// Both archives are read once, from their current position. Data is
+    /// compared through hashes computed while reading, so only a small
+    /// summary of each entry of this archive is kept in memory and neither
+    /// reader needs to be seekable.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use tar::Archive;
+    ///
+    /// let mut old = Archive::new(File::open("old.tar").unwrap());
+    /// let mut new = Archive::new(File::open("new.tar").unwrap());
+    /// for change in old.diff(&mut new).unwrap() {
+    ///     println!("{:?} {}", change.kind(), change.path().display());
+    /// }
+    /// ```
+    pub fn diff<S: Read>(&mut self, other: &mut Archive<S>) -> io::Result<Vec<ArchiveChange>> {
+        let me: &mut Archive<dyn Read> = self;
+        let other: &mut Archive<dyn Read> = other;
+        me._diff(other)
+    }
+
+    /// Copies this archive to `dst` without the members for which `predicate`
+    /// returns true, like `tar --delete` does when reading from a pipe.
+    ///
+    /// The kept members are copied byte for byte, along with the pax and GNU
+    /// long name headers preceding them, and removed members are dropped
+    /// together with theirs. A new end-of-archive marker is written after the
+    /// last member copied and `dst` is returned.
+    ///
+    /// Headers are held in memory until `predicate` decides on their member,
+    /// as is any data it reads from the entry; the rest of the data is
+    /// streamed.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use std::path::Path;
+    /// use tar::Archive;
+    ///
+    /// let mut ar = Archive::new(File::open("foo.tar").unwrap());
+    /// let dst = File::create("bar.tar").unwrap();
+    /// ar.delete_into(dst, |entry| {
+    ///     entry.path().map(|p| p == Path::new("secret.txt")).unwrap_or(false)
+    /// })
+    /// .unwrap();
+    /// ```
+    pub fn delete_into<W, F>(&mut self, mut dst: W, mut predicate: F) -> io::Result<W>
+    where
+        W: Write,
+        F: FnMut(&Entry<R>) -> bool,
+    {
+        let me: &mut Archive<dyn Read> = self;
+        let ret = me._delete_into(&mut dst, &mut |fields| predicate(&fields.into_entry()));
+        me.inner.record.replace(None);
+        ret.map(|()| dst)
+    }
+
+    /// Checks this archive for damage and for members which are unsafe to
+    /// unpack, returning a report of all problems found.
+    ///
+    /// This reads the whole archive, so it should be called on a freshly
+    /// created archive and the entries of the archive need to be read from a
+    /// new `Archive` afterwards. Unlike iterating over the entries, validation
+    /// continues past most problems, skipping damaged headers until the next
+    /// valid one.
+    ///
+    /// An error is only returned for I/O errors of the underlying reader, and
+    /// a `LimitError` if the GNU long name and pax extension entries of a
+    /// member exceed `set_max_metadata_size`, as they're read into memory.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use tar::Archive;
+    ///
+    /// let mut ar = Archive::new(File::open("foo.tar").unwrap());
+    /// let report = ar.validate().unwrap();
+    /// for finding in report.findings() {
+    ///     println!("{}", finding);
+    /// }
+    /// ```
+    pub fn validate(&mut self) -> io::Result<ValidationReport> {
+        let me: &mut Archive<dyn Read> = self;
+        me._validate()
+    }
+
     /// Indicate whether extended file attributes (xattrs on Unix) are preserved
     /// when unpacking this archive.
     ///
@@ -119,6 +367,21 @@ impl<R: Read> Archive<R> {
         self.inner.unpack_xattrs = unpack_xattrs;
     }
 
+    /// Indicate whether POSIX ACLs are restored when unpacking this archive.
+    ///
+    /// ACLs are read from the `SCHILY.acl.access` and `SCHILY.acl.default`
+    /// pax records written by GNU tar and star, and set as the
+    /// `system.posix_acl_access` and `system.posix_acl_default` extended
+    /// attributes after the permissions of a file are set. Named users and
+    /// groups are looked up on this system unless their numeric ids are
+    /// recorded as well.
+    ///
+    /// This flag is disabled by default and is currently only implemented on
+    /// Linux using xattr support.
+    pub fn set_unpack_acls(&mut self, unpack_acls: bool) {
+        self.inner.unpack_acls = unpack_acls;
+    }
+
     /// Indicate whether extended permissions (like suid on Unix) are preserved
     /// when unpacking this entry.
     ///
@@ -128,8 +391,13 @@ impl<R: Read> Archive<R> {
         self.inner.preserve_permissions = preserve;
     }
 
-    /// Indicate whether numeric ownership ids (like uid and gid on Unix)
-    /// are preserved when unpacking this entry.
+    /// Indicate whether ownerships (like uid and gid on Unix) are preserved
+    /// when unpacking this entry.
+    ///
+    /// Like GNU tar, the owner and group are looked up by the user and group
+    /// names recorded in the archive, falling back to the numeric ids if a
+    /// name is missing or unknown, see `set_numeric_owner` and
+    /// `set_owner_resolver`.
     ///
     /// This flag is disabled by default and is currently only implemented on
     /// Unix.
@@ -137,9 +405,75 @@ impl<R: Read> Archive<R> {
         self.inner.preserve_ownerships = preserve;
     }
 
+    /// Indicate whether only the numeric ids recorded in the archive are used
+    /// to preserve ownerships, ignoring the user and group names, like GNU
+    /// tar's `--numeric-owner`.
+    ///
+    /// This flag is disabled by default.
+    pub fn set_numeric_owner(&mut self, numeric: bool) {
+        self.inner.numeric_owner = numeric;
+    }
+
+    /// Sets how user and group names are looked up when preserving
+    /// ownerships. Defaults to `SystemOwnerResolver`, which uses the passwd
+    /// and group databases of this system.
+    pub fn set_owner_resolver<T: OwnerResolver + 'static>(&mut self, resolver: T) {
+        self.inner.owner_resolver = Some(Arc::new(resolver));
+    }
+
+    /// Maps the uids of entries through `map` when preserving ownerships,
+    /// for example to shift them into the range of a user namespace.
+    ///
+    /// The map applies to the uid after the user name is looked up, so
+    /// archives recording ids from another system are usually unpacked with
+    /// `set_numeric_owner` as well. Entries with uids which aren't mapped
+    /// fail to unpack unless the map has a fallback, see `IdMap::set_unmapped`.
+    pub fn set_uid_map(&mut self, map: IdMap) {
+        self.inner.uid_map = Some(Arc::new(map));
+    }
+
+    /// Maps the gids of entries through `map` when preserving ownerships, see
+    /// `set_uid_map`.
+    pub fn set_gid_map(&mut self, map: IdMap) {
+        self.inner.gid_map = Some(Arc::new(map));
+    }
+
+    /// Records ownerships in the extended attribute `name` rather than
+    /// changing the owner of unpacked files when preserving ownerships, for
+    /// unpacking without the privileges to do so.
+    ///
+    /// This is the format rootless container tools use for
+    /// `user.containers.override_stat`: the uid, gid, mode and type of each
+    /// file, like `0:0:0755:dir` or `0:5:0620:char-136-0`, which is read back
+    /// by `Builder::ownership_xattr`. Devices are unpacked as empty regular
+    /// files as usual. Symbolic links and hard links don't get the attribute.
+    ///
+    /// This is currently only implemented on Unix using xattr support.
+    pub fn set_ownership_xattr<S: Into<String>>(&mut self, name: S) {
+        self.inner.ownership_xattr = Some(Arc::from(name.into()));
+    }
+
     /// Indicate whether files and symlinks should be overwritten on extraction.
+    ///
+    /// This is a shorthand for setting the overwrite policy to
+    /// `OverwritePolicy::Replace` or `OverwritePolicy::Error`.
     pub fn set_overwrite(&mut self, overwrite: bool) {
-        self.inner.overwrite = overwrite;
+        self.inner.overwrite = if overwrite {
+            OverwritePolicy::Replace
+        } else {
+            OverwritePolicy::Error
+        };
+    }
+
+    /// Sets what happens when a file or symlink being unpacked already
+    /// exists.
+    ///
+    /// By default existing files are replaced, see `OverwritePolicy` for the
+    /// alternatives. `unpack_into` can only tell an `UnpackTarget` whether
+    /// existing files may be replaced, which they may for all policies except
+    /// `OverwritePolicy::Error` and `OverwritePolicy::KeepOld`.
+    pub fn set_overwrite_policy(&mut self, policy: OverwritePolicy) {
+        self.inner.overwrite = policy;
     }
 
     /// Indicate whether access time information is preserved when unpacking
@@ -150,6 +484,114 @@ impl<R: Read> Archive<R> {
         self.inner.preserve_mtime = preserve;
     }
 
+    /// Indicate whether entries are unpacked relative to directory file
+    /// descriptors rather than by path.
+    ///
+    /// By default `unpack` checks that the parent directory of each entry is
+    /// inside the destination by canonicalizing it, and then creates the entry
+    /// by its path. A concurrent process which replaces a parent directory by
+    /// a symbolic link in between can redirect the entry outside of the
+    /// destination. With this flag the destination and the parent directories
+    /// are opened instead and entries are created relative to them with
+    /// `openat`, `mkdirat`, `symlinkat` and `linkat`, never following a
+    /// symbolic link in the last component.
+    ///
+    /// Parent directories are opened with `openat2` and `RESOLVE_BENEATH`
+    /// where the kernel supports it, which follows symbolic links only as long
+    /// as they stay inside the destination. On older kernels symbolic links in
+    /// parent directories aren't followed at all, failing the unpack of
+    /// entries below them. Hard link targets are resolved from the destination
+    /// the same way.
+    ///
+    /// This flag is disabled by default and is currently only implemented on
+    /// Linux.
+    pub fn set_unpack_beneath(&mut self, beneath: bool) {
+        self.inner.unpack_beneath = beneath;
+    }
+
+    /// Indicate whether `unpack` is all-or-nothing.
+    ///
+    /// When enabled, the archive is unpacked into a staging directory next to
+    /// the destination, which is renamed to the destination once all entries
+    /// were unpacked. If unpacking fails, the staging directory is removed and
+    /// the destination is left untouched.
+    ///
+    /// The destination must not exist or be an empty directory, otherwise
+    /// `unpack` and `plan_unpack` fail without writing anything. Files which
+    /// are already there couldn't be kept otherwise, so an existing
+    /// destination is never overwritten and the overwrite policy and
+    /// whiteouts only see the entries of the archive itself.
+    ///
+    /// This flag is disabled by default.
+    pub fn set_transactional(&mut self, transactional: bool) {
+        self.inner.transactional = transactional;
+    }
+
+    /// Sets how `unpack` handles the whiteout entries of OCI image layers,
+    /// which delete files of the layers below.
+    ///
+    /// With `Whiteouts::Apply` the destination is expected to hold the layers
+    /// below already, and whiteouts remove the files they hide from it.
+    /// Files unpacked from this archive are never removed by its whiteouts.
+    /// With `Whiteouts::Overlay` whiteouts are converted to the form overlayfs
+    /// uses instead. Whiteouts are unpacked as regular files by default.
+    pub fn set_whiteouts(&mut self, whiteouts: Whiteouts) {
+        self.inner.whiteouts = whiteouts;
+    }
+
+    /// Removes the given number of leading components from the path of each
+    /// entry when unpacking, like `--strip-components` of GNU tar. Entries
+    /// with no components left are skipped.
+    ///
+    /// Leading `/` and `.` components aren't counted. Paths are rewritten
+    /// before `unpack` and `Entry::unpack_in` check them, and the targets of
+    /// hard links are rewritten the same way so that they still point to the
+    /// rewritten entries.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use tar::Archive;
+    ///
+    /// // Unpack the contents of `project-1.2.3/` into `foo`.
+    /// let mut ar = Archive::new(File::open("project-1.2.3.tar").unwrap());
+    /// ar.set_strip_components(1);
+    /// ar.unpack("foo").unwrap();
+    /// ```
+    pub fn set_strip_components(&mut self, n: usize) {
+        self.rewrite().strip_components = n;
+    }
+
+    /// Replaces the leading components `from` of the path of each entry by
+    /// `to` when unpacking. Mapping to an empty path unpacks the entries below
+    /// `from` in place.
+    ///
+    /// Mappings are applied after `set_strip_components`, and only the first
+    /// mapping matching a path is applied, in the order they were added.
+    pub fn map_path_prefix<P: AsRef<Path>, Q: AsRef<Path>>(&mut self, from: P, to: Q) {
+        let mapping = (from.as_ref().to_path_buf(), to.as_ref().to_path_buf());
+        self.rewrite().prefixes.push(mapping);
+    }
+
+    /// Rewrites the path of each entry with `rewriter` when unpacking. The
+    /// rewriter returns the new path, or `None` to skip the entry.
+    ///
+    /// The rewriter is called with the path after `set_strip_components` and
+    /// `map_path_prefix` were applied. The paths it returns are checked for
+    /// `..` components like any other.
+    pub fn set_path_rewriter<F>(&mut self, rewriter: F)
+    where
+        F: Fn(&Path) -> Option<PathBuf> + Send + Sync + 'static,
+    {
+        self.rewrite().rewriter = Some(Arc::new(rewriter));
+    }
+
+    fn rewrite(&mut self) -> &mut PathRewrite {
+        let rewrite = self.inner.rewrite.get_or_insert_with(Default::default);
+        Arc::make_mut(rewrite)
+    }
+
     /// Ignore zeroed headers, which would otherwise indicate to the archive that it has no more
     /// entries.
     ///
@@ -157,6 +599,98 @@ impl<R: Read> Archive<R> {
     pub fn set_ignore_zeros(&mut self, ignore_zeros: bool) {
         self.inner.ignore_zeros = ignore_zeros;
     }
+
+    /// Recover from damaged headers instead of failing.
+    ///
+    /// By default a header with a bad checksum or an invalid size ends the
+    /// iteration with an error. When recovering, the archive is instead
+    /// scanned forward block by block until the next valid header, where
+    /// iteration resumes. Each region skipped that way is recorded and can be
+    /// retrieved with `skipped_regions`.
+    ///
+    /// Blocks of zeros found while scanning still end the archive unless
+    /// `set_ignore_zeros` is enabled as well, which is usually desired when
+    /// recovering data from a damaged archive. Note that the data of a file
+    /// whose header was damaged may itself contain something that looks like
+    /// a valid header, such as another tar archive.
+    ///
+    /// This flag is disabled by default.
+    pub fn set_recover(&mut self, recover: bool) {
+        self.inner.recover = recover;
+    }
+
+    /// Limits the total size of the GNU long name and pax extension entries
+    /// describing a member, and the size of the sparse map of a sparse file.
+    ///
+    /// These are read into memory before the member is returned, so without a
+    /// limit a crafted archive can make the reader allocate arbitrary amounts
+    /// of memory. Exceeding the limit returns a `LimitError`.
+    ///
+    /// There is no limit by default.
+    pub fn set_max_metadata_size(&mut self, max: u64) {
+        self.inner.limits.metadata_size = max;
+    }
+
+    /// Limits the number of entries read from this archive. Exceeding the
+    /// limit returns a `LimitError` for the first entry over it.
+    ///
+    /// There is no limit by default.
+    pub fn set_max_entries(&mut self, max: u64) {
+        self.inner.limits.entries = max;
+    }
+
+    /// Limits the total size of the data of all entries read from this
+    /// archive, which is the number of bytes unpacking it writes. The size of
+    /// a sparse file counts with its holes. Exceeding the limit returns a
+    /// `LimitError` for the first entry over it, before any of its data is
+    /// read.
+    ///
+    /// There is no limit by default.
+    pub fn set_max_total_size(&mut self, max: u64) {
+        self.inner.limits.total_size = max;
+    }
+
+    /// Limits how much larger a sparse file may be than the space it takes up
+    /// in the archive, including its headers. A small archive can otherwise
+    /// describe sparse files of terabytes. Exceeding the limit returns a
+    /// `LimitError`.
+    ///
+    /// There is no limit by default.
+    pub fn set_max_sparse_ratio(&mut self, max: u64) {
+        self.inner.limits.sparse_ratio = max;
+    }
+
+    /// Limits the length, in bytes, of the path and link name of each entry.
+    /// Exceeding the limit returns a `LimitError`.
+    ///
+    /// There is no limit by default.
+    pub fn set_max_path_len(&mut self, max: u64) {
+        self.inner.limits.path_len = max;
+    }
+
+    /// Limits the number of components of the path of each entry, ignoring
+    /// empty and `.` components. Exceeding the limit returns a `LimitError`.
+    ///
+    /// There is no limit by default.
+    pub fn set_max_path_depth(&mut self, max: u64) {
+        self.inner.limits.path_depth = max;
+    }
+
+    /// Returns the regions of the archive skipped so far while recovering
+    /// from damaged headers, see `set_recover`.
+    pub fn skipped_regions(&self) -> Vec<SkippedRegion> {
+        self.inner.skipped.borrow().clone()
+    }
+
+    /// Returns the labels of the volumes read so far.
+    ///
+    /// Archives created by GNU tar with `--label` start with a volume label
+    /// header, which is also returned as an entry by the iterator. When
+    /// reading a multi-volume archive through `MultiVolume` the label of each
+    /// further volume is recorded as well.
+    pub fn volume_labels(&self) -> Vec<Vec<u8>> {
+        self.inner.volume.borrow().labels.clone()
+    }
 }
 
 impl<R: Seek + Read> Archive<R> {
@@ -175,6 +709,108 @@ impl<R: Seek + Read> Archive<R> {
             _ignored: marker::PhantomData,
         })
     }
+
+    /// Calls `f` with each entry of this archive and the position its data
+    /// starts at, skipping over the data.
+    pub(crate) fn scan<F>(&mut self, mut f: F) -> io::Result<()>
+    where
+        F: FnMut(&EntryFields, u64) -> io::Result<()>,
+    {
+        let me: &Archive<dyn Read> = self;
+        let me_seekable: &Archive<dyn SeekRead> = self;
+        for entry in me._entries(Some(me_seekable))? {
+            let fields = EntryFields::from(entry?);
+            f(&fields, me.inner.pos.get())?;
+        }
+        Ok(())
+    }
+
+    /// Calls `f` with each entry of this archive, skipping over the data, and
+    /// returns where its last member ends, which is where the end-of-archive
+    /// marker starts.
+    pub(crate) fn end_of_entries<F>(&mut self, mut f: F) -> io::Result<u64>
+    where
+        F: FnMut(&EntryFields) -> io::Result<()>,
+    {
+        let me: &Archive<dyn Read> = self;
+        let me_seekable: &Archive<dyn SeekRead> = self;
+        let mut entries = me._entries(Some(me_seekable))?;
+        let mut end = 0;
+        while let Some(entry) = entries.next() {
+            f(&EntryFields::from(entry?))?;
+            end = entries.next;
+        }
+        Ok(end)
+    }
+}
+
+impl Archive<fs::File> {
+    /// Removes the members for which `predicate` returns true from the
+    /// archive file, like `tar --delete` does, and returns the file.
+    ///
+    /// The members are removed along with the pax and GNU long name headers
+    /// preceding them. The members following the first one removed are moved
+    /// up over the gaps, a new end-of-archive marker is written after the
+    /// last one and the file is truncated. The file is left untouched if no
+    /// member is removed.
+    ///
+    /// The archive must start at the beginning of the file. The members are
+    /// moved in place, so an I/O error while doing so leaves a damaged
+    /// archive behind.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::OpenOptions;
+    /// use std::path::Path;
+    /// use tar::Archive;
+    ///
+    /// let file = OpenOptions::new()
+    ///     .read(true)
+    ///     .write(true)
+    ///     .open("foo.tar")
+    ///     .unwrap();
+    /// let ar = Archive::new(file);
+    /// ar.delete(|entry| {
+    ///     entry.path().map(|p| p.starts_with("tmp")).unwrap_or(false)
+    /// })
+    /// .unwrap();
+    /// ```
+    pub fn delete<F>(self, mut predicate: F) -> io::Result<fs::File>
+    where
+        F: FnMut(&Entry<fs::File>) -> bool,
+    {
+        // The regions taken up by the members removed and the end of the
+        // last member.
+        let mut removed = Vec::new();
+        let mut end = 0;
+        {
+            let me: &Archive<dyn Read> = &self;
+            let me_seekable: &Archive<dyn SeekRead> = &self;
+            let mut entries = me._entries(Some(me_seekable))?;
+            loop {
+                let start = entries.next;
+                let entry = match entries.next() {
+                    Some(entry) => EntryFields::from(entry?).into_entry(),
+                    None => break,
+                };
+                end = entries.next;
+                if predicate(&entry) {
+                    removed.push((start, end));
+                }
+            }
+        }
+        let file = self.into_inner();
+        // Seeking over the data of the last member succeeds even if the file
+        // is truncated, so check before moving anything.
+        if end > file.metadata()?.len() {
+            return Err(other("archive ends in the middle of a member"));
+        }
+        if !removed.is_empty() {
+            compact(&file, &removed, end)?;
+        }
+        Ok(file)
+    }
 }
 
 impl Archive<dyn Read + '_> {
@@ -194,10 +830,51 @@ impl Archive<dyn Read + '_> {
             done: false,
             next: 0,
             raw: false,
+            count: 0,
+            total_size: 0,
         })
     }
 
+    fn _validate(&mut self) -> io::Result<ValidationReport> {
+        let pos = self.inner.pos.get();
+        validate(&mut &self.inner, pos, &self.inner.limits)
+    }
+
     fn _unpack(&mut self, dst: &Path) -> io::Result<()> {
+        if self.inner.transactional {
+            self.unpack_staged(dst)
+        } else {
+            self.unpack_dir(dst)
+        }
+    }
+
+    /// Unpacks into a staging directory next to `dst` and moves it into
+    /// place, see `set_transactional`.
+    fn unpack_staged(&mut self, dst: &Path) -> io::Result<()> {
+        check_empty(dst)?;
+        let name = dst.file_name().ok_or_else(|| {
+            other(&format!(
+                "cannot unpack transactionally to `{}`",
+                dst.display()
+            ))
+        })?;
+        let parent = dst.parent().unwrap_or_else(|| Path::new(""));
+        let staging = parent.join(temp_name(name));
+        fs::create_dir(&staging)
+            .map_err(|e| TarError::new(format!("failed to create `{}`", staging.display()), e))?;
+
+        let ret = self.unpack_dir(&staging).and_then(|()| {
+            replace_dir(&staging, dst, &parent.join(temp_name(name))).map_err(|e| {
+                TarError::new(format!("failed to move `{}` into place", dst.display()), e).into()
+            })
+        });
+        if ret.is_err() {
+            let _ = fs::remove_dir_all(&staging);
+        }
+        ret
+    }
+
+    fn unpack_dir(&mut self, dst: &Path) -> io::Result<()> {
         if dst.symlink_metadata().is_err() {
             fs::create_dir_all(&dst)
                 .map_err(|e| TarError::new(format!("failed to create `{}`", dst.display()), e))?;
@@ -213,9 +890,24 @@ impl Archive<dyn Read + '_> {
         // Delay any directory entries until the end (they will be created if needed by
         // descendants), to ensure that directory permissions do not interfer with descendant
         // extraction.
+        let whiteouts = self.inner.whiteouts;
+        let mut layer = Layer::default();
         let mut directories = Vec::new();
         for entry in self._entries(None)? {
-            let mut file = entry.map_err(|e| TarError::new("failed to iterate over archive", e))?;
+            let mut file = entry.map_err(|e| {
+                if LimitError::is_limit_error(&e) {
+                    e
+                } else {
+                    TarError::new("failed to iterate over archive", e).into()
+                }
+            })?;
+            if whiteouts != Whiteouts::Keep {
+                let fields = EntryFields::from(file);
+                if fields.unpack_whiteout(dst, whiteouts, &mut layer)? {
+                    continue;
+                }
+                file = fields.into_entry();
+            }
             if file.header().entry_type() == crate::EntryType::Directory {
                 directories.push(file);
             } else {
@@ -228,6 +920,179 @@ impl Archive<dyn Read + '_> {
 
         Ok(())
     }
+
+    fn _plan_unpack(&mut self, dst: &Path) -> io::Result<UnpackPlan> {
+        let mut plan = UnpackPlan::new(dst);
+        if self.inner.transactional {
+            check_empty(dst)?;
+            plan.set_fresh(true);
+        }
+        let mut directories = Vec::new();
+        for entry in self._entries(None)? {
+            let file = entry.map_err(|e| {
+                if LimitError::is_limit_error(&e) {
+                    e
+                } else {
+                    TarError::new("failed to iterate over archive", e).into()
+                }
+            })?;
+            if file.header().entry_type() == crate::EntryType::Directory {
+                directories.push(file);
+            } else {
+                EntryFields::from(file).plan_unpack_in(&mut plan)?;
+            }
+        }
+        for dir in directories {
+            EntryFields::from(dir).plan_unpack_in(&mut plan)?;
+        }
+
+        Ok(plan)
+    }
+
+    fn _compare(&mut self, root: &Path, contents: bool) -> io::Result<Vec<Difference>> {
+        let mut differences = Vec::new();
+        for entry in self._entries(None)? {
+            let file = entry.map_err(|e| {
+                if LimitError::is_limit_error(&e) {
+                    e
+                } else {
+                    TarError::new("failed to iterate over archive", e).into()
+                }
+            })?;
+            differences.extend(EntryFields::from(file).compare_in(root, contents)?);
+        }
+        Ok(differences)
+    }
+
+    fn _diff(&mut self, other: &mut Archive<dyn Read + '_>) -> io::Result<Vec<ArchiveChange>> {
+        let mut diff = Diff::default();
+        for entry in self._entries(None)? {
+            let mut fields = EntryFields::from(entry?);
+            if let Some((path, summary)) = Summary::read(&mut fields, diff.keys())? {
+                diff.insert(path, summary);
+            }
+        }
+        for entry in other._entries(None)? {
+            let mut fields = EntryFields::from(entry?);
+            if let Some((path, summary)) = Summary::read(&mut fields, diff.keys())? {
+                diff.compare(path, summary);
+            }
+        }
+        Ok(diff.finish())
+    }
+
+    fn _delete_into<'a>(
+        &'a self,
+        dst: &mut dyn Write,
+        predicate: &mut dyn FnMut(EntryFields<'a>) -> bool,
+    ) -> io::Result<()> {
+        let mut entries = self._entries(None)?;
+        loop {
+            // Everything read up to the data of the member, and whatever of
+            // the data `predicate` reads, is recorded.
+            self.inner.record.replace(Some(Vec::new()));
+            let fields = match entries.next() {
+                Some(entry) => EntryFields::from(entry?),
+                None => break,
+            };
+            let delete = predicate(fields);
+            let read = self.inner.record.replace(None).unwrap_or_default();
+            let rest = entries.next - self.inner.pos.get();
+            let copied = if delete {
+                io::copy(&mut (&self.inner).take(rest), &mut io::sink())?
+            } else {
+                dst.write_all(&read)?;
+                io::copy(&mut (&self.inner).take(rest), dst)?
+            };
+            if copied < rest {
+                return Err(other("archive ends in the middle of a member"));
+            }
+        }
+        dst.write_all(&[0; 1024])
+    }
+
+    fn _unpack_into(&mut self, target: &mut dyn UnpackTarget) -> io::Result<()> {
+        let mut directories = Vec::new();
+        for entry in self._entries(None)? {
+            let file = entry.map_err(|e| {
+                if LimitError::is_limit_error(&e) {
+                    e
+                } else {
+                    TarError::new("failed to iterate over archive", e).into()
+                }
+            })?;
+            if file.header().entry_type() == crate::EntryType::Directory {
+                directories.push(file);
+            } else {
+                EntryFields::from(file).unpack_into(target)?;
+            }
+        }
+        for dir in directories {
+            EntryFields::from(dir).unpack_into(target)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Removes the regions `removed` of the archive `file`, sorted by position,
+/// by moving up what follows them up to `end`. A new end-of-archive marker
+/// is written after the last member and the file is truncated after it.
+fn compact(mut file: &fs::File, removed: &[(u64, u64)], end: u64) -> io::Result<()> {
+    let mut to = removed[0].0;
+    let mut buf = vec![0; 64 * 1024];
+    for (i, &(_, from)) in removed.iter().enumerate() {
+        let until = removed.get(i + 1).map_or(end, |next| next.0);
+        let mut pos = from;
+        while pos < until {
+            let n = cmp::min(buf.len() as u64, until - pos) as usize;
+            file.seek(SeekFrom::Start(pos))?;
+            file.read_exact(&mut buf[..n])?;
+            file.seek(SeekFrom::Start(to))?;
+            file.write_all(&buf[..n])?;
+            pos += n as u64;
+            to += n as u64;
+        }
+    }
+    file.seek(SeekFrom::Start(to))?;
+    file.write_all(&[0; 1024])?;
+    file.set_len(to + 1024)
+}
+
+/// Fails unless `dst` is missing or an empty directory, which a transactional
+/// unpack can replace without losing anything.
+fn check_empty(dst: &Path) -> io::Result<()> {
+    let empty = match fs::read_dir(dst) {
+        Ok(mut entries) => entries.next().is_none(),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => true,
+        Err(_) => false,
+    };
+    if empty {
+        Ok(())
+    } else {
+        Err(other(&format!(
+            "cannot unpack transactionally to `{}`, which exists and isn't an \
+             empty directory",
+            dst.display()
+        )))
+    }
+}
+
+/// Renames the directory `src` to `dst`. An existing, empty `dst` is renamed
+/// to `old` first and removed once `src` is in place.
+fn replace_dir(src: &Path, dst: &Path, old: &Path) -> io::Result<()> {
+    if dst.symlink_metadata().is_err() {
+        return fs::rename(src, dst);
+    }
+    fs::rename(dst, old)?;
+    if let Err(e) = fs::rename(src, dst) {
+        let _ = fs::rename(old, dst);
+        return Err(e);
+    }
+    // The archive is in place at this point, so failing to clean up doesn't
+    // fail the unpack. Files created in the meantime are left alone.
+    let _ = fs::remove_dir(old);
+    Ok(())
 }
 
 impl<'a, R: Read> Entries<'a, R> {
@@ -263,13 +1128,16 @@ impl<'a> EntriesFields<'a> {
     ) -> io::Result<Option<Entry<'a, io::Empty>>> {
         let mut header = Header::new_old();
         let mut header_pos = self.next;
-        loop {
+        // Start of the region skipped while recovering and why.
+        let mut skipped: Option<(u64, String)> = None;
+        let size = loop {
             // Seek to the start of the next header in the archive
             let delta = self.next - self.archive.inner.pos.get();
             self.skip(delta)?;
 
             // EOF is an indicator that we are at the end of the archive.
             if !try_read_all(&mut &self.archive.inner, header.as_mut_bytes())? {
+                self.skipped(skipped, header_pos);
                 return Ok(None);
             }
 
@@ -278,78 +1146,99 @@ impl<'a> EntriesFields<'a> {
             // end of the archive.
             if !header.as_bytes().iter().all(|i| *i == 0) {
                 self.next += 512;
-                break;
+
+                // Make sure the checksum is ok
+                let size = verify_cksum(&header).and_then(|()| entry_data_size(&header, pax_size));
+                match size {
+                    Ok(size) => {
+                        self.skipped(skipped, header_pos);
+                        break size;
+                    }
+                    Err(e) if self.archive.inner.recover => {
+                        if skipped.is_none() {
+                            skipped = Some((header_pos, e.to_string()));
+                        }
+                        header_pos = self.next;
+                        continue;
+                    }
+                    Err(e) => return Err(e),
+                }
             }
 
             if !self.archive.inner.ignore_zeros {
+                self.skipped(skipped, header_pos);
                 return Ok(None);
             }
             self.next += 512;
             header_pos = self.next;
-        }
+        };
 
-        // Make sure the checksum is ok
-        let sum = header.as_bytes()[..148]
-            .iter()
-            .chain(&header.as_bytes()[156..])
-            .fold(0, |a, b| a + (*b as u32))
-            + 8 * 32;
-        let cksum = header.cksum()?;
-        if sum != cksum {
-            return Err(other("archive header checksum mismatch"));
+        if header.entry_type().is_gnu_volume_label() {
+            let label = header.path_bytes().into_owned();
+            self.archive.inner.volume.borrow_mut().labels.push(label);
         }
 
         let file_pos = self.next;
-        let mut size = header.entry_size()?;
-        if size == 0 {
-            if let Some(pax_size) = pax_size {
-                size = pax_size;
-            }
-        }
+        let inner = &self.archive.inner;
         let ret = EntryFields {
-            size: size,
-            header_pos: header_pos,
-            file_pos: file_pos,
-            data: vec![EntryIo::Data((&self.archive.inner).take(size))],
-            header: header,
-            long_pathname: None,
-            long_linkname: None,
-            pax_extensions: None,
-            unpack_xattrs: self.archive.inner.unpack_xattrs,
-            preserve_permissions: self.archive.inner.preserve_permissions,
-            preserve_mtime: self.archive.inner.preserve_mtime,
-            overwrite: self.archive.inner.overwrite,
-            preserve_ownerships: self.archive.inner.preserve_ownerships,
+            data: vec![EntryIo::Data(inner.take(size))],
+            unpack_xattrs: inner.unpack_xattrs,
+            unpack_acls: inner.unpack_acls,
+            preserve_permissions: inner.preserve_permissions,
+            preserve_mtime: inner.preserve_mtime,
+            overwrite: inner.overwrite,
+            preserve_ownerships: inner.preserve_ownerships,
+            numeric_owner: inner.numeric_owner,
+            owner_resolver: inner.owner_resolver.clone(),
+            uid_map: inner.uid_map.clone(),
+            gid_map: inner.gid_map.clone(),
+            ownership_xattr: inner.ownership_xattr.clone(),
+            unpack_beneath: inner.unpack_beneath,
+            rewrite: inner.rewrite.clone(),
+            ..EntryFields::new(header, size, header_pos, file_pos)
         };
 
         // Store where the next entry is, rounding up by 512 bytes (the size of
         // a header);
-        let size = size
-            .checked_add(511)
-            .ok_or_else(|| other("size overflow"))?;
         self.next = self
             .next
-            .checked_add(size & !(512 - 1))
+            .checked_add(block_size(size)?)
             .ok_or_else(|| other("size overflow"))?;
+        self.archive
+            .inner
+            .start_member(file_pos, ret.size, self.next);
 
         Ok(Some(ret.into_entry()))
     }
 
+    /// Records the region from `skipped` up to `end` as skipped, if any.
+    fn skipped(&self, skipped: Option<(u64, String)>, end: u64) {
+        if let Some((offset, reason)) = skipped {
+            self.archive.inner.skipped.borrow_mut().push(SkippedRegion {
+                offset,
+                length: end - offset,
+                reason,
+            });
+        }
+    }
+
     fn next_entry(&mut self) -> io::Result<Option<Entry<'a, io::Empty>>> {
         if self.raw {
-            return self.next_entry_raw(None);
+            let fields = match self.next_entry_raw(None)? {
+                Some(entry) => EntryFields::from(entry),
+                None => return Ok(None),
+            };
+            self.check_limits(&fields)?;
+            return Ok(Some(fields.into_entry()));
         }
 
-        let mut gnu_longname = None;
-        let mut gnu_longlink = None;
-        let mut pax_extensions = None;
-        let mut pax_size = None;
-        let mut processed = 0;
+        let limits = &self.archive.inner.limits;
+        let mut meta = MemberMeta::default();
+        let mut meta_size = 0u64;
         loop {
-            processed += 1;
-            let entry = match self.next_entry_raw(pax_size)? {
+            let entry = match self.next_entry_raw(meta.pax_size())? {
                 Some(entry) => entry,
-                None if processed > 1 => {
+                None if !meta.is_empty() => {
                     return Err(other(
                         "members found describing a future member \
                          but no future member found",
@@ -358,58 +1247,60 @@ impl<'a> EntriesFields<'a> {
                 None => return Ok(None),
             };
 
-            let is_recognized_header =
-                entry.header().as_gnu().is_some() || entry.header().as_ustar().is_some();
-
-            if is_recognized_header && entry.header().entry_type().is_gnu_longname() {
-                if gnu_longname.is_some() {
-                    return Err(other(
-                        "two long name entries describing \
-                         the same member",
-                    ));
-                }
-                gnu_longname = Some(EntryFields::from(entry).read_all()?);
-                continue;
-            }
-
-            if is_recognized_header && entry.header().entry_type().is_gnu_longlink() {
-                if gnu_longlink.is_some() {
-                    return Err(other(
-                        "two long name entries describing \
-                         the same member",
-                    ));
-                }
-                gnu_longlink = Some(EntryFields::from(entry).read_all()?);
-                continue;
-            }
-
-            if is_recognized_header && entry.header().entry_type().is_pax_local_extensions() {
-                if pax_extensions.is_some() {
-                    return Err(other(
-                        "two pax extensions entries describing \
-                         the same member",
-                    ));
-                }
-                pax_extensions = Some(EntryFields::from(entry).read_all()?);
-                if let Some(pax_extensions_ref) = &pax_extensions {
-                    pax_size = pax_extensions_size(pax_extensions_ref);
-                }
+            if let Some(kind) = meta.kind(entry.header())? {
+                meta_size = meta_size.saturating_add(entry.size());
+                let pos = entry.raw_header_position();
+                limits.check(LimitKind::MetadataSize, meta_size, &entry.path_bytes(), pos)?;
+                meta.add(kind, EntryFields::from(entry).read_all()?);
                 continue;
             }
 
             let mut fields = EntryFields::from(entry);
-            fields.long_pathname = gnu_longname;
-            fields.long_linkname = gnu_longlink;
-            fields.pax_extensions = pax_extensions;
+            meta.apply(&mut fields);
+            self.check_limits(&fields)?;
             self.parse_sparse_header(&mut fields)?;
             return Ok(Some(fields.into_entry()));
         }
     }
 
-    fn parse_sparse_header(&mut self, entry: &mut EntryFields<'a>) -> io::Result<()> {
+    /// Checks the limits on the number of entries, their total size and their
+    /// paths against the next entry returned.
+    fn check_limits(&mut self, entry: &EntryFields) -> io::Result<()> {
+        let limits = &self.archive.inner.limits;
+        let path = entry.path_bytes();
+        let pos = entry.header_pos;
+        self.count += 1;
+        limits.check(LimitKind::Entries, self.count, &path, pos)?;
+
+        // The size of sparse files is only known once their sparse map is
+        // read, it's checked again in `set_sparse_data`.
         if !entry.header.entry_type().is_gnu_sparse() {
-            return Ok(());
+            self.total_size = self.total_size.saturating_add(entry.size);
+            limits.check(LimitKind::TotalSize, self.total_size, &path, pos)?;
+        }
+
+        limits.check(LimitKind::PathLength, path.len() as u64, &path, pos)?;
+        if let Some(link) = entry.link_name_bytes() {
+            limits.check(LimitKind::PathLength, link.len() as u64, &path, pos)?;
         }
+        let depth = path
+            .split(|b| *b == b'/')
+            .filter(|c| !c.is_empty() && *c != b".")
+            .count();
+        limits.check(LimitKind::PathDepth, depth as u64, &path, pos)
+    }
+
+    fn parse_sparse_header(&mut self, entry: &mut EntryFields<'a>) -> io::Result<()> {
+        if entry.header.entry_type().is_gnu_sparse() {
+            return self.parse_gnu_sparse_header(entry);
+        }
+        if entry.pax_extensions.is_some() {
+            return self.parse_pax_sparse_header(entry);
+        }
+        Ok(())
+    }
+
+    fn parse_gnu_sparse_header(&mut self, entry: &mut EntryFields<'a>) -> io::Result<()> {
         let gnu = match entry.header.as_gnu() {
             Some(gnu) => gnu,
             None => return Err(other("sparse entry type listed but not GNU header")),
@@ -424,86 +1315,98 @@ impl<'a> EntriesFields<'a> {
         // which may also be contained after the first header in further
         // headers.
         //
-        // We read off all the blocks here and use the `add_block` function to
-        // incrementally add them to the list of I/O block (in `entry.data`).
-        // The `add_block` function also validates that each chunk comes after
-        // the previous, we don't overrun the end of the file, and each block is
-        // aligned to a 512-byte boundary in the archive itself.
+        // We read off all the blocks here and use a `SparseMap` to
+        // incrementally build the list of chunks of the file. The map also
+        // validates that each chunk comes after the previous, we don't overrun
+        // the end of the file, and each block is aligned to a 512-byte
+        // boundary in the archive itself.
         //
         // At the end we verify that the sparse file size (`Header::size`) is
         // the same as the current offset (described by the list of blocks) as
         // well as the amount of data read equals the size of the entry
         // (`Header::entry_size`).
-        entry.data.truncate(0);
-
-        let mut cur = 0;
-        let mut remaining = entry.size;
-        {
-            let data = &mut entry.data;
-            let reader = &self.archive.inner;
-            let size = entry.size;
-            let mut add_block = |block: &GnuSparseHeader| -> io::Result<_> {
-                if block.is_empty() {
-                    return Ok(());
+        let mut map = gnu_sparse_map(gnu, entry.size)?;
+        if gnu.is_extended() {
+            let limits = &self.archive.inner.limits;
+            let mut ext_size = 0;
+            let mut ext = GnuExtSparseHeader::new();
+            ext.isextended[0] = 1;
+            while ext.is_extended() {
+                ext_size += 512;
+                let path = entry.path_bytes();
+                limits.check(LimitKind::MetadataSize, ext_size, &path, entry.header_pos)?;
+                if !try_read_all(&mut &self.archive.inner, ext.as_mut_bytes())? {
+                    return Err(other("failed to read extension"));
                 }
-                let off = block.offset()?;
-                let len = block.length()?;
-                if len != 0 && (size - remaining) % 512 != 0 {
-                    return Err(other(
-                        "previous block in sparse file was not \
-                         aligned to 512-byte boundary",
-                    ));
-                } else if off < cur {
-                    return Err(other(
-                        "out of order or overlapping sparse \
-                         blocks",
-                    ));
-                } else if cur < off {
-                    let block = io::repeat(0).take(off - cur);
-                    data.push(EntryIo::Pad(block));
+
+                self.next += 512;
+                for block in ext.sparse.iter() {
+                    map.add_gnu_block(block)?;
                 }
-                cur = off
-                    .checked_add(len)
-                    .ok_or_else(|| other("more bytes listed in sparse file than u64 can hold"))?;
-                remaining = remaining.checked_sub(len).ok_or_else(|| {
-                    other(
-                        "sparse file consumed more data than the header \
-                         listed",
-                    )
-                })?;
-                data.push(EntryIo::Data(reader.take(len)));
-                Ok(())
-            };
-            for block in gnu.sparse.iter() {
-                add_block(block)?
             }
-            if gnu.is_extended() {
-                let mut ext = GnuExtSparseHeader::new();
-                ext.isextended[0] = 1;
-                while ext.is_extended() {
-                    if !try_read_all(&mut &self.archive.inner, ext.as_mut_bytes())? {
-                        return Err(other("failed to read extension"));
-                    }
+            let pos = self.archive.inner.pos.get();
+            self.archive.inner.start_member(pos, entry.size, self.next);
+        }
+        let (size, chunks) = finish_gnu_sparse(map, gnu)?;
+        entry.size = size;
+        self.set_sparse_data(entry, chunks, 0)
+    }
 
-                    self.next += 512;
-                    for block in ext.sparse.iter() {
-                        add_block(block)?;
+    /// Parses the sparse map of GNU sparse files stored in the pax format.
+    fn parse_pax_sparse_header(&mut self, entry: &mut EntryFields<'a>) -> io::Result<()> {
+        let (real_size, blocks, map_len) = match pax_sparse_format(entry)? {
+            Some(PaxSparse::Records { real_size, blocks }) => (real_size, blocks, 0),
+            Some(PaxSparse::Data { real_size }) => {
+                let limits = &self.archive.inner.limits;
+                let mut map = PaxSparseMap::new();
+                let mut block = [0; 512];
+                loop {
+                    entry
+                        .read_exact(&mut block)
+                        .map_err(|e| TarError::new("failed to read sparse map", e))?;
+                    if map.parse(&block)? {
+                        break;
                     }
+                    let path = entry.path_bytes();
+                    limits.check(LimitKind::MetadataSize, map.len(), &path, entry.header_pos)?;
                 }
+                (real_size, map.blocks(), map.len())
             }
-        }
-        if cur != gnu.real_size()? {
-            return Err(other(
-                "mismatch in sparse file chunks and \
-                 size in header",
-            ));
-        }
-        entry.size = cur;
-        if remaining > 0 {
-            return Err(other(
-                "mismatch in sparse file chunks and \
-                 entry size in header",
-            ));
+            None => return Ok(()),
+        };
+        let chunks = pax_sparse_chunks(entry, entry.size - map_len, real_size, blocks)?;
+        let stored = entry.size;
+        entry.size = real_size;
+        self.set_sparse_data(entry, chunks, stored)
+    }
+
+    /// Replaces the data of `entry`, whose size is already set to the size of
+    /// the sparse file, with the chunks of its sparse map. `counted` is the
+    /// size `check_limits` already added to the total size.
+    fn set_sparse_data(
+        &mut self,
+        entry: &mut EntryFields<'a>,
+        chunks: Vec<SparseChunk>,
+        counted: u64,
+    ) -> io::Result<()> {
+        let limits = &self.archive.inner.limits;
+        let path = entry.path_bytes().into_owned();
+        let pos = entry.header_pos;
+        // The member takes up its headers and data blocks, up to the next
+        // header.
+        let occupied = self.next - pos;
+        limits.check(LimitKind::SparseRatio, entry.size / occupied, &path, pos)?;
+        self.total_size = self
+            .total_size
+            .saturating_add(entry.size.saturating_sub(counted));
+        limits.check(LimitKind::TotalSize, self.total_size, &path, pos)?;
+
+        entry.data.truncate(0);
+        for chunk in chunks {
+            entry.data.push(match chunk {
+                SparseChunk::Data(len) => EntryIo::Data((&self.archive.inner).take(len)),
+                SparseChunk::Hole(len) => EntryIo::Pad(io::repeat(0).take(len)),
+            });
         }
         Ok(())
     }
@@ -529,6 +1432,449 @@ impl<'a> EntriesFields<'a> {
     }
 }
 
+impl SkippedRegion {
+    /// Returns the position, in bytes, of the start of the skipped region in
+    /// the archive.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Returns the length, in bytes, of the skipped region.
+    pub fn length(&self) -> u64 {
+        self.length
+    }
+
+    /// Returns why the region was skipped, i.e. what was wrong with the
+    /// header at its start.
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+}
+
+/// A region of a sparse file, listed in order of offsets in the file.
+pub(crate) enum SparseChunk {
+    /// The given number of bytes are stored next in the archive.
+    Data(u64),
+    /// The given number of bytes are a hole filled with zeros.
+    Hole(u64),
+}
+
+/// Incrementally turns the blocks of a sparse map into the chunks of the file
+/// they describe, validating the blocks against the data in the archive.
+pub(crate) struct SparseMap {
+    chunks: Vec<SparseChunk>,
+    cur: u64,
+    size: u64,
+    remaining: u64,
+    aligned: bool,
+}
+
+impl SparseMap {
+    /// Creates a map for a sparse file with `size` bytes of data stored in
+    /// the archive. With `aligned`, each block of data except the last must
+    /// have a length which is a multiple of 512 bytes.
+    pub(crate) fn new(size: u64, aligned: bool) -> SparseMap {
+        SparseMap {
+            chunks: Vec::new(),
+            cur: 0,
+            size,
+            remaining: size,
+            aligned,
+        }
+    }
+
+    /// Returns the offset in the file just past the last block added.
+    pub(crate) fn offset(&self) -> u64 {
+        self.cur
+    }
+
+    pub(crate) fn add_gnu_block(&mut self, block: &GnuSparseHeader) -> io::Result<()> {
+        if block.is_empty() {
+            return Ok(());
+        }
+        self.add(block.offset()?, block.length()?)
+    }
+
+    pub(crate) fn add(&mut self, off: u64, len: u64) -> io::Result<()> {
+        if self.aligned && len != 0 && (self.size - self.remaining) % 512 != 0 {
+            return Err(other(
+                "previous block in sparse file was not \
+                 aligned to 512-byte boundary",
+            ));
+        } else if off < self.cur {
+            return Err(other(
+                "out of order or overlapping sparse \
+                 blocks",
+            ));
+        } else if self.cur < off {
+            self.chunks.push(SparseChunk::Hole(off - self.cur));
+        }
+        self.cur = off
+            .checked_add(len)
+            .ok_or_else(|| other("more bytes listed in sparse file than u64 can hold"))?;
+        self.remaining = self.remaining.checked_sub(len).ok_or_else(|| {
+            other(
+                "sparse file consumed more data than the header \
+                 listed",
+            )
+        })?;
+        self.chunks.push(SparseChunk::Data(len));
+        Ok(())
+    }
+
+    /// Returns the chunks of the file, verifying that all of the data stored
+    /// in the archive was consumed by the map.
+    pub(crate) fn finish(self) -> io::Result<Vec<SparseChunk>> {
+        if self.remaining > 0 {
+            return Err(other(
+                "mismatch in sparse file chunks and \
+                 entry size in header",
+            ));
+        }
+        Ok(self.chunks)
+    }
+}
+
+fn pax_sparse_num(entry: &EntryFields, key: &[u8]) -> io::Result<u64> {
+    entry.pax_num(key)?.ok_or_else(|| {
+        other(&format!(
+            "sparse file is missing the `{}` pax record",
+            String::from_utf8_lossy(key)
+        ))
+    })
+}
+
+/// Collects the blocks of a version 0.0 sparse map, which are listed as
+/// repeated `GNU.sparse.offset` and `GNU.sparse.numbytes` records.
+fn pax_sparse_records(entry: &EntryFields) -> io::Result<Option<Vec<(u64, u64)>>> {
+    let pax = match entry.pax_extensions {
+        Some(ref pax) => pax,
+        None => return Ok(None),
+    };
+    let mut blocks = Vec::new();
+    let mut offset = None;
+    let mut found = false;
+    for ext in PaxExtensions::new(pax) {
+        let ext = ext?;
+        let value = || {
+            parse_pax_num(ext.value_bytes())
+                .ok_or_else(|| other("invalid number in sparse map pax record"))
+        };
+        match ext.key_bytes() {
+            b"GNU.sparse.offset" => {
+                if offset.is_some() {
+                    return Err(other("sparse map offset listed without a length"));
+                }
+                offset = Some(value()?);
+                found = true;
+            }
+            b"GNU.sparse.numbytes" => match offset.take() {
+                Some(off) => blocks.push((off, value()?)),
+                None => return Err(other("sparse map length listed without an offset")),
+            },
+            _ => {}
+        }
+    }
+    if offset.is_some() {
+        return Err(other("sparse map offset listed without a length"));
+    }
+    Ok(if found { Some(blocks) } else { None })
+}
+
+/// Parses the `GNU.sparse.map` record of a version 0.1 sparse map, which is a
+/// comma separated list of alternating offsets and lengths.
+fn parse_pax_sparse_map_record(map: &[u8]) -> io::Result<Vec<(u64, u64)>> {
+    if map.is_empty() {
+        return Ok(Vec::new());
+    }
+    let nums = map
+        .split(|b| *b == b',')
+        .map(|n| parse_pax_num(n).ok_or_else(|| other("invalid number in GNU.sparse.map")))
+        .collect::<io::Result<Vec<_>>>()?;
+    if nums.len() % 2 != 0 {
+        return Err(other("odd number of values in GNU.sparse.map"));
+    }
+    Ok(nums.chunks(2).map(|c| (c[0], c[1])).collect())
+}
+
+/// The way the sparse map of a GNU sparse file in the pax format is stored.
+///
+/// There are three versions of this format. Versions 0.0 and 0.1 store the
+/// map in `GNU.sparse.*` pax records while version 1.0 stores it as decimal
+/// text at the start of the entry's data, to be parsed with `PaxSparseMap`.
+pub(crate) enum PaxSparse {
+    Records {
+        real_size: u64,
+        blocks: Vec<(u64, u64)>,
+    },
+    Data {
+        real_size: u64,
+    },
+}
+
+/// Returns how the sparse map of `entry` is stored, if it's a GNU sparse file
+/// in the pax format.
+pub(crate) fn pax_sparse_format(entry: &EntryFields) -> io::Result<Option<PaxSparse>> {
+    if entry.pax_extensions.is_none() {
+        return Ok(None);
+    }
+    let major = entry.pax_num(b"GNU.sparse.major")?;
+    let minor = entry.pax_num(b"GNU.sparse.minor")?;
+    match (major, minor.unwrap_or(0)) {
+        (Some(1), 0) => {
+            let real_size = pax_sparse_num(entry, b"GNU.sparse.realsize")?;
+            Ok(Some(PaxSparse::Data { real_size }))
+        }
+        (Some(major), minor) if major != 0 => Err(other(&format!(
+            "unsupported GNU sparse format version {}.{}",
+            major, minor
+        ))),
+        _ => {
+            let blocks = match entry.pax_value(b"GNU.sparse.map") {
+                Some(map) => parse_pax_sparse_map_record(map)?,
+                None => match pax_sparse_records(entry)? {
+                    Some(blocks) => blocks,
+                    None => return Ok(None),
+                },
+            };
+            let real_size = pax_sparse_num(entry, b"GNU.sparse.size")?;
+            Ok(Some(PaxSparse::Records { real_size, blocks }))
+        }
+    }
+}
+
+/// Turns the blocks of the sparse map of a GNU sparse file in the pax format
+/// into the chunks of the file, given the `size` of the data stored in the
+/// archive (not counting a version 1.0 map).
+pub(crate) fn pax_sparse_chunks(
+    entry: &EntryFields,
+    size: u64,
+    real_size: u64,
+    blocks: Vec<(u64, u64)>,
+) -> io::Result<Vec<SparseChunk>> {
+    if let Some(numblocks) = entry.pax_num(b"GNU.sparse.numblocks")? {
+        if numblocks != blocks.len() as u64 {
+            return Err(other(
+                "mismatch in number of sparse blocks and \
+                 GNU.sparse.numblocks",
+            ));
+        }
+    }
+
+    // Unlike the old GNU format, the data of the chunks isn't required to
+    // be aligned to 512-byte boundaries and the map doesn't necessarily
+    // describe the trailing hole of the file.
+    let mut map = SparseMap::new(size, false);
+    for (off, len) in blocks {
+        map.add(off, len)?;
+    }
+    if map.offset() > real_size {
+        return Err(other(
+            "mismatch in sparse file chunks and \
+             size in header",
+        ));
+    }
+    map.add(real_size, 0)?;
+    map.finish()
+}
+
+/// Parser for the sparse map of a version 1.0 sparse file, stored at the
+/// start of the entry's data.
+///
+/// The map is a newline separated list of decimal numbers: the number of
+/// blocks followed by an offset and length for each block, padded with zeros
+/// to a 512-byte boundary.
+pub(crate) struct PaxSparseMap {
+    nums: Vec<u64>,
+    wanted: Option<usize>,
+    line: Vec<u8>,
+    len: u64,
+}
+
+impl PaxSparseMap {
+    pub(crate) fn new() -> PaxSparseMap {
+        PaxSparseMap {
+            nums: Vec::new(),
+            wanted: None,
+            line: Vec::new(),
+            len: 0,
+        }
+    }
+
+    /// Parses the next 512-byte block of the map, returning whether the map
+    /// is complete.
+    pub(crate) fn parse(&mut self, block: &[u8; 512]) -> io::Result<bool> {
+        self.len += 512;
+        for b in block.iter() {
+            if self.is_complete() {
+                break;
+            }
+            if *b != b'\n' {
+                self.line.push(*b);
+                continue;
+            }
+            let num =
+                parse_pax_num(&self.line).ok_or_else(|| other("invalid number in sparse map"))?;
+            self.line.clear();
+            match self.wanted {
+                None => {
+                    let n = num
+                        .checked_mul(2)
+                        .and_then(|n| usize::try_from(n).ok())
+                        .ok_or_else(|| other("too many blocks in sparse map"))?;
+                    self.wanted = Some(n);
+                }
+                Some(_) => self.nums.push(num),
+            }
+        }
+        Ok(self.is_complete())
+    }
+
+    fn is_complete(&self) -> bool {
+        self.wanted.map(|n| self.nums.len() >= n).unwrap_or(false)
+    }
+
+    /// Returns the number of bytes of data the map occupied.
+    pub(crate) fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub(crate) fn blocks(&self) -> Vec<(u64, u64)> {
+        self.nums.chunks(2).map(|c| (c[0], c[1])).collect()
+    }
+}
+
+/// Starts the sparse map of an old GNU sparse file with the blocks listed in
+/// its header, given the `size` of the data stored in the archive.
+pub(crate) fn gnu_sparse_map(gnu: &GnuHeader, size: u64) -> io::Result<SparseMap> {
+    let mut map = SparseMap::new(size, true);
+    for block in gnu.sparse.iter() {
+        map.add_gnu_block(block)?;
+    }
+    Ok(map)
+}
+
+/// Finishes the sparse map of an old GNU sparse file once all extension
+/// headers were added, returning the size of the file and its chunks.
+pub(crate) fn finish_gnu_sparse(
+    map: SparseMap,
+    gnu: &GnuHeader,
+) -> io::Result<(u64, Vec<SparseChunk>)> {
+    if map.offset() != gnu.real_size()? {
+        return Err(other(
+            "mismatch in sparse file chunks and \
+             size in header",
+        ));
+    }
+    let size = map.offset();
+    Ok((size, map.finish()?))
+}
+
+/// GNU long name and pax extension entries read so far, describing the next
+/// member of an archive.
+#[derive(Default)]
+pub(crate) struct MemberMeta {
+    gnu_longname: Option<Vec<u8>>,
+    gnu_longlink: Option<Vec<u8>>,
+    pax_extensions: Option<Vec<u8>>,
+}
+
+pub(crate) enum MetaKind {
+    LongName,
+    LongLink,
+    Pax,
+}
+
+impl MemberMeta {
+    /// Returns which kind of entry describing the next member `header` is, if
+    /// any.
+    pub(crate) fn kind(&self, header: &Header) -> io::Result<Option<MetaKind>> {
+        let is_recognized_header = header.as_gnu().is_some() || header.as_ustar().is_some();
+        if !is_recognized_header {
+            return Ok(None);
+        }
+        let entry_type = header.entry_type();
+        if entry_type.is_gnu_longname() {
+            if self.gnu_longname.is_some() {
+                return Err(other(
+                    "two long name entries describing \
+                     the same member",
+                ));
+            }
+            Ok(Some(MetaKind::LongName))
+        } else if entry_type.is_gnu_longlink() {
+            if self.gnu_longlink.is_some() {
+                return Err(other(
+                    "two long name entries describing \
+                     the same member",
+                ));
+            }
+            Ok(Some(MetaKind::LongLink))
+        } else if entry_type.is_pax_local_extensions() {
+            if self.pax_extensions.is_some() {
+                return Err(other(
+                    "two pax extensions entries describing \
+                     the same member",
+                ));
+            }
+            Ok(Some(MetaKind::Pax))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub(crate) fn add(&mut self, kind: MetaKind, data: Vec<u8>) {
+        match kind {
+            MetaKind::LongName => self.gnu_longname = Some(data),
+            MetaKind::LongLink => self.gnu_longlink = Some(data),
+            MetaKind::Pax => self.pax_extensions = Some(data),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.gnu_longname.is_none() && self.gnu_longlink.is_none() && self.pax_extensions.is_none()
+    }
+
+    /// Returns the size of the next member listed in a pax `size` record.
+    pub(crate) fn pax_size(&self) -> Option<u64> {
+        self.pax_extensions
+            .as_ref()
+            .and_then(|pax| pax_extensions_size(pax))
+    }
+
+    pub(crate) fn apply(self, fields: &mut EntryFields) {
+        fields.long_pathname = self.gnu_longname;
+        fields.long_linkname = self.gnu_longlink;
+        fields.pax_extensions = self.pax_extensions;
+    }
+}
+
+/// Returns the size of the data following `header` in the archive.
+///
+/// A pax `size` record applies to headers which don't list a size themselves.
+pub(crate) fn entry_data_size(header: &Header, pax_size: Option<u64>) -> io::Result<u64> {
+    // GNU tar leaves the numeric fields of volume labels empty, they never
+    // have any data though.
+    let size = if header.entry_type().is_gnu_volume_label() {
+        0
+    } else {
+        header.entry_size()?
+    };
+    match pax_size {
+        Some(pax_size) if size == 0 => Ok(pax_size),
+        _ => Ok(size),
+    }
+}
+
+/// Rounds `size` up to the number of bytes of whole 512-byte blocks it
+/// occupies in an archive.
+pub(crate) fn block_size(size: u64) -> io::Result<u64> {
+    let size = size
+        .checked_add(511)
+        .ok_or_else(|| other("size overflow"))?;
+    Ok(size & !(512 - 1))
+}
+
 impl<'a> Iterator for EntriesFields<'a> {
     type Item = io::Result<Entry<'a, io::Empty>>;
 
@@ -551,9 +1897,115 @@ impl<'a> Iterator for EntriesFields<'a> {
     }
 }
 
+impl<R: ?Sized> ArchiveInner<R> {
+    fn start_member(&self, start: u64, size: u64, end: u64) {
+        let mut volume = self.volume.borrow_mut();
+        volume.member_start = start;
+        volume.member_size = size;
+        volume.member_end = end;
+    }
+}
+
+impl<R: ?Sized + Read> ArchiveInner<R> {
+    /// Reads the headers at the start of the next volume of a multi-volume
+    /// archive.
+    ///
+    /// Each volume may start with a volume label, followed by a continuation
+    /// header if a member was split off from the previous volume. Any other
+    /// header is the next member of the archive and is left to be read again.
+    fn start_volume(&self) -> io::Result<()> {
+        let mut volume = self.volume.borrow_mut();
+        let pos = self.pos.get();
+        let in_member = pos < volume.member_end;
+        let mut header = Header::new_old();
+        if !self.read_volume_header(&mut header)? {
+            return if in_member {
+                Err(other("tar volume ended in the middle of a member"))
+            } else {
+                Ok(())
+            };
+        }
+        if header.entry_type().is_gnu_volume_label() {
+            volume.labels.push(header.path_bytes().into_owned());
+            if !self.read_volume_header(&mut header)? {
+                return if in_member {
+                    Err(other("tar volume ended in the middle of a member"))
+                } else {
+                    Ok(())
+                };
+            }
+        }
+
+        if in_member {
+            if !header.entry_type().is_gnu_multi_volume() {
+                return Err(other(
+                    "next tar volume does not continue the member \
+                     split off from the previous volume",
+                ));
+            }
+            let offset = match header.as_gnu() {
+                Some(gnu) => gnu.offset()?,
+                None => return Err(other("continuation entry type listed but not GNU header")),
+            };
+            let written = pos - volume.member_start;
+            let remaining = volume.member_size.checked_sub(written);
+            if offset != written || remaining != Some(header.entry_size()?) {
+                return Err(other(
+                    "continuation header in next tar volume does not \
+                     match the member split off from the previous volume",
+                ));
+            }
+        } else if header.entry_type().is_gnu_multi_volume() {
+            return Err(other(
+                "tar volume continues a member which wasn't split off \
+                 from the previous volume",
+            ));
+        } else {
+            volume.pending.extend_from_slice(header.as_bytes());
+        }
+        Ok(())
+    }
+
+    fn read_volume_header(&self, header: &mut Header) -> io::Result<bool> {
+        let mut obj = self.obj.borrow_mut();
+        match try_read_all(&mut *obj, header.as_mut_bytes()) {
+            Ok(true) => {}
+            Ok(false) => return Ok(false),
+            Err(ref e) if is_end_of_volume(e) => {
+                return Err(other("tar volume ended in the middle of a header"));
+            }
+            Err(e) => return Err(e),
+        }
+        if header.as_bytes().iter().all(|i| *i == 0) {
+            return Ok(true);
+        }
+        verify_cksum(header)?;
+        Ok(true)
+    }
+}
+
 impl<'a, R: ?Sized + Read> Read for &'a ArchiveInner<R> {
     fn read(&mut self, into: &mut [u8]) -> io::Result<usize> {
-        let i = self.obj.borrow_mut().read(into)?;
+        let i = loop {
+            {
+                let mut volume = self.volume.borrow_mut();
+                if !volume.pending.is_empty() {
+                    let n = cmp::min(into.len(), volume.pending.len());
+                    into[..n].copy_from_slice(&volume.pending[..n]);
+                    volume.pending.drain(..n);
+                    break n;
+                }
+            }
+            let res = self.obj.borrow_mut().read(into);
+            match res {
+                Ok(i) => break i,
+                Err(ref e) if is_end_of_volume(e) => self.start_volume()?,
+                Err(e) => return Err(e),
+            }
+        };
+        if let Some(ref mut record) = *self.record.borrow_mut() {
+            record.extend_from_slice(&into[..i]);
+        }
         self.pos.set(self.pos.get() + i as u64);
         Ok(i)
     }
@@ -567,11 +2019,24 @@ impl<'a, R: ?Sized + Seek> Seek for &'a ArchiveInner<R> {
     }
 }
 
+pub(crate) fn verify_cksum(header: &Header) -> io::Result<()> {
+    let sum = header.as_bytes()[..148]
+        .iter()
+        .chain(&header.as_bytes()[156..])
+        .fold(0, |a, b| a + (*b as u32))
+        + 8 * 32;
+    let cksum = header.cksum()?;
+    if sum != cksum {
+        return Err(other("archive header checksum mismatch"));
+    }
+    Ok(())
+}
+
 /// Try to fill the buffer from the reader.
 ///
 /// If the reader reaches its end before filling the buffer at all, returns `false`.
 /// Otherwise returns `true`.
-fn try_read_all<R: Read>(r: &mut R, buf: &mut [u8]) -> io::Result<bool> {
+fn try_read_all<R: Read + ?Sized>(r: &mut R, buf: &mut [u8]) -> io::Result<bool> {
     let mut read = 0;
     while read < buf.len() {
         match r.read(&mut buf[read..])? {
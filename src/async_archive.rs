@@ -0,0 +1,432 @@
+use std::borrow::Cow;
+use std::cmp;
+use std::collections::VecDeque;
+use std::io;
+use std::path::Path;
+use std::pin::Pin;
+use std::str;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncReadExt, ReadBuf};
+
+use crate::archive::{
+    block_size, entry_data_size, finish_gnu_sparse, gnu_sparse_map, pax_sparse_chunks,
+    pax_sparse_format, verify_cksum, MemberMeta, PaxSparse, PaxSparseMap, SparseChunk,
+};
+use crate::entry::EntryFields;
+use crate::error::TarError;
+use crate::{other, GnuExtSparseHeader, Header, PaxExtensions};
+
+/// A top-level representation of an archive file read from an `AsyncRead`.
+///
+/// This is the asynchronous counterpart of `Archive`, sharing its parsing of
+/// headers, GNU long name entries, pax extensions and sparse files. Entries
+/// are read in sequence with `next_entry`, each borrowing the archive until
+/// it's dropped.
+///
+/// Unpacking to the filesystem and multi-volume archives are only supported
+/// by `Archive`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use tar::AsyncArchive;
+/// use tokio::io::AsyncReadExt;
+///
+/// # async fn run() -> std::io::Result<()> {
+/// let file = tokio::fs::File::open("foo.tar").await?;
+/// let mut ar = AsyncArchive::new(file);
+/// while let Some(mut entry) = ar.next_entry().await? {
+///     let mut contents = Vec::new();
+///     entry.read_to_end(&mut contents).await?;
+///     println!("{}: {} bytes", entry.path()?.display(), contents.len());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct AsyncArchive<R> {
+    obj: R,
+    pos: u64,
+    next: u64,
+    done: bool,
+    ignore_zeros: bool,
+}
+
+/// A read-only view into an entry of an `AsyncArchive`.
+///
+/// This structure is a window into a portion of the archive and implements
+/// `AsyncRead` for the contents of the entry. Holes of sparse files are read
+/// as zeros.
+pub struct AsyncEntry<'a, R> {
+    archive: &'a mut AsyncArchive<R>,
+    fields: EntryFields<'static>,
+    chunks: VecDeque<SparseChunk>,
+}
+
+impl<R: AsyncRead + Unpin> AsyncArchive<R> {
+    /// Create a new archive with the underlying object as the reader.
+    pub fn new(obj: R) -> AsyncArchive<R> {
+        AsyncArchive {
+            obj,
+            pos: 0,
+            next: 0,
+            done: false,
+            ignore_zeros: false,
+        }
+    }
+
+    /// Unwrap this archive, returning the underlying object.
+    pub fn into_inner(self) -> R {
+        self.obj
+    }
+
+    /// Ignore zeroed headers, which would otherwise indicate to the archive
+    /// that it has no more entries.
+    ///
+    /// This can be used in case multiple tar archives have been concatenated
+    /// together.
+    pub fn set_ignore_zeros(&mut self, ignore_zeros: bool) {
+        self.ignore_zeros = ignore_zeros;
+    }
+
+    /// Reads the header of the next entry in this archive, returning `None`
+    /// once the end of the archive is reached.
+    ///
+    /// Any data of the previous entry which wasn't read is skipped.
+    pub async fn next_entry(&mut self) -> io::Result<Option<AsyncEntry<'_, R>>> {
+        if self.done {
+            return Ok(None);
+        }
+        match self.read_entry().await {
+            Ok(Some((fields, chunks))) => Ok(Some(AsyncEntry {
+                archive: self,
+                fields,
+                chunks,
+            })),
+            Ok(None) => {
+                self.done = true;
+                Ok(None)
+            }
+            Err(e) => {
+                self.done = true;
+                Err(e)
+            }
+        }
+    }
+
+    async fn read_entry(
+        &mut self,
+    ) -> io::Result<Option<(EntryFields<'static>, VecDeque<SparseChunk>)>> {
+        let mut meta = MemberMeta::default();
+        loop {
+            let mut fields = match self.read_header(meta.pax_size()).await? {
+                Some(fields) => fields,
+                None if !meta.is_empty() => {
+                    return Err(other(
+                        "members found describing a future member \
+                         but no future member found",
+                    ));
+                }
+                None => return Ok(None),
+            };
+
+            if let Some(kind) = meta.kind(&fields.header)? {
+                let mut data = Vec::with_capacity(cmp::min(fields.size, 128 * 1024) as usize);
+                (&mut self.obj)
+                    .take(fields.size)
+                    .read_to_end(&mut data)
+                    .await?;
+                self.pos += data.len() as u64;
+                meta.add(kind, data);
+                continue;
+            }
+
+            meta.apply(&mut fields);
+            let chunks = self.read_sparse_header(&mut fields).await?;
+            return Ok(Some((fields, chunks)));
+        }
+    }
+
+    async fn read_header(
+        &mut self,
+        pax_size: Option<u64>,
+    ) -> io::Result<Option<EntryFields<'static>>> {
+        let mut header = Header::new_old();
+        let mut header_pos = self.next;
+        loop {
+            // Seek to the start of the next header in the archive
+            self.skip(self.next - self.pos).await?;
+
+            // EOF is an indicator that we are at the end of the archive.
+            if !self.read_block(header.as_mut_bytes()).await? {
+                return Ok(None);
+            }
+
+            // If a header is not all zeros, we have another valid header.
+            // Otherwise, check if we are ignoring zeros and continue, or break
+            // as if this is the end of the archive.
+            if !header.as_bytes().iter().all(|i| *i == 0) {
+                self.next += 512;
+                break;
+            }
+
+            if !self.ignore_zeros {
+                return Ok(None);
+            }
+            self.next += 512;
+            header_pos = self.next;
+        }
+
+        verify_cksum(&header)?;
+
+        let file_pos = self.next;
+        let size = entry_data_size(&header, pax_size)?;
+        self.next = self
+            .next
+            .checked_add(block_size(size)?)
+            .ok_or_else(|| other("size overflow"))?;
+        Ok(Some(EntryFields::new(header, size, header_pos, file_pos)))
+    }
+
+    async fn read_sparse_header(
+        &mut self,
+        fields: &mut EntryFields<'static>,
+    ) -> io::Result<VecDeque<SparseChunk>> {
+        if fields.header.entry_type().is_gnu_sparse() {
+            let gnu = match fields.header.as_gnu() {
+                Some(gnu) => gnu,
+                None => return Err(other("sparse entry type listed but not GNU header")),
+            };
+            let mut map = gnu_sparse_map(gnu, fields.size)?;
+            if gnu.is_extended() {
+                let mut ext = GnuExtSparseHeader::new();
+                ext.isextended[0] = 1;
+                while ext.is_extended() {
+                    if !self.read_block(ext.as_mut_bytes()).await? {
+                        return Err(other("failed to read extension"));
+                    }
+
+                    self.next += 512;
+                    for block in ext.sparse.iter() {
+                        map.add_gnu_block(block)?;
+                    }
+                }
+            }
+            let (size, chunks) = finish_gnu_sparse(map, gnu)?;
+            fields.size = size;
+            return Ok(chunks.into());
+        }
+
+        let (real_size, blocks, map_len) = match pax_sparse_format(fields)? {
+            Some(PaxSparse::Records { real_size, blocks }) => (real_size, blocks, 0),
+            Some(PaxSparse::Data { real_size }) => {
+                let mut map = PaxSparseMap::new();
+                let mut block = [0; 512];
+                loop {
+                    if map.len() >= fields.size || !self.read_block(&mut block).await? {
+                        let err = io::Error::new(io::ErrorKind::UnexpectedEof, "unexpected EOF");
+                        return Err(TarError::new("failed to read sparse map", err).into());
+                    }
+                    if map.parse(&block)? {
+                        break;
+                    }
+                }
+                (real_size, map.blocks(), map.len())
+            }
+            None => return Ok(vec![SparseChunk::Data(fields.size)].into()),
+        };
+        let chunks = pax_sparse_chunks(fields, fields.size - map_len, real_size, blocks)?;
+        fields.size = real_size;
+        Ok(chunks.into())
+    }
+
+    /// Reads a whole block, returning `false` if the archive ended right
+    /// before it.
+    async fn read_block(&mut self, buf: &mut [u8]) -> io::Result<bool> {
+        let mut read = 0;
+        while read < buf.len() {
+            match self.obj.read(&mut buf[read..]).await? {
+                0 => {
+                    if read == 0 {
+                        return Ok(false);
+                    }
+
+                    return Err(other("failed to read entire block"));
+                }
+                n => {
+                    read += n;
+                    self.pos += n as u64;
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    async fn skip(&mut self, mut amt: u64) -> io::Result<()> {
+        let mut buf = [0u8; 4096 * 8];
+        while amt > 0 {
+            let n = cmp::min(amt, buf.len() as u64);
+            let n = self.obj.read(&mut buf[..n as usize]).await?;
+            if n == 0 {
+                return Err(other("unexpected EOF during skip"));
+            }
+            amt -= n as u64;
+            self.pos += n as u64;
+        }
+        Ok(())
+    }
+}
+
+impl<'a, R: AsyncRead + Unpin> AsyncEntry<'a, R> {
+    /// Returns the path name for this entry.
+    ///
+    /// See `Entry::path` for details.
+    pub fn path(&self) -> io::Result<Cow<Path>> {
+        self.fields.path()
+    }
+
+    /// Returns the raw bytes listed for this entry.
+    pub fn path_bytes(&self) -> Cow<[u8]> {
+        self.fields.path_bytes()
+    }
+
+    /// Returns the link name for this entry, if any is found.
+    ///
+    /// See `Entry::link_name` for details.
+    pub fn link_name(&self) -> io::Result<Option<Cow<Path>>> {
+        self.fields.link_name()
+    }
+
+    /// Returns the link name for this entry, in bytes, if listed.
+    pub fn link_name_bytes(&self) -> Option<Cow<[u8]>> {
+        self.fields.link_name_bytes()
+    }
+
+    /// Returns an iterator over the pax extensions describing this entry, if
+    /// any.
+    ///
+    /// Unlike `Entry::pax_extensions` this doesn't read the contents of pax
+    /// extension entries themselves (such as global extensions), those can
+    /// be read like any other entry and parsed with `PaxExtensions::new`.
+    pub fn pax_extensions(&self) -> Option<PaxExtensions> {
+        self.fields
+            .pax_extensions
+            .as_ref()
+            .map(|pax| PaxExtensions::new(pax))
+    }
+
+    /// Returns access to the header of this entry in the archive.
+    pub fn header(&self) -> &Header {
+        &self.fields.header
+    }
+
+    /// Returns access to the size of this entry in the archive.
+    ///
+    /// For sparse files this is the size of the file with its holes.
+    pub fn size(&self) -> u64 {
+        self.fields.size
+    }
+
+    /// Returns the last modification time of this entry in Unix time format.
+    pub fn mtime(&self) -> io::Result<u64> {
+        self.fields.mtime()
+    }
+
+    /// Returns the last access time of this entry in Unix time format, if any
+    /// is recorded.
+    pub fn atime(&self) -> io::Result<Option<u64>> {
+        self.fields.atime()
+    }
+
+    /// Returns the user ID of the owner of this entry.
+    pub fn uid(&self) -> io::Result<u64> {
+        self.fields.uid()
+    }
+
+    /// Returns the group ID of the owner of this entry.
+    pub fn gid(&self) -> io::Result<u64> {
+        self.fields.gid()
+    }
+
+    /// Returns the user name of the owner of this entry.
+    pub fn username(&self) -> Result<Option<&str>, str::Utf8Error> {
+        self.fields.username()
+    }
+
+    /// Returns the user name of the owner of this entry in bytes, if present.
+    pub fn username_bytes(&self) -> Option<&[u8]> {
+        self.fields.username_bytes()
+    }
+
+    /// Returns the group name of the owner of this entry.
+    pub fn groupname(&self) -> Result<Option<&str>, str::Utf8Error> {
+        self.fields.groupname()
+    }
+
+    /// Returns the group name of the owner of this entry in bytes, if present.
+    pub fn groupname_bytes(&self) -> Option<&[u8]> {
+        self.fields.groupname_bytes()
+    }
+
+    /// Returns the starting position, in bytes, of the header of this entry in
+    /// the archive.
+    pub fn raw_header_position(&self) -> u64 {
+        self.fields.header_pos
+    }
+
+    /// Returns the starting position, in bytes, of the file of this entry in
+    /// the archive.
+    pub fn raw_file_position(&self) -> u64 {
+        self.fields.file_pos
+    }
+}
+
+impl<'a, R: AsyncRead + Unpin> AsyncRead for AsyncEntry<'a, R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        while buf.remaining() > 0 {
+            let chunk = match this.chunks.front_mut() {
+                Some(chunk) => chunk,
+                None => break,
+            };
+            match chunk {
+                SparseChunk::Hole(0) | SparseChunk::Data(0) => {
+                    this.chunks.pop_front();
+                }
+                SparseChunk::Hole(len) => {
+                    let n = cmp::min(*len, buf.remaining() as u64) as usize;
+                    buf.initialize_unfilled_to(n)
+                        .iter_mut()
+                        .for_each(|b| *b = 0);
+                    buf.advance(n);
+                    *len -= n as u64;
+                    break;
+                }
+                SparseChunk::Data(len) => {
+                    let n = cmp::min(*len, buf.remaining() as u64) as usize;
+                    let mut data = ReadBuf::new(buf.initialize_unfilled_to(n));
+                    match Pin::new(&mut this.archive.obj).poll_read(cx, &mut data) {
+                        Poll::Ready(Ok(())) => {}
+                        other => return other,
+                    }
+                    let n = data.filled().len();
+                    if n == 0 {
+                        return Poll::Ready(Err(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            "unexpected EOF in entry data",
+                        )));
+                    }
+                    buf.advance(n);
+                    *len -= n as u64;
+                    this.archive.pos += n as u64;
+                    break;
+                }
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
@@ -0,0 +1,365 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+
+use crate::builder::{prepare_fs_header, prepare_header_link, prepare_header_path};
+use crate::header::HeaderMode;
+use crate::Header;
+
+/// A structure for building archives into an `AsyncWrite`.
+///
+/// This is the asynchronous counterpart of `Builder`, sharing its encoding of
+/// headers and GNU long name entries. Files on the local filesystem are read
+/// through `tokio::fs`.
+///
+/// Unlike `Builder`, the archive isn't finished when this structure is
+/// dropped as that would require blocking. Call `finish` or `into_inner` once
+/// all entries were appended, otherwise the archive lacks its terminating
+/// blocks of zeros.
+///
+/// # Examples
+///
+/// ```no_run
+/// use tar::AsyncBuilder;
+///
+/// # async fn run() -> std::io::Result<()> {
+/// let file = tokio::fs::File::create("foo.tar").await?;
+/// let mut ar = AsyncBuilder::new(file);
+/// ar.append_path("file1.txt").await?;
+/// ar.append_dir_all("bardir", ".").await?;
+/// ar.finish().await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct AsyncBuilder<W> {
+    mode: HeaderMode,
+    follow: bool,
+    finished: bool,
+    obj: W,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncBuilder<W> {
+    /// Create a new archive builder with the underlying object as the
+    /// destination of all data written. The builder will use
+    /// `HeaderMode::Complete` by default.
+    pub fn new(obj: W) -> AsyncBuilder<W> {
+        AsyncBuilder {
+            mode: HeaderMode::Complete,
+            follow: true,
+            finished: false,
+            obj,
+        }
+    }
+
+    /// Changes the HeaderMode that will be used when reading fs Metadata for
+    /// methods that implicitly read metadata for an input Path. Notably, this
+    /// does _not_ apply to `append(Header)`.
+    pub fn mode(&mut self, mode: HeaderMode) {
+        self.mode = mode;
+    }
+
+    /// Follow symlinks, archiving the contents of the file they point to rather
+    /// than adding a symlink to the archive. Defaults to true.
+    pub fn follow_symlinks(&mut self, follow: bool) {
+        self.follow = follow;
+    }
+
+    /// Gets shared reference to the underlying object.
+    pub fn get_ref(&self) -> &W {
+        &self.obj
+    }
+
+    /// Gets mutable reference to the underlying object.
+    ///
+    /// Note that care must be taken while writing to the underlying
+    /// object. But, e.g. `get_mut().flush()` is claimed to be safe and
+    /// useful in the situations when one needs to be ensured that
+    /// tar entry was flushed to the disk.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.obj
+    }
+
+    /// Unwrap this archive, returning the underlying object.
+    ///
+    /// This function will finish writing the archive if the `finish` function
+    /// hasn't yet been called, returning any I/O error which happens during
+    /// that operation.
+    pub async fn into_inner(mut self) -> io::Result<W> {
+        if !self.finished {
+            self.finish().await?;
+        }
+        Ok(self.obj)
+    }
+
+    /// Adds a new entry to this archive.
+    ///
+    /// This function will append the header specified, followed by contents of
+    /// the stream specified by `data`. To produce a valid archive the `size`
+    /// field of `header` must be the same as the length of the stream that's
+    /// being written. Additionally the checksum for the header should have been
+    /// set via the `set_cksum` method.
+    pub async fn append<R: AsyncRead + Unpin>(
+        &mut self,
+        header: &Header,
+        mut data: R,
+    ) -> io::Result<()> {
+        append(&mut self.obj, Vec::new(), header, &mut data).await
+    }
+
+    /// Adds a new entry to this archive with the specified path.
+    ///
+    /// This function will set the specified path in the given header, which may
+    /// require appending a GNU long-name extension entry to the archive first.
+    /// The checksum for the header will be automatically updated via the
+    /// `set_cksum` method after setting the path. No other metadata in the
+    /// header will be modified.
+    ///
+    /// Then it will append the header, followed by contents of the stream
+    /// specified by `data`. To produce a valid archive the `size` field of
+    /// `header` must be the same as the length of the stream that's being
+    /// written.
+    pub async fn append_data<P: AsRef<Path>, R: AsyncRead + Unpin>(
+        &mut self,
+        header: &mut Header,
+        path: P,
+        mut data: R,
+    ) -> io::Result<()> {
+        let mut prefix = Vec::new();
+        prepare_header_path(&mut prefix, header, path.as_ref())?;
+        header.set_cksum();
+        append(&mut self.obj, prefix, header, &mut data).await
+    }
+
+    /// Adds a new link (symbolic or hard) entry to this archive with the
+    /// specified path and target.
+    ///
+    /// See `Builder::append_link` for details.
+    pub async fn append_link<P: AsRef<Path>, T: AsRef<Path>>(
+        &mut self,
+        header: &mut Header,
+        path: P,
+        target: T,
+    ) -> io::Result<()> {
+        let mut prefix = Vec::new();
+        prepare_header_path(&mut prefix, header, path.as_ref())?;
+        prepare_header_link(&mut prefix, header, target.as_ref())?;
+        header.set_cksum();
+        append(&mut self.obj, prefix, header, &mut tokio::io::empty()).await
+    }
+
+    /// Adds a file on the local filesystem to this archive.
+    ///
+    /// The path name for the file inside of this archive will be the same as
+    /// `path`, and it is required that the path is a relative path.
+    pub async fn append_path<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let (mode, follow) = (self.mode, self.follow);
+        append_path_with_name(&mut self.obj, path.as_ref(), None, mode, follow).await
+    }
+
+    /// Adds a file on the local filesystem to this archive under another name.
+    ///
+    /// The path name for the file inside of this archive will be `name`, which
+    /// is required to be a relative path.
+    pub async fn append_path_with_name<P: AsRef<Path>, N: AsRef<Path>>(
+        &mut self,
+        path: P,
+        name: N,
+    ) -> io::Result<()> {
+        let (mode, follow) = (self.mode, self.follow);
+        let name = Some(name.as_ref());
+        append_path_with_name(&mut self.obj, path.as_ref(), name, mode, follow).await
+    }
+
+    /// Adds a file to this archive with the given path as the name of the file
+    /// in the archive.
+    ///
+    /// This will use the metadata of `file` to populate a `Header`, and it will
+    /// then append the file to the archive with the name `path`.
+    pub async fn append_file<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        file: &mut tokio::fs::File,
+    ) -> io::Result<()> {
+        let stat = file.metadata().await?;
+        let mode = self.mode;
+        append_fs(&mut self.obj, path.as_ref(), &stat, file, mode, None).await
+    }
+
+    /// Adds a directory to this archive with the given path as the name of the
+    /// directory in the archive.
+    ///
+    /// Note this will not add the contents of the directory to the archive.
+    /// See `append_dir_all` for recusively adding the contents of the directory.
+    pub async fn append_dir<P, Q>(&mut self, path: P, src_path: Q) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        let stat = tokio::fs::metadata(src_path).await?;
+        let mode = self.mode;
+        append_fs(
+            &mut self.obj,
+            path.as_ref(),
+            &stat,
+            &mut tokio::io::empty(),
+            mode,
+            None,
+        )
+        .await
+    }
+
+    /// Adds a directory and all of its contents (recursively) to this archive
+    /// with the given path as the name of the directory in the archive.
+    pub async fn append_dir_all<P, Q>(&mut self, path: P, src_path: Q) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        let (mode, follow) = (self.mode, self.follow);
+        let (path, src_path) = (path.as_ref(), src_path.as_ref());
+        let mut stack = vec![(src_path.to_path_buf(), true, false)];
+        while let Some((src, is_dir, is_symlink)) = stack.pop() {
+            let dest = path.join(src.strip_prefix(src_path).unwrap());
+            // In case of a symlink pointing to a directory, is_dir is false, but src.is_dir() will return true
+            if is_dir || (is_symlink && follow && src.is_dir()) {
+                let mut entries = tokio::fs::read_dir(&src).await?;
+                while let Some(entry) = entries.next_entry().await? {
+                    let file_type = entry.file_type().await?;
+                    stack.push((entry.path(), file_type.is_dir(), file_type.is_symlink()));
+                }
+                if dest != Path::new("") {
+                    self.append_dir(&dest, &src).await?;
+                }
+            } else if !follow && is_symlink {
+                let stat = tokio::fs::symlink_metadata(&src).await?;
+                let link_name = tokio::fs::read_link(&src).await?;
+                let empty = &mut tokio::io::empty();
+                append_fs(&mut self.obj, &dest, &stat, empty, mode, Some(&link_name)).await?;
+            } else {
+                #[cfg(unix)]
+                {
+                    let stat = tokio::fs::metadata(&src).await?;
+                    if !stat.is_file() {
+                        append_special(&mut self.obj, &dest, &stat, mode).await?;
+                        continue;
+                    }
+                }
+                let mut file = tokio::fs::File::open(&src).await?;
+                self.append_file(&dest, &mut file).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Finish writing this archive, emitting the termination sections.
+    ///
+    /// This function should only be called when the archive has been written
+    /// entirely and if an I/O error happens the underlying object still needs
+    /// to be acquired.
+    pub async fn finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        self.obj.write_all(&[0; 1024]).await?;
+        self.obj.flush().await
+    }
+}
+
+async fn append<W, R>(dst: &mut W, prefix: Vec<u8>, header: &Header, data: &mut R) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+    R: AsyncRead + Unpin + ?Sized,
+{
+    let mut buf = prefix;
+    buf.extend_from_slice(header.as_bytes());
+    dst.write_all(&buf).await?;
+    let len = tokio::io::copy(data, dst).await?;
+
+    // Pad with zeros if necessary.
+    let buf = [0; 512];
+    let remaining = 512 - (len % 512);
+    if remaining < 512 {
+        dst.write_all(&buf[..remaining as usize]).await?;
+    }
+
+    Ok(())
+}
+
+pub(crate) async fn metadata(path: &Path, follow: bool) -> io::Result<fs::Metadata> {
+    let stat = if follow {
+        tokio::fs::metadata(path).await
+    } else {
+        tokio::fs::symlink_metadata(path).await
+    };
+    stat.map_err(|err| {
+        io::Error::new(
+            err.kind(),
+            format!("{} when getting metadata for {}", err, path.display()),
+        )
+    })
+}
+
+async fn append_path_with_name<W: AsyncWrite + Unpin>(
+    dst: &mut W,
+    path: &Path,
+    name: Option<&Path>,
+    mode: HeaderMode,
+    follow: bool,
+) -> io::Result<()> {
+    let stat = metadata(path, follow).await?;
+    let ar_name = name.unwrap_or(path);
+    if stat.is_file() {
+        let mut file = tokio::fs::File::open(path).await?;
+        append_fs(dst, ar_name, &stat, &mut file, mode, None).await
+    } else if stat.is_dir() {
+        append_fs(dst, ar_name, &stat, &mut tokio::io::empty(), mode, None).await
+    } else if stat.file_type().is_symlink() {
+        let link_name = tokio::fs::read_link(path).await?;
+        let empty = &mut tokio::io::empty();
+        append_fs(dst, ar_name, &stat, empty, mode, Some(&link_name)).await
+    } else {
+        #[cfg(unix)]
+        {
+            append_special(dst, path, &stat, mode).await
+        }
+        #[cfg(not(unix))]
+        {
+            Err(crate::other(&format!(
+                "{} has unknown file type",
+                path.display()
+            )))
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn append_special<W: AsyncWrite + Unpin>(
+    dst: &mut W,
+    path: &Path,
+    stat: &fs::Metadata,
+    mode: HeaderMode,
+) -> io::Result<()> {
+    let mut buf = Vec::new();
+    crate::builder::append_special(&mut buf, None, path, stat, mode)?;
+    dst.write_all(&buf).await
+}
+
+async fn append_fs<W, R>(
+    dst: &mut W,
+    path: &Path,
+    meta: &fs::Metadata,
+    read: &mut R,
+    mode: HeaderMode,
+    link_name: Option<&Path>,
+) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+    R: AsyncRead + Unpin + ?Sized,
+{
+    let mut prefix = Vec::new();
+    let header = prepare_fs_header(&mut prefix, path, meta, mode, link_name)?;
+    append(dst, prefix, &header, read).await
+}
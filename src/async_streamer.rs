@@ -0,0 +1,371 @@
+use std::cmp;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, ReadBuf};
+
+use crate::archive::block_size;
+use crate::async_builder::metadata;
+use crate::builder::{prepare_fs_header, prepare_header_link, prepare_header_path};
+use crate::header::HeaderMode;
+use crate::{other, Header};
+
+/// A structure for building archives which implements `AsyncRead` for the
+/// archive being built.
+///
+/// This is the asynchronous counterpart of `Streamer`. Entries are queued by
+/// the `append*` methods and the archive is produced while reading from the
+/// streamer, which "auto-finishes" the archive once all entries were read.
+///
+/// The metadata of files on the local filesystem is read when they're
+/// appended, their contents are only opened once they're read.
+///
+/// # Examples
+///
+/// ```no_run
+/// use tar::AsyncStreamer;
+///
+/// # async fn run() -> std::io::Result<()> {
+/// let mut streamer = AsyncStreamer::new();
+/// streamer.append_dir_all("my_download_dir", "/home/user/Downloads").await?;
+/// let mut target = tokio::fs::File::create("my_downloads.tar").await?;
+/// tokio::io::copy(&mut streamer, &mut target).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct AsyncStreamer {
+    mode: HeaderMode,
+    follow: bool,
+    parts: VecDeque<Part>,
+    finish_bytes_remaining: usize,
+}
+
+/// A piece of the archive which still needs to be read.
+enum Part {
+    Bytes(Vec<u8>, usize),
+    Data {
+        data: Pin<Box<dyn AsyncRead + Send>>,
+        read: u64,
+    },
+    File {
+        path: PathBuf,
+        remaining: u64,
+        state: FileState,
+    },
+}
+
+enum FileState {
+    Closed,
+    Opening(Pin<Box<dyn Future<Output = io::Result<tokio::fs::File>> + Send>>),
+    Open(tokio::fs::File),
+}
+
+impl Default for AsyncStreamer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AsyncStreamer {
+    /// Creates a new empty archive streamer. The streamer will use
+    /// `HeaderMode::Complete` by default.
+    pub fn new() -> AsyncStreamer {
+        AsyncStreamer {
+            mode: HeaderMode::Complete,
+            follow: true,
+            parts: VecDeque::new(),
+            finish_bytes_remaining: 1024,
+        }
+    }
+
+    /// Changes the HeaderMode that will be used when reading fs Metadata for
+    /// methods that implicitly read metadata for an input Path. Notably, this
+    /// does _not_ apply to `append(Header)`.
+    pub fn mode(&mut self, mode: HeaderMode) {
+        self.mode = mode;
+    }
+
+    /// Follow symlinks, archiving the contents of the file they point to rather
+    /// than adding a symlink to the archive. Defaults to true.
+    pub fn follow_symlinks(&mut self, follow: bool) {
+        self.follow = follow;
+    }
+
+    /// Adds a new entry to the archive.
+    ///
+    /// This function will append the header specified, followed by contents of
+    /// the stream specified by `data`. To produce a valid archive the `size`
+    /// field of `header` must be the same as the length of the stream that's
+    /// being read. Additionally the checksum for the header should have been
+    /// set via the `set_cksum` method.
+    pub fn append<R: AsyncRead + Send + 'static>(&mut self, header: Header, data: R) {
+        self.append_stream_data(header.as_bytes().to_vec(), data);
+    }
+
+    /// Adds a new entry to this archive with the specified path.
+    ///
+    /// This function will set the specified path in the given header, which may
+    /// require appending a GNU long-name extension entry to the archive first.
+    /// The checksum for the header will be automatically updated via the
+    /// `set_cksum` method after setting the path. No other metadata in the
+    /// header will be modified.
+    pub fn append_data<P: AsRef<Path>, R: AsyncRead + Send + 'static>(
+        &mut self,
+        header: &mut Header,
+        path: P,
+        data: R,
+    ) -> io::Result<()> {
+        let mut encoded_header = Vec::new();
+        prepare_header_path(&mut encoded_header, header, path.as_ref())?;
+        header.set_cksum();
+        encoded_header.extend_from_slice(header.as_bytes());
+        self.append_stream_data(encoded_header, data);
+        Ok(())
+    }
+
+    /// Adds a new link (symbolic or hard) entry to this archive with the
+    /// specified path and target.
+    ///
+    /// See `Streamer::append_link` for details.
+    pub fn append_link<P: AsRef<Path>, T: AsRef<Path>>(
+        &mut self,
+        header: &mut Header,
+        path: P,
+        target: T,
+    ) -> io::Result<()> {
+        let mut encoded_header = Vec::new();
+        prepare_header_path(&mut encoded_header, header, path.as_ref())?;
+        prepare_header_link(&mut encoded_header, header, target.as_ref())?;
+        header.set_cksum();
+        encoded_header.extend_from_slice(header.as_bytes());
+        self.parts.push_back(Part::Bytes(encoded_header, 0));
+        Ok(())
+    }
+
+    /// Adds a file on the local filesystem to this archive.
+    ///
+    /// The path name for the file inside of this archive will be the same as
+    /// `path`, and it is required that the path is a relative path.
+    pub async fn append_path<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        self.append_stream_file(path.as_ref(), None).await
+    }
+
+    /// Adds a file on the local filesystem to this archive under another name.
+    ///
+    /// The path name for the file inside of this archive will be `name`, which
+    /// is required to be a relative path.
+    ///
+    /// Note if the `path` is a directory. This will just add an entry to the
+    /// archive, rather than contents of the directory.
+    pub async fn append_path_with_name<P: AsRef<Path>, N: AsRef<Path>>(
+        &mut self,
+        path: P,
+        name: N,
+    ) -> io::Result<()> {
+        self.append_stream_file(path.as_ref(), Some(name.as_ref()))
+            .await
+    }
+
+    /// Adds a file to this archive with the given path as the name of the file
+    /// in the archive.
+    ///
+    /// This will use the metadata of `file` to populate a `Header`, and it will
+    /// then append the file to the archive with the name `path`.
+    pub async fn append_file<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        file: &mut tokio::fs::File,
+    ) -> io::Result<()> {
+        let stat = file.metadata().await?;
+        let mut encoded_header = Vec::new();
+        let header = prepare_fs_header(&mut encoded_header, path.as_ref(), &stat, self.mode, None)?;
+        encoded_header.extend_from_slice(header.as_bytes());
+        self.append_stream_data(encoded_header, file.try_clone().await?);
+        Ok(())
+    }
+
+    /// Adds a directory to this archive with the given path as the name of the
+    /// directory in the archive.
+    ///
+    /// Note this will not add the contents of the directory to the archive.
+    /// See `append_dir_all` for recusively adding the contents of the directory.
+    pub async fn append_dir<P, Q>(&mut self, path: P, src_path: Q) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        self.append_stream_file(src_path.as_ref(), Some(path.as_ref()))
+            .await
+    }
+
+    /// Adds a directory and all of its contents (recursively) to this archive
+    /// with the given path as the name of the directory in the archive.
+    pub async fn append_dir_all<P, Q>(&mut self, path: P, src_path: Q) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        let (path, src_path) = (path.as_ref(), src_path.as_ref());
+        let mut stack = vec![(src_path.to_path_buf(), true, false)];
+        while let Some((src, is_dir, is_symlink)) = stack.pop() {
+            let dest = path.join(src.strip_prefix(src_path).unwrap());
+            // In case of a symlink pointing to a directory, is_dir is false, but src.is_dir() will return true
+            if is_dir || (is_symlink && self.follow && src.is_dir()) {
+                let mut entries = tokio::fs::read_dir(&src).await?;
+                while let Some(entry) = entries.next_entry().await? {
+                    let file_type = entry.file_type().await?;
+                    stack.push((entry.path(), file_type.is_dir(), file_type.is_symlink()));
+                }
+                if dest != Path::new("") {
+                    self.append_dir(&dest, &src).await?;
+                }
+            } else {
+                self.append_stream_file(&src, Some(&dest)).await?;
+            }
+        }
+        Ok(())
+    }
+
+    fn append_stream_data<R: AsyncRead + Send + 'static>(&mut self, header: Vec<u8>, data: R) {
+        self.parts.push_back(Part::Bytes(header, 0));
+        self.parts.push_back(Part::Data {
+            data: Box::pin(data),
+            read: 0,
+        });
+    }
+
+    async fn append_stream_file(&mut self, path: &Path, name: Option<&Path>) -> io::Result<()> {
+        let stat = metadata(path, self.follow).await?;
+        let ar_name = name.unwrap_or(path);
+        let mut encoded_header = Vec::new();
+        if stat.is_file() {
+            let header = prepare_fs_header(&mut encoded_header, ar_name, &stat, self.mode, None)?;
+            encoded_header.extend_from_slice(header.as_bytes());
+            let size = header.entry_size()?;
+            self.parts.push_back(Part::Bytes(encoded_header, 0));
+            self.parts.push_back(Part::File {
+                path: path.to_path_buf(),
+                remaining: size,
+                state: FileState::Closed,
+            });
+            let padding = (block_size(size)? - size) as usize;
+            self.parts.push_back(Part::Bytes(vec![0; padding], 0));
+            return Ok(());
+        }
+
+        if stat.is_dir() {
+            let header = prepare_fs_header(&mut encoded_header, ar_name, &stat, self.mode, None)?;
+            encoded_header.extend_from_slice(header.as_bytes());
+        } else if stat.file_type().is_symlink() {
+            let link_name = tokio::fs::read_link(path).await?;
+            let link_name = Some(link_name.as_path());
+            let header =
+                prepare_fs_header(&mut encoded_header, ar_name, &stat, self.mode, link_name)?;
+            encoded_header.extend_from_slice(header.as_bytes());
+        } else {
+            #[cfg(unix)]
+            crate::builder::append_special(&mut encoded_header, None, ar_name, &stat, self.mode)?;
+            #[cfg(not(unix))]
+            return Err(other(&format!("{} has unknown file type", path.display())));
+        }
+        self.parts.push_back(Part::Bytes(encoded_header, 0));
+        Ok(())
+    }
+}
+
+impl AsyncRead for AsyncStreamer {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        while buf.remaining() > 0 {
+            let part = match this.parts.front_mut() {
+                Some(part) => part,
+                None => {
+                    let n = cmp::min(this.finish_bytes_remaining, buf.remaining());
+                    buf.put_slice(&[0; 1024][..n]);
+                    this.finish_bytes_remaining -= n;
+                    break;
+                }
+            };
+            match part {
+                Part::Bytes(bytes, pos) => {
+                    if *pos == bytes.len() {
+                        this.parts.pop_front();
+                        continue;
+                    }
+                    let n = cmp::min(bytes.len() - *pos, buf.remaining());
+                    buf.put_slice(&bytes[*pos..*pos + n]);
+                    *pos += n;
+                    break;
+                }
+                Part::Data { data, read } => {
+                    let filled = buf.filled().len();
+                    match data.as_mut().poll_read(cx, buf) {
+                        Poll::Ready(Ok(())) => {}
+                        other => return other,
+                    }
+                    let n = buf.filled().len() - filled;
+                    if n > 0 {
+                        *read += n as u64;
+                        break;
+                    }
+                    // Pad with zeros if necessary.
+                    let padding = (512 - *read % 512) % 512;
+                    this.parts.pop_front();
+                    this.parts
+                        .push_front(Part::Bytes(vec![0; padding as usize], 0));
+                }
+                Part::File {
+                    path,
+                    remaining,
+                    state,
+                } => {
+                    if *remaining == 0 {
+                        this.parts.pop_front();
+                        continue;
+                    }
+                    let file = match state {
+                        FileState::Open(file) => file,
+                        FileState::Opening(open) => match open.as_mut().poll(cx) {
+                            Poll::Ready(Ok(file)) => {
+                                *state = FileState::Open(file);
+                                continue;
+                            }
+                            Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                            Poll::Pending => return Poll::Pending,
+                        },
+                        FileState::Closed => {
+                            *state =
+                                FileState::Opening(Box::pin(tokio::fs::File::open(path.clone())));
+                            continue;
+                        }
+                    };
+                    let n = cmp::min(*remaining, buf.remaining() as u64) as usize;
+                    let mut data = ReadBuf::new(buf.initialize_unfilled_to(n));
+                    match Pin::new(file).poll_read(cx, &mut data) {
+                        Poll::Ready(Ok(())) => {}
+                        other => return other,
+                    }
+                    let n = data.filled().len();
+                    if n == 0 {
+                        return Poll::Ready(Err(other(&format!(
+                            "{} changed size while being archived",
+                            path.display()
+                        ))));
+                    }
+                    buf.advance(n);
+                    *remaining -= n as u64;
+                    break;
+                }
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
@@ -0,0 +1,300 @@
+//! Creating files relative to directory file descriptors, so that the kernel
+//! rather than path checks keeps an unpacked archive inside its destination.
+
+use std::ffi::{CString, OsStr};
+use std::fs;
+use std::io;
+use std::os::unix::prelude::*;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use filetime::FileTime;
+
+/// Whether `openat2` may be available, cleared the first time the syscall
+/// fails with `ENOSYS`. Other errors only fail the path they occur for.
+static HAS_OPENAT2: AtomicBool = AtomicBool::new(true);
+
+/// An open directory which files are created in.
+pub(crate) struct Dir {
+    file: fs::File,
+}
+
+impl Dir {
+    /// Opens the destination directory of an unpack, following symlinks.
+    pub(crate) fn open(path: &Path) -> io::Result<Dir> {
+        let path = cstr(path.as_os_str())?;
+        let flags = libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC;
+        let fd = cvt(unsafe { libc::open(path.as_ptr(), flags) })?;
+        Ok(Dir::from_fd(fd))
+    }
+
+    /// Returns the open directory.
+    pub(crate) fn file(&self) -> &fs::File {
+        &self.file
+    }
+
+    /// Opens the directory reached by following `components` from this one,
+    /// creating missing directories if `create` is set.
+    ///
+    /// With `openat2` symbolic links are followed as long as they resolve
+    /// beneath this directory. Without it symbolic links aren't followed at
+    /// all.
+    pub(crate) fn open_beneath(&self, components: &[&OsStr], create: bool) -> io::Result<Dir> {
+        if HAS_OPENAT2.load(Ordering::Relaxed) {
+            match self.open_beneath_openat2(components, create) {
+                Err(_) if !HAS_OPENAT2.load(Ordering::Relaxed) => {}
+                ret => return ret,
+            }
+        }
+
+        let mut dir = self.try_clone()?;
+        for component in components {
+            dir = match dir.open_dir(component) {
+                Err(ref e) if create && e.kind() == io::ErrorKind::NotFound => {
+                    dir.mkdir(component)?;
+                    dir.open_dir(component)?
+                }
+                ret => ret?,
+            };
+        }
+        Ok(dir)
+    }
+
+    fn open_beneath_openat2(&self, components: &[&OsStr], create: bool) -> io::Result<Dir> {
+        let mut dir = self.try_clone()?;
+        let mut path = Vec::new();
+        for component in components {
+            if !path.is_empty() {
+                path.push(b'/');
+            }
+            path.extend_from_slice(component.as_bytes());
+            // Resolve from the destination every time so that symbolic links
+            // pointing to other directories beneath it keep working.
+            dir = match self.openat2(&path) {
+                Err(ref e) if create && e.kind() == io::ErrorKind::NotFound => {
+                    dir.mkdir(component)?;
+                    self.openat2(&path)?
+                }
+                ret => ret?,
+            };
+        }
+        Ok(dir)
+    }
+
+    fn openat2(&self, path: &[u8]) -> io::Result<Dir> {
+        let path = cstr(OsStr::from_bytes(path))?;
+        let mut how: libc::open_how = unsafe { std::mem::zeroed() };
+        how.flags = (libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC) as u64;
+        how.resolve = libc::RESOLVE_BENEATH | libc::RESOLVE_NO_MAGICLINKS;
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_openat2,
+                self.file.as_raw_fd(),
+                path.as_ptr(),
+                &how as *const libc::open_how,
+                std::mem::size_of::<libc::open_how>(),
+            )
+        };
+        match cvt(fd as libc::c_int) {
+            Ok(fd) => Ok(Dir::from_fd(fd)),
+            Err(e) => {
+                if e.raw_os_error() == Some(libc::ENOSYS) {
+                    HAS_OPENAT2.store(false, Ordering::Relaxed);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Opens the directory `name` in this one, without following symbolic
+    /// links.
+    pub(crate) fn open_dir(&self, name: &OsStr) -> io::Result<Dir> {
+        let name = cstr(name)?;
+        let flags = libc::O_RDONLY | libc::O_DIRECTORY | libc::O_NOFOLLOW | libc::O_CLOEXEC;
+        let fd = cvt(unsafe { libc::openat(self.file.as_raw_fd(), name.as_ptr(), flags) })?;
+        Ok(Dir::from_fd(fd))
+    }
+
+    /// Creates the directory `name` in this one, unless a directory by that
+    /// name exists already.
+    pub(crate) fn mkdir(&self, name: &OsStr) -> io::Result<()> {
+        let cname = cstr(name)?;
+        match cvt(unsafe { libc::mkdirat(self.file.as_raw_fd(), cname.as_ptr(), 0o777) }) {
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                self.open_dir(name).map(|_| ())
+            }
+            ret => ret.map(|_| ()),
+        }
+    }
+
+    /// Creates the file `name` in this one, replacing an existing file if
+    /// `overwrite` is set. Symbolic links are never followed.
+    pub(crate) fn create_file(&self, name: &OsStr, overwrite: bool) -> io::Result<fs::File> {
+        let name = cstr(name)?;
+        let open = || {
+            let flags =
+                libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL | libc::O_NOFOLLOW | libc::O_CLOEXEC;
+            let fd = unsafe {
+                libc::openat(
+                    self.file.as_raw_fd(),
+                    name.as_ptr(),
+                    flags,
+                    0o666 as libc::c_uint,
+                )
+            };
+            cvt(fd).map(|fd| unsafe { fs::File::from_raw_fd(fd) })
+        };
+        match open() {
+            Err(ref e) if overwrite && e.kind() == io::ErrorKind::AlreadyExists => {
+                self.unlink(&name)?;
+                open()
+            }
+            ret => ret,
+        }
+    }
+
+    /// Creates the symbolic link `name` pointing to `target` in this one,
+    /// replacing an existing file if `overwrite` is set.
+    pub(crate) fn symlink(&self, target: &Path, name: &OsStr, overwrite: bool) -> io::Result<()> {
+        let target = cstr(target.as_os_str())?;
+        let name = cstr(name)?;
+        let link = || {
+            cvt(unsafe { libc::symlinkat(target.as_ptr(), self.file.as_raw_fd(), name.as_ptr()) })
+        };
+        match link() {
+            Err(ref e) if overwrite && e.kind() == io::ErrorKind::AlreadyExists => {
+                self.unlink(&name)?;
+                link().map(|_| ())
+            }
+            ret => ret.map(|_| ()),
+        }
+    }
+
+    /// Creates the hard link `name` in this one to the file `src_name` in
+    /// `src_dir`, replacing an existing file if `overwrite` is set. If
+    /// `src_name` is a symbolic link, the link itself is linked.
+    pub(crate) fn hard_link(
+        &self,
+        src_dir: &Dir,
+        src_name: &OsStr,
+        name: &OsStr,
+        overwrite: bool,
+    ) -> io::Result<()> {
+        let src_name = cstr(src_name)?;
+        let name = cstr(name)?;
+        let link = || {
+            cvt(unsafe {
+                libc::linkat(
+                    src_dir.file.as_raw_fd(),
+                    src_name.as_ptr(),
+                    self.file.as_raw_fd(),
+                    name.as_ptr(),
+                    0,
+                )
+            })
+        };
+        match link() {
+            Err(ref e) if overwrite && e.kind() == io::ErrorKind::AlreadyExists => {
+                self.unlink(&name)?;
+                link().map(|_| ())
+            }
+            ret => ret.map(|_| ()),
+        }
+    }
+
+    /// Sets the access and modification times of the symbolic link `name` in
+    /// this one.
+    pub(crate) fn set_symlink_times(
+        &self,
+        name: &OsStr,
+        atime: FileTime,
+        mtime: FileTime,
+    ) -> io::Result<()> {
+        let name = cstr(name)?;
+        let times = [timespec(atime), timespec(mtime)];
+        cvt(unsafe {
+            libc::utimensat(
+                self.file.as_raw_fd(),
+                name.as_ptr(),
+                times.as_ptr(),
+                libc::AT_SYMLINK_NOFOLLOW,
+            )
+        })
+        .map(|_| ())
+    }
+
+    /// Returns the modification time of the file `name` in this one, without
+    /// following symbolic links, or `None` if it doesn't exist.
+    pub(crate) fn mtime(&self, name: &OsStr) -> Option<FileTime> {
+        let name = cstr(name).ok()?;
+        let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+        let ret = unsafe {
+            libc::fstatat(
+                self.file.as_raw_fd(),
+                name.as_ptr(),
+                &mut stat,
+                libc::AT_SYMLINK_NOFOLLOW,
+            )
+        };
+        cvt(ret).ok()?;
+        Some(FileTime::from_unix_time(
+            stat.st_mtime as i64,
+            stat.st_mtime_nsec as u32,
+        ))
+    }
+
+    /// Renames the file `from` in this directory to `to`, replacing `to` if
+    /// it exists.
+    pub(crate) fn rename(&self, from: &OsStr, to: &OsStr) -> io::Result<()> {
+        let from = cstr(from)?;
+        let to = cstr(to)?;
+        let fd = self.file.as_raw_fd();
+        cvt(unsafe { libc::renameat(fd, from.as_ptr(), fd, to.as_ptr()) }).map(|_| ())
+    }
+
+    /// Removes the file `name` in this one, if it exists.
+    pub(crate) fn remove_file(&self, name: &OsStr) -> io::Result<()> {
+        self.unlink(&cstr(name)?)
+    }
+
+    fn unlink(&self, name: &CString) -> io::Result<()> {
+        match cvt(unsafe { libc::unlinkat(self.file.as_raw_fd(), name.as_ptr(), 0) }) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            ret => ret.map(|_| ()),
+        }
+    }
+
+    fn try_clone(&self) -> io::Result<Dir> {
+        self.file.try_clone().map(|file| Dir { file })
+    }
+
+    fn from_fd(fd: libc::c_int) -> Dir {
+        Dir {
+            file: unsafe { fs::File::from_raw_fd(fd) },
+        }
+    }
+}
+
+fn cstr(s: &OsStr) -> io::Result<CString> {
+    CString::new(s.as_bytes()).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::Other,
+            format!("path contains null character: {:?}", e),
+        )
+    })
+}
+
+fn cvt(ret: libc::c_int) -> io::Result<libc::c_int> {
+    if ret == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
+
+fn timespec(time: FileTime) -> libc::timespec {
+    libc::timespec {
+        tv_sec: time.unix_seconds() as libc::time_t,
+        tv_nsec: time.nanoseconds() as libc::c_long,
+    }
+}
@@ -1,11 +1,20 @@
+use std::collections::HashMap;
 use std::fs;
 use std::io;
 use std::io::prelude::*;
+use std::io::{Seek, SeekFrom};
 use std::path::Path;
 use std::str;
 
+#[cfg(all(target_os = "linux", feature = "xattr"))]
+use crate::acl;
+use crate::entry::EntryFields;
 use crate::header::{path2bytes, HeaderMode, prepare_header};
-use crate::{other, EntryType, Header};
+#[cfg(all(target_os = "linux", feature = "xattr"))]
+use crate::pax::pax_record;
+use crate::squash::{self, ChunkReader};
+use crate::stat_override::{self, StatOverride};
+use crate::{other, Archive, EntryType, Header, PortabilityChecker, PortabilityIssue};
 
 /// A structure for building archives
 ///
@@ -14,7 +23,9 @@ use crate::{other, EntryType, Header};
 pub struct Builder<W: Write> {
     mode: HeaderMode,
     follow: bool,
+    capture: Capture,
     finished: bool,
+    portability: Option<PortabilityChecker>,
     obj: Option<W>,
 }
 
@@ -26,7 +37,9 @@ impl<W: Write> Builder<W> {
         Builder {
             mode: HeaderMode::Complete,
             follow: true,
+            capture: Capture::default(),
             finished: false,
+            portability: None,
             obj: Some(obj),
         }
     }
@@ -44,6 +57,52 @@ impl<W: Write> Builder<W> {
         self.follow = follow;
     }
 
+    /// Archives the POSIX ACLs of files and directories appended from the
+    /// local filesystem in `SCHILY.acl.access` and `SCHILY.acl.default` pax
+    /// records, like GNU tar's `--acls` does. Defaults to false.
+    ///
+    /// ACLs are only read on Linux with the `xattr` feature enabled, they're
+    /// restored by `Archive::set_unpack_acls`.
+    pub fn capture_acls(&mut self, capture: bool) {
+        self.capture.acls = capture;
+    }
+
+    /// Reads the ownerships of files and directories appended from the local
+    /// filesystem from the extended attribute `name` if they have it, such as
+    /// `user.containers.override_stat`, rather than from their metadata.
+    ///
+    /// This archives files unpacked with `Archive::set_ownership_xattr` with
+    /// the uid, gid and mode recorded there, and turns the empty regular files
+    /// standing in for devices and FIFOs back into such entries.
+    ///
+    /// This is currently only implemented on Unix using xattr support.
+    pub fn ownership_xattr<S: Into<String>>(&mut self, name: S) {
+        self.capture.ownership_xattr = Some(name.into());
+    }
+
+    /// Checks the path and type of every entry appended to this archive for
+    /// problems extracting it on Windows or macOS, such as names colliding
+    /// case-insensitively or reserved names. Defaults to false.
+    ///
+    /// The problems found are returned by `portability_issues`, they don't
+    /// prevent entries from being appended.
+    pub fn check_portability(&mut self, check: bool) {
+        self.portability = if check {
+            Some(PortabilityChecker::new())
+        } else {
+            None
+        };
+    }
+
+    /// Returns the portability problems found in the entries appended so
+    /// far, if `check_portability` is enabled.
+    pub fn portability_issues(&self) -> &[PortabilityIssue] {
+        match self.portability {
+            Some(ref checker) => checker.issues(),
+            None => &[],
+        }
+    }
+
     /// Gets shared reference to the underlying object.
     pub fn get_ref(&self) -> &W {
         self.obj.as_ref().unwrap()
@@ -108,6 +167,9 @@ impl<W: Write> Builder<W> {
     /// let data = ar.into_inner().unwrap();
     /// ```
     pub fn append<R: Read>(&mut self, header: &Header, mut data: R) -> io::Result<()> {
+        if let Some(ref mut checker) = self.portability {
+            checker.check(&header.path_bytes(), header.entry_type());
+        }
         append(self.get_mut(), header, &mut data)
     }
 
@@ -155,11 +217,13 @@ impl<W: Write> Builder<W> {
         &mut self,
         header: &mut Header,
         path: P,
-        data: R,
+        mut data: R,
     ) -> io::Result<()> {
-        prepare_header_path(self.get_mut(), header, path.as_ref())?;
+        let (dst, checker) = self.parts();
+        check_path(checker, path.as_ref(), header.entry_type());
+        prepare_header_path(dst, header, path.as_ref())?;
         header.set_cksum();
-        self.append(&header, data)
+        append(dst, header, &mut data)
     }
 
     /// Adds a new link (symbolic or hard) entry to this archive with the specified path and target.
@@ -204,10 +268,12 @@ impl<W: Write> Builder<W> {
     }
 
     fn _append_link(&mut self, header: &mut Header, path: &Path, target: &Path) -> io::Result<()> {
-        prepare_header_path(self.get_mut(), header, path)?;
-        prepare_header_link(self.get_mut(), header, target)?;
+        let (dst, checker) = self.parts();
+        check_path(checker, path, header.entry_type());
+        prepare_header_path(dst, header, path)?;
+        prepare_header_link(dst, header, target)?;
         header.set_cksum();
-        self.append(&header, std::io::empty())
+        append(dst, header, &mut io::empty())
     }
 
     /// Adds a file on the local filesystem to this archive.
@@ -237,7 +303,9 @@ impl<W: Write> Builder<W> {
     pub fn append_path<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
         let mode = self.mode.clone();
         let follow = self.follow;
-        append_path_with_name(self.get_mut(), path.as_ref(), None, mode, follow)
+        let capture = self.capture.clone();
+        let (dst, checker) = self.parts();
+        append_path_with_name(dst, checker, path.as_ref(), None, mode, follow, &capture)
     }
 
     /// Adds a file on the local filesystem to this archive under another name.
@@ -275,12 +343,16 @@ impl<W: Write> Builder<W> {
     ) -> io::Result<()> {
         let mode = self.mode.clone();
         let follow = self.follow;
+        let capture = self.capture.clone();
+        let (dst, checker) = self.parts();
         append_path_with_name(
-            self.get_mut(),
+            dst,
+            checker,
             path.as_ref(),
             Some(name.as_ref()),
             mode,
             follow,
+            &capture,
         )
     }
 
@@ -312,7 +384,9 @@ impl<W: Write> Builder<W> {
     /// ```
     pub fn append_file<P: AsRef<Path>>(&mut self, path: P, file: &mut fs::File) -> io::Result<()> {
         let mode = self.mode.clone();
-        append_file(self.get_mut(), path.as_ref(), file, mode)
+        let capture = self.capture.clone();
+        let (dst, checker) = self.parts();
+        append_file(dst, checker, path.as_ref(), file, mode, &capture)
     }
 
     /// Adds a directory to this archive with the given path as the name of the
@@ -349,7 +423,16 @@ impl<W: Write> Builder<W> {
         Q: AsRef<Path>,
     {
         let mode = self.mode.clone();
-        append_dir(self.get_mut(), path.as_ref(), src_path.as_ref(), mode)
+        let capture = self.capture.clone();
+        let (dst, checker) = self.parts();
+        append_dir(
+            dst,
+            checker,
+            path.as_ref(),
+            src_path.as_ref(),
+            mode,
+            &capture,
+        )
     }
 
     /// Adds a directory and all of its contents (recursively) to this archive
@@ -381,15 +464,67 @@ impl<W: Write> Builder<W> {
     {
         let mode = self.mode.clone();
         let follow = self.follow;
+        let capture = self.capture.clone();
+        let (dst, checker) = self.parts();
         append_dir_all(
-            self.get_mut(),
+            dst,
+            checker,
             path.as_ref(),
             src_path.as_ref(),
             mode,
             follow,
+            &capture,
         )
     }
 
+    /// Appends the final state of a stack of layered archives, such as the
+    /// layers of a container image, given from the lowest to the topmost.
+    ///
+    /// Every path is appended once, as found in the topmost layer holding it.
+    /// OCI whiteouts hide the paths of the layers below them and are left out
+    /// themselves, see `Whiteouts`. A hard link whose target is replaced or
+    /// removed by a layer above is appended as a copy of the target, so that
+    /// it keeps its contents. Sparse files are appended in full.
+    ///
+    /// The headers of all layers are read first, and the data of the entries
+    /// is then copied from the layers as it's appended rather than buffered,
+    /// which is why the layers need to be seekable.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use tar::{Archive, Builder};
+    ///
+    /// let layers = vec![
+    ///     Archive::new(File::open("base.tar").unwrap()),
+    ///     Archive::new(File::open("app.tar").unwrap()),
+    /// ];
+    /// let mut ar = Builder::new(File::create("rootfs.tar").unwrap());
+    /// ar.append_squashed(layers).unwrap();
+    /// ar.finish().unwrap();
+    /// ```
+    pub fn append_squashed<R: Read + Seek>(&mut self, layers: Vec<Archive<R>>) -> io::Result<()> {
+        let mut layers = layers;
+        let squashed = squash::squash(&mut layers)?;
+        let mut layers = layers
+            .into_iter()
+            .map(Archive::into_inner)
+            .collect::<Vec<_>>();
+        let (dst, mut checker) = self.parts();
+        for entry in squashed {
+            check_path(
+                checker.as_deref_mut(),
+                &entry.path,
+                entry.header.entry_type(),
+            );
+            dst.write_all(&entry.prefix)?;
+            let mut data = ChunkReader::new(&mut layers[entry.layer], entry.chunks);
+            append(dst, &entry.header, &mut data)?;
+        }
+        Ok(())
+    }
+
     /// Finish writing this archive, emitting the termination sections.
     ///
     /// This function should only be called when the archive has been written
@@ -404,6 +539,133 @@ impl<W: Write> Builder<W> {
         self.finished = true;
         self.get_mut().write_all(&[0; 1024])
     }
+
+    fn parts(&mut self) -> (&mut dyn Write, Option<&mut PortabilityChecker>) {
+        (self.obj.as_mut().unwrap(), self.portability.as_mut())
+    }
+}
+
+impl<W: Read + Write + Seek> Builder<W> {
+    /// Create a new archive builder which appends to the existing archive in
+    /// `obj`, like `tar -r` does.
+    ///
+    /// The headers of the archive are read from the start of `obj`, skipping
+    /// over the data, up to the end-of-archive marker. The new members are
+    /// then written over the marker and `finish` writes a new one after them.
+    /// Anything following the old marker, such as padding up to a record
+    /// size, is left in place behind the new one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the archive is damaged or ends in the middle of a
+    /// member, or for any I/O error.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::OpenOptions;
+    /// use tar::Builder;
+    ///
+    /// let file = OpenOptions::new()
+    ///     .read(true)
+    ///     .write(true)
+    ///     .open("foo.tar")
+    ///     .unwrap();
+    /// let mut ar = Builder::new_append(file).unwrap();
+    /// ar.append_path("bar.txt").unwrap();
+    /// ar.finish().unwrap();
+    /// ```
+    pub fn new_append(mut obj: W) -> io::Result<Builder<W>> {
+        seek_to_end(&mut obj, |_| Ok(()))?;
+        Ok(Builder::new(obj))
+    }
+
+    /// Appends the files below `src_path` which are missing from the archive
+    /// or newer than their archived copy, with `path` as the name of
+    /// `src_path` in the archive, like `tar -u` does.
+    ///
+    /// The archive is read from the start of the underlying object first,
+    /// noting the mtime of each path; if a path is archived more than once
+    /// its last copy counts. The files are then compared against these using
+    /// the mtime they'd be archived with, so with `HeaderMode::Deterministic`
+    /// only missing files are appended. Whatever is appended goes over the
+    /// end-of-archive marker, as with `new_append`, and directories are
+    /// walked like `append_dir_all` does.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::OpenOptions;
+    /// use tar::Builder;
+    ///
+    /// let file = OpenOptions::new()
+    ///     .read(true)
+    ///     .write(true)
+    ///     .open("backup.tar")
+    ///     .unwrap();
+    /// let mut ar = Builder::new(file);
+    /// ar.update("docs", "/home/user/docs").unwrap();
+    /// ar.finish().unwrap();
+    /// ```
+    pub fn update<P, Q>(&mut self, path: P, src_path: Q) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        let mut archived = HashMap::new();
+        seek_to_end(self.get_mut(), |fields| {
+            archived.insert(squash::normalize(&fields.path()?), fields.mtime()?);
+            Ok(())
+        })?;
+
+        let mode = self.mode;
+        let follow = self.follow;
+        let capture = self.capture.clone();
+        let (dst, mut checker) = self.parts();
+        let (path, src_path) = (path.as_ref(), src_path.as_ref());
+        let mut stack = vec![src_path.to_path_buf()];
+        while let Some(src) = stack.pop() {
+            let stat = if follow {
+                fs::metadata(&src)?
+            } else {
+                fs::symlink_metadata(&src)?
+            };
+            if stat.is_dir() {
+                for entry in fs::read_dir(&src)? {
+                    stack.push(entry?.path());
+                }
+            }
+            let dest = path.join(src.strip_prefix(src_path).unwrap());
+            if dest == Path::new("") {
+                continue;
+            }
+            let mut header = Header::new_gnu();
+            header.set_metadata_in_mode(&stat, mode);
+            match archived.get(&squash::normalize(&dest)) {
+                Some(&mtime) if mtime >= header.mtime()? => continue,
+                _ => {}
+            }
+            let checker = checker.as_deref_mut();
+            append_path_with_name(dst, checker, &src, Some(&dest), mode, follow, &capture)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads the archive in `obj` from its start, calling `f` with each entry,
+/// and positions `obj` at the end-of-archive marker.
+fn seek_to_end<W, F>(obj: &mut W, f: F) -> io::Result<()>
+where
+    W: Read + Seek,
+    F: FnMut(&EntryFields) -> io::Result<()>,
+{
+    obj.seek(SeekFrom::Start(0))?;
+    let end = Archive::new(&mut *obj).end_of_entries(f)?;
+    if end > obj.seek(SeekFrom::End(0))? {
+        return Err(other("archive ends in the middle of a member"));
+    }
+    obj.seek(SeekFrom::Start(end))?;
+    Ok(())
 }
 
 fn append(mut dst: &mut dyn Write, header: &Header, mut data: &mut dyn Read) -> io::Result<()> {
@@ -422,10 +684,12 @@ fn append(mut dst: &mut dyn Write, header: &Header, mut data: &mut dyn Read) ->
 
 fn append_path_with_name(
     dst: &mut dyn Write,
+    checker: Option<&mut PortabilityChecker>,
     path: &Path,
     name: Option<&Path>,
     mode: HeaderMode,
     follow: bool,
+    capture: &Capture,
 ) -> io::Result<()> {
     let stat = if follow {
         fs::metadata(path).map_err(|err| {
@@ -444,13 +708,18 @@ fn append_path_with_name(
     };
     let ar_name = name.unwrap_or(path);
     if stat.is_file() {
-        append_fs(dst, ar_name, &stat, &mut fs::File::open(path)?, mode, None)
+        let mut file = fs::File::open(path)?;
+        let captured = capture.read(Some(&file), path, false);
+        append_fs_captured(dst, checker, ar_name, &stat, &mut file, mode, &captured)
     } else if stat.is_dir() {
-        append_fs(dst, ar_name, &stat, &mut io::empty(), mode, None)
+        let captured = capture.read(None, path, true);
+        let empty = &mut io::empty();
+        append_fs_captured(dst, checker, ar_name, &stat, empty, mode, &captured)
     } else if stat.file_type().is_symlink() {
         let link_name = fs::read_link(path)?;
         append_fs(
             dst,
+            checker,
             ar_name,
             &stat,
             &mut io::empty(),
@@ -460,7 +729,7 @@ fn append_path_with_name(
     } else {
         #[cfg(unix)]
         {
-            append_special(dst, path, &stat, mode)
+            append_special(dst, checker, path, &stat, mode)
         }
         #[cfg(not(unix))]
         {
@@ -470,8 +739,9 @@ fn append_path_with_name(
 }
 
 #[cfg(unix)]
-fn append_special(
+pub(crate) fn append_special(
     dst: &mut dyn Write,
+    checker: Option<&mut PortabilityChecker>,
     path: &Path,
     stat: &fs::Metadata,
     mode: HeaderMode,
@@ -496,6 +766,7 @@ fn append_special(
         return Err(other(&format!("{} has unknown file type", path.display())));
     }
 
+    check_path(checker, path, entry_type);
     let mut header = Header::new_gnu();
     header.set_metadata_in_mode(stat, mode);
     prepare_header_path(dst, &mut header, path)?;
@@ -515,25 +786,36 @@ fn append_special(
 
 fn append_file(
     dst: &mut dyn Write,
+    checker: Option<&mut PortabilityChecker>,
     path: &Path,
     file: &mut fs::File,
     mode: HeaderMode,
+    capture: &Capture,
 ) -> io::Result<()> {
     let stat = file.metadata()?;
-    append_fs(dst, path, &stat, file, mode, None)
+    let captured = capture.read(Some(file), path, false);
+    append_fs_captured(dst, checker, path, &stat, file, mode, &captured)
 }
 
 fn append_dir(
     dst: &mut dyn Write,
+    checker: Option<&mut PortabilityChecker>,
     path: &Path,
     src_path: &Path,
     mode: HeaderMode,
+    capture: &Capture,
 ) -> io::Result<()> {
     let stat = fs::metadata(src_path)?;
-    append_fs(dst, path, &stat, &mut io::empty(), mode, None)
+    let captured = capture.read(None, src_path, true);
+    let empty = &mut io::empty();
+    append_fs_captured(dst, checker, path, &stat, empty, mode, &captured)
 }
 
-fn prepare_header_path(dst: &mut dyn Write, header: &mut Header, path: &Path) -> io::Result<()> {
+pub(crate) fn prepare_header_path(
+    dst: &mut dyn Write,
+    header: &mut Header,
+    path: &Path,
+) -> io::Result<()> {
     // Try to encode the path directly in the header, but if it ends up not
     // working (probably because it's too long) then try to use the GNU-specific
     // long name extension by emitting an entry which indicates that it's the
@@ -565,7 +847,7 @@ fn prepare_header_path(dst: &mut dyn Write, header: &mut Header, path: &Path) ->
     Ok(())
 }
 
-fn prepare_header_link(
+pub(crate) fn prepare_header_link(
     dst: &mut dyn Write,
     header: &mut Header,
     link_name: &Path,
@@ -585,12 +867,147 @@ fn prepare_header_link(
 
 fn append_fs(
     dst: &mut dyn Write,
+    checker: Option<&mut PortabilityChecker>,
     path: &Path,
     meta: &fs::Metadata,
     read: &mut dyn Read,
     mode: HeaderMode,
     link_name: Option<&Path>,
 ) -> io::Result<()> {
+    let header = prepare_fs_header(dst, path, meta, mode, link_name)?;
+    check_path(checker, path, header.entry_type());
+    append(dst, &header, read)
+}
+
+/// What is read from files on the local filesystem besides their metadata,
+/// see `Builder::capture_acls` and `Builder::ownership_xattr`.
+#[derive(Clone, Default)]
+struct Capture {
+    acls: bool,
+    ownership_xattr: Option<String>,
+}
+
+/// What was captured from a file, see `Capture::read`.
+struct Captured {
+    pax: Vec<u8>,
+    stat: Option<StatOverride>,
+}
+
+impl Capture {
+    /// Captures the file at `path`, reading through `file` if it's open
+    /// already.
+    fn read(&self, file: Option<&fs::File>, path: &Path, is_dir: bool) -> Captured {
+        let stat = match self.ownership_xattr {
+            Some(ref name) => stat_override::read(file, path, name),
+            None => None,
+        };
+        Captured {
+            pax: acl_records(self.acls, file, path, is_dir),
+            stat,
+        }
+    }
+}
+
+/// Appends a pax extended header holding `records`, which apply to the entry
+/// appended next, unless there are no records.
+pub(crate) fn append_pax_records(dst: &mut dyn Write, records: &[u8]) -> io::Result<()> {
+    if records.is_empty() {
+        return Ok(());
+    }
+    let mut header = Header::new_ustar();
+    // `set_path` would normalize the leading `./` away.
+    let name = b"././@PaxHeader";
+    header.as_old_mut().name[..name.len()].copy_from_slice(name);
+    header.set_mode(0o644);
+    header.set_uid(0);
+    header.set_gid(0);
+    header.set_mtime(0);
+    header.set_size(records.len() as u64);
+    header.set_entry_type(EntryType::XHeader);
+    header.set_cksum();
+    append(dst, &header, &mut &records[..])
+}
+
+/// Returns the pax records holding the POSIX ACLs of the file at `path`, read
+/// through `file` if it's open already, or nothing if `acls` isn't set.
+///
+/// ACLs which can't be read, for example because the filesystem doesn't
+/// support them, are left out.
+#[cfg(all(target_os = "linux", feature = "xattr"))]
+fn acl_records(acls: bool, file: Option<&fs::File>, path: &Path, is_dir: bool) -> Vec<u8> {
+    use xattr::FileExt;
+
+    let mut records = Vec::new();
+    if !acls {
+        return records;
+    }
+    let mut names = vec![(acl::ACCESS_XATTR, "SCHILY.acl.access")];
+    if is_dir {
+        names.push((acl::DEFAULT_XATTR, "SCHILY.acl.default"));
+    }
+    for (name, key) in names {
+        let value = match file {
+            Some(file) => file.get_xattr(name),
+            None => xattr::get(path, name),
+        };
+        let value = match value {
+            Ok(Some(value)) => value,
+            _ => continue,
+        };
+        if name == acl::ACCESS_XATTR && acl::is_minimal(&value) {
+            continue;
+        }
+        if let Ok(text) = acl::xattr_to_text(&value) {
+            records.extend(pax_record(key, text.as_bytes()));
+        }
+    }
+    records
+}
+
+#[cfg(not(all(target_os = "linux", feature = "xattr")))]
+fn acl_records(_acls: bool, _file: Option<&fs::File>, _path: &Path, _is_dir: bool) -> Vec<u8> {
+    Vec::new()
+}
+
+/// Like `append_fs` for regular files and directories, also writing what was
+/// captured from them.
+fn append_fs_captured(
+    dst: &mut dyn Write,
+    checker: Option<&mut PortabilityChecker>,
+    path: &Path,
+    meta: &fs::Metadata,
+    read: &mut dyn Read,
+    mode: HeaderMode,
+    captured: &Captured,
+) -> io::Result<()> {
+    append_pax_records(dst, &captured.pax)?;
+    let mut header = prepare_fs_header(dst, path, meta, mode, None)?;
+    if let Some(ref stat) = captured.stat {
+        stat.apply(&mut header, mode)?;
+        header.set_cksum();
+    }
+    check_path(checker, path, header.entry_type());
+    append(dst, &header, read)
+}
+
+fn check_path(checker: Option<&mut PortabilityChecker>, path: &Path, entry_type: EntryType) {
+    if let Some(checker) = checker {
+        // Paths which can't be encoded fail to be appended anyway.
+        if let Ok(bytes) = path2bytes(path) {
+            checker.check(&bytes, entry_type);
+        }
+    }
+}
+
+/// Builds the header of a file on the local filesystem, writing any GNU long
+/// name entries it requires to `dst` first.
+pub(crate) fn prepare_fs_header(
+    dst: &mut dyn Write,
+    path: &Path,
+    meta: &fs::Metadata,
+    mode: HeaderMode,
+    link_name: Option<&Path>,
+) -> io::Result<Header> {
     let mut header = Header::new_gnu();
 
     prepare_header_path(dst, &mut header, path)?;
@@ -599,15 +1016,17 @@ fn append_fs(
         prepare_header_link(dst, &mut header, link_name)?;
     }
     header.set_cksum();
-    append(dst, &header, read)
+    Ok(header)
 }
 
 fn append_dir_all(
     dst: &mut dyn Write,
+    mut checker: Option<&mut PortabilityChecker>,
     path: &Path,
     src_path: &Path,
     mode: HeaderMode,
     follow: bool,
+    capture: &Capture,
 ) -> io::Result<()> {
     let mut stack = vec![(src_path.to_path_buf(), true, false)];
     while let Some((src, is_dir, is_symlink)) = stack.pop() {
@@ -620,22 +1039,33 @@ fn append_dir_all(
                 stack.push((entry.path(), file_type.is_dir(), file_type.is_symlink()));
             }
             if dest != Path::new("") {
-                append_dir(dst, &dest, &src, mode)?;
+                append_dir(dst, checker.as_deref_mut(), &dest, &src, mode, capture)?;
             }
         } else if !follow && is_symlink {
             let stat = fs::symlink_metadata(&src)?;
             let link_name = fs::read_link(&src)?;
-            append_fs(dst, &dest, &stat, &mut io::empty(), mode, Some(&link_name))?;
+            let link_name = Some(link_name.as_path());
+            let checker = checker.as_deref_mut();
+            append_fs(
+                dst,
+                checker,
+                &dest,
+                &stat,
+                &mut io::empty(),
+                mode,
+                link_name,
+            )?;
         } else {
             #[cfg(unix)]
             {
                 let stat = fs::metadata(&src)?;
                 if !stat.is_file() {
-                    append_special(dst, &dest, &stat, mode)?;
+                    append_special(dst, checker.as_deref_mut(), &dest, &stat, mode)?;
                     continue;
                 }
             }
-            append_file(dst, &dest, &mut fs::File::open(src)?, mode)?;
+            let mut file = fs::File::open(src)?;
+            append_file(dst, checker.as_deref_mut(), &dest, &mut file, mode, capture)?;
         }
     }
     Ok(())
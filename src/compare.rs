@@ -0,0 +1,201 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::EntryType;
+
+/// A difference between an entry of an archive and the file at its path, as
+/// returned by `Archive::compare`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::fs::File;
+/// use tar::Archive;
+///
+/// let mut ar = Archive::new(File::open("release.tar").unwrap());
+/// for difference in ar.compare("/srv/release", true).unwrap() {
+///     println!("{}", difference);
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Difference {
+    path: PathBuf,
+    kind: DifferenceKind,
+}
+
+/// How a file differs from the entry for its path, see `Difference`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DifferenceKind {
+    /// Nothing exists at the path of the entry.
+    Missing,
+    /// The path of the entry leads outside of the root through a symbolic
+    /// link, so unpacking would reject it.
+    Outside,
+    /// The file has another type than the entry. Nothing else is compared.
+    Type {
+        /// The type of the entry.
+        archive: EntryType,
+        /// The type of the file.
+        fs: fs::FileType,
+    },
+    /// The size of a regular file differs.
+    Size {
+        /// The size of the entry.
+        archive: u64,
+        /// The size of the file.
+        fs: u64,
+    },
+    /// The permission bits differ. Only compared on Unix.
+    Mode {
+        /// The permission bits of the entry.
+        archive: u32,
+        /// The permission bits of the file.
+        fs: u32,
+    },
+    /// The owning user differs. Only compared on Unix.
+    Uid {
+        /// The uid unpacking the entry would restore.
+        archive: u64,
+        /// The uid of the file.
+        fs: u64,
+    },
+    /// The owning group differs. Only compared on Unix.
+    Gid {
+        /// The gid unpacking the entry would restore.
+        archive: u64,
+        /// The gid of the file.
+        fs: u64,
+    },
+    /// The modification time of a regular file differs, in whole seconds
+    /// since the Unix epoch.
+    Mtime {
+        /// The modification time of the entry.
+        archive: u64,
+        /// The modification time of the file.
+        fs: i64,
+    },
+    /// The target of a symbolic link differs.
+    LinkTarget {
+        /// The target of the entry.
+        archive: PathBuf,
+        /// The target of the symbolic link.
+        fs: PathBuf,
+    },
+    /// The file isn't a hard link to the given file, which the entry links
+    /// to. Only compared on Unix.
+    NotLinked {
+        /// The file the entry links to.
+        target: PathBuf,
+    },
+    /// The device numbers of a character or block device differ, as major
+    /// and minor numbers. Only compared on Linux.
+    Device {
+        /// The device numbers of the entry.
+        archive: (u32, u32),
+        /// The device numbers of the file.
+        fs: (u32, u32),
+    },
+    /// The contents of a regular file differ, see `Archive::compare`.
+    Contents,
+}
+
+impl Difference {
+    pub(crate) fn new(path: PathBuf, kind: DifferenceKind) -> Difference {
+        Difference { path, kind }
+    }
+
+    /// Returns the path of the file, below the root compared against.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns how the file differs.
+    pub fn kind(&self) -> &DifferenceKind {
+        &self.kind
+    }
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.kind)
+    }
+}
+
+impl fmt::Display for DifferenceKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DifferenceKind::Missing => write!(f, "does not exist"),
+            DifferenceKind::Outside => write!(f, "leads outside of the root"),
+            DifferenceKind::Type { archive, fs } => {
+                write!(f, "type differs ({:?} in archive, {:?})", archive, fs)
+            }
+            DifferenceKind::Size { archive, fs } => {
+                write!(f, "size differs ({} in archive, {})", archive, fs)
+            }
+            DifferenceKind::Mode { archive, fs } => {
+                write!(f, "mode differs ({:o} in archive, {:o})", archive, fs)
+            }
+            DifferenceKind::Uid { archive, fs } => {
+                write!(f, "uid differs ({} in archive, {})", archive, fs)
+            }
+            DifferenceKind::Gid { archive, fs } => {
+                write!(f, "gid differs ({} in archive, {})", archive, fs)
+            }
+            DifferenceKind::Mtime { archive, fs } => {
+                write!(f, "mtime differs ({} in archive, {})", archive, fs)
+            }
+            DifferenceKind::LinkTarget {
+                ref archive,
+                ref fs,
+            } => write!(
+                f,
+                "symlink target differs ({} in archive, {})",
+                archive.display(),
+                fs.display()
+            ),
+            DifferenceKind::NotLinked { ref target } => {
+                write!(f, "is not a hard link to {}", target.display())
+            }
+            DifferenceKind::Device { archive, fs } => write!(
+                f,
+                "device differs ({},{} in archive, {},{})",
+                archive.0, archive.1, fs.0, fs.1
+            ),
+            DifferenceKind::Contents => write!(f, "contents differ"),
+        }
+    }
+}
+
+/// Returns whether `err` means nothing exists at a path, including when one
+/// of its parents has been replaced by something other than a directory.
+pub(crate) fn is_missing(err: &io::Error) -> bool {
+    #[cfg(unix)]
+    {
+        if err.raw_os_error() == Some(libc::ENOTDIR) {
+            return true;
+        }
+    }
+    err.kind() == io::ErrorKind::NotFound
+}
+
+/// Returns whether a file of type `file_type` is what unpacking an entry of
+/// type `entry_type` creates.
+pub(crate) fn matches_type(entry_type: EntryType, file_type: fs::FileType) -> bool {
+    #[cfg(unix)]
+    use std::os::unix::fs::FileTypeExt;
+
+    match entry_type {
+        EntryType::Directory => file_type.is_dir(),
+        EntryType::Symlink => file_type.is_symlink(),
+        #[cfg(unix)]
+        EntryType::Char => file_type.is_char_device(),
+        #[cfg(unix)]
+        EntryType::Block => file_type.is_block_device(),
+        #[cfg(unix)]
+        EntryType::Fifo => file_type.is_fifo(),
+        _ => file_type.is_file(),
+    }
+}
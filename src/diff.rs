@@ -0,0 +1,257 @@
+use std::borrow::Cow;
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use crate::entry::EntryFields;
+use crate::squash::normalize;
+use crate::{EntryType, PaxExtensions};
+
+/// A path which differs between two archives, as returned by
+/// `Archive::diff`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::fs::File;
+/// use tar::{Archive, ChangeKind};
+///
+/// let mut old = Archive::new(File::open("release-1.0.tar").unwrap());
+/// let mut new = Archive::new(File::open("release-1.1.tar").unwrap());
+/// for change in old.diff(&mut new).unwrap() {
+///     match change.kind() {
+///         ChangeKind::Added => println!("+ {}", change.path().display()),
+///         ChangeKind::Removed => println!("- {}", change.path().display()),
+///         _ => println!("M {} {:?}", change.path().display(), change.metadata()),
+///     }
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ArchiveChange {
+    path: PathBuf,
+    kind: ChangeKind,
+    metadata: Vec<MetadataChange>,
+}
+
+/// How a path differs between two archives, see `ArchiveChange`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ChangeKind {
+    /// The path is only in the second archive.
+    Added,
+    /// The path is only in the first archive.
+    Removed,
+    /// The contents differ: the entry type, the size, the target of a link
+    /// or the data itself. Metadata may have changed as well.
+    Content,
+    /// The contents are the same but some metadata differs.
+    Metadata,
+}
+
+/// A piece of metadata which differs between two entries for a path, see
+/// `ArchiveChange::metadata`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum MetadataChange {
+    /// The permission bits.
+    Mode,
+    /// The uid, gid, user name or group name.
+    Owner,
+    /// The modification time.
+    Mtime,
+    /// Extended attributes recorded in pax records.
+    Xattrs,
+    /// POSIX ACLs recorded in pax records.
+    Acls,
+}
+
+impl ArchiveChange {
+    fn new(path: PathBuf, kind: ChangeKind) -> ArchiveChange {
+        ArchiveChange {
+            path,
+            kind,
+            metadata: Vec::new(),
+        }
+    }
+
+    /// Returns the path, without leading `/` and `.` components.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns how the path differs.
+    pub fn kind(&self) -> ChangeKind {
+        self.kind
+    }
+
+    /// Returns the metadata which differs, in the order of `MetadataChange`.
+    /// This is empty for added and removed paths.
+    pub fn metadata(&self) -> &[MetadataChange] {
+        &self.metadata
+    }
+}
+
+/// What is compared of an entry, with its data reduced to a hash keyed by
+/// the `Diff` it is compared in, so colliding data can't be crafted ahead
+/// of time.
+pub(crate) struct Summary {
+    entry_type: EntryType,
+    size: u64,
+    link_name: Option<PathBuf>,
+    hash: u64,
+    mode: u32,
+    owner: (u64, u64, Option<Vec<u8>>, Option<Vec<u8>>),
+    mtime: u64,
+    xattrs: Vec<(Vec<u8>, Vec<u8>)>,
+    acls: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl Summary {
+    /// Summarizes `entry`, reading its data. Returns the normalized path
+    /// along with the summary, or `None` for entries which only describe
+    /// other entries. The data is hashed with a hasher built from `keys`.
+    pub(crate) fn read(
+        entry: &mut EntryFields,
+        keys: &RandomState,
+    ) -> io::Result<Option<(PathBuf, Summary)>> {
+        let kind = entry.header.entry_type();
+        if kind.is_pax_global_extensions()
+            || kind.is_pax_local_extensions()
+            || kind.is_gnu_longname()
+            || kind.is_gnu_longlink()
+            || kind.is_gnu_volume_label()
+        {
+            return Ok(None);
+        }
+        let path = normalize(&entry.path()?);
+
+        let mut xattrs = Vec::new();
+        let mut acls = Vec::new();
+        if let Some(ref records) = entry.pax_extensions {
+            for record in PaxExtensions::new(records) {
+                let record = record?;
+                let key = record.key_bytes();
+                if key.starts_with(b"SCHILY.xattr.") || key.starts_with(b"LIBARCHIVE.xattr.") {
+                    xattrs.push((key.to_vec(), record.value_bytes().to_vec()));
+                } else if key.starts_with(b"SCHILY.acl.") {
+                    acls.push((key.to_vec(), record.value_bytes().to_vec()));
+                }
+            }
+        }
+        xattrs.sort();
+        acls.sort();
+
+        let mut summary = Summary {
+            entry_type: kind,
+            size: entry.size,
+            link_name: entry.link_name()?.map(Cow::into_owned),
+            hash: 0,
+            mode: entry.header.mode()? & 0o7777,
+            owner: (
+                entry.uid()?,
+                entry.gid()?,
+                entry.username_bytes().map(<[u8]>::to_vec),
+                entry.groupname_bytes().map(<[u8]>::to_vec),
+            ),
+            mtime: entry.mtime()?,
+            xattrs,
+            acls,
+        };
+        let mut hasher = keys.build_hasher();
+        let mut buf = [0; 8192];
+        loop {
+            let n = entry.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            hasher.write(&buf[..n]);
+        }
+        summary.hash = hasher.finish();
+        Ok(Some((path, summary)))
+    }
+}
+
+/// Collects the changes between the entries of the first archive, given to
+/// `insert`, and those of the second one, given to `compare`. Summaries of
+/// both archives must be read with the same `keys`.
+#[derive(Default)]
+pub(crate) struct Diff {
+    keys: RandomState,
+    old: HashMap<PathBuf, Summary>,
+    seen: HashSet<PathBuf>,
+    changes: HashMap<PathBuf, ArchiveChange>,
+}
+
+impl Diff {
+    /// Returns the randomly chosen keys to hash the data of entries with.
+    pub(crate) fn keys(&self) -> &RandomState {
+        &self.keys
+    }
+
+    /// Adds an entry of the first archive. A later entry for the same path
+    /// replaces an earlier one, like it does when unpacking.
+    pub(crate) fn insert(&mut self, path: PathBuf, summary: Summary) {
+        self.old.insert(path, summary);
+    }
+
+    /// Compares an entry of the second archive against the first one,
+    /// replacing what an earlier entry for the same path reported.
+    pub(crate) fn compare(&mut self, path: PathBuf, new: Summary) {
+        self.seen.insert(path.clone());
+        self.changes.remove(&path);
+        let old = match self.old.get(&path) {
+            Some(old) => old,
+            None => {
+                self.changes
+                    .insert(path.clone(), ArchiveChange::new(path, ChangeKind::Added));
+                return;
+            }
+        };
+
+        let mut metadata = Vec::new();
+        if old.mode != new.mode {
+            metadata.push(MetadataChange::Mode);
+        }
+        if old.owner != new.owner {
+            metadata.push(MetadataChange::Owner);
+        }
+        if old.mtime != new.mtime {
+            metadata.push(MetadataChange::Mtime);
+        }
+        if old.xattrs != new.xattrs {
+            metadata.push(MetadataChange::Xattrs);
+        }
+        if old.acls != new.acls {
+            metadata.push(MetadataChange::Acls);
+        }
+        let content = old.entry_type != new.entry_type
+            || old.size != new.size
+            || old.link_name != new.link_name
+            || old.hash != new.hash;
+        let kind = if content {
+            ChangeKind::Content
+        } else if !metadata.is_empty() {
+            ChangeKind::Metadata
+        } else {
+            return;
+        };
+        let mut change = ArchiveChange::new(path.clone(), kind);
+        change.metadata = metadata;
+        self.changes.insert(path, change);
+    }
+
+    /// Returns the changes sorted by path, reporting the paths of the first
+    /// archive which aren't in the second one as removed.
+    pub(crate) fn finish(self) -> Vec<ArchiveChange> {
+        let mut changes = self.changes.into_values().collect::<Vec<_>>();
+        for path in self.old.into_keys() {
+            if !self.seen.contains(&path) {
+                changes.push(ArchiveChange::new(path, ChangeKind::Removed));
+            }
+        }
+        changes.sort_by(|a, b| a.path.cmp(&b.path));
+        changes
+    }
+}
@@ -1,19 +1,38 @@
 use std::borrow::Cow;
 use std::cmp;
+use std::convert::TryFrom;
+use std::ffi::{OsStr, OsString};
 use std::fs;
 use std::fs::OpenOptions;
 use std::io::prelude::*;
 use std::io::{self, Error, ErrorKind, SeekFrom};
 use std::marker;
 use std::path::{Component, Path, PathBuf};
+use std::str;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use filetime::{self, FileTime};
 
+#[cfg(all(target_os = "linux", feature = "xattr"))]
+use crate::acl;
 use crate::archive::ArchiveInner;
+#[cfg(target_os = "linux")]
+use crate::beneath::Dir;
+use crate::compare::{self, Difference, DifferenceKind};
 use crate::error::TarError;
 use crate::header::bytes2path;
+use crate::idmap::IdMap;
 use crate::other;
-use crate::{Archive, Header, PaxExtensions};
+use crate::overwrite::{backup_name, temp_name, OverwritePolicy};
+use crate::owner::{OwnerResolver, SystemOwnerResolver};
+use crate::pax::{parse_pax_num, parse_pax_time};
+use crate::plan::{PlannedAction, UnpackPlan};
+use crate::rewrite::PathRewrite;
+use crate::stat_override::StatOverride;
+use crate::target::UnpackTarget;
+use crate::whiteout::{self, Layer, Whiteout, Whiteouts};
+use crate::{Archive, EntryType, Header, PaxExtensions};
 
 /// A read-only view into an entry of an archive.
 ///
@@ -37,10 +56,18 @@ pub struct EntryFields<'a> {
     pub file_pos: u64,
     pub data: Vec<EntryIo<'a>>,
     pub unpack_xattrs: bool,
+    pub unpack_acls: bool,
     pub preserve_permissions: bool,
     pub preserve_ownerships: bool,
+    pub numeric_owner: bool,
+    pub owner_resolver: Option<Arc<dyn OwnerResolver>>,
+    pub uid_map: Option<Arc<IdMap>>,
+    pub gid_map: Option<Arc<IdMap>>,
+    pub ownership_xattr: Option<Arc<str>>,
     pub preserve_mtime: bool,
-    pub overwrite: bool,
+    pub overwrite: OverwritePolicy,
+    pub unpack_beneath: bool,
+    pub rewrite: Option<Arc<PathRewrite>>,
 }
 
 pub enum EntryIo<'a> {
@@ -138,6 +165,9 @@ impl<'a, R: Read> Entry<'a, R> {
     /// Returns access to the header of this entry in the archive.
     ///
     /// This provides access to the metadata for this entry in the archive.
+    /// Note that pax extensions may override some of the values listed in the
+    /// header, so prefer accessors like `Entry::mtime` or `Entry::uid` to
+    /// inspecting the header directly.
     pub fn header(&self) -> &Header {
         &self.fields.header
     }
@@ -150,6 +180,79 @@ impl<'a, R: Read> Entry<'a, R> {
         self.fields.size
     }
 
+    /// Returns the last modification time of this entry in Unix time format.
+    ///
+    /// If a pax `mtime` record describes this entry then that value, truncated
+    /// to whole seconds, is returned. Otherwise the value stored in the header
+    /// is returned.
+    pub fn mtime(&self) -> io::Result<u64> {
+        self.fields.mtime()
+    }
+
+    /// Returns the last access time of this entry in Unix time format, if any
+    /// is recorded.
+    ///
+    /// This is taken from a pax `atime` record if present, and otherwise from
+    /// the `atime` field of a GNU header. `Ok(None)` indicates that the archive
+    /// does not record an access time for this entry.
+    pub fn atime(&self) -> io::Result<Option<u64>> {
+        self.fields.atime()
+    }
+
+    /// Returns the user ID of the owner of this entry.
+    ///
+    /// A pax `uid` record takes precedence over the header, which allows ids
+    /// that don't fit in the header's numeric field.
+    pub fn uid(&self) -> io::Result<u64> {
+        self.fields.uid()
+    }
+
+    /// Returns the group ID of the owner of this entry.
+    ///
+    /// A pax `gid` record takes precedence over the header, which allows ids
+    /// that don't fit in the header's numeric field.
+    pub fn gid(&self) -> io::Result<u64> {
+        self.fields.gid()
+    }
+
+    /// Returns the user name of the owner of this entry.
+    ///
+    /// A return value of `Ok(Some(..))` indicates that the user name was
+    /// present and was valid utf-8, `Ok(None)` indicates that the user name is
+    /// not present, and `Err` indicates that the user name was present but was
+    /// not valid utf-8.
+    ///
+    /// A pax `uname` record takes precedence over the header.
+    pub fn username(&self) -> Result<Option<&str>, str::Utf8Error> {
+        self.fields.username()
+    }
+
+    /// Returns the user name of the owner of this entry in bytes, if present.
+    ///
+    /// A pax `uname` record takes precedence over the header.
+    pub fn username_bytes(&self) -> Option<&[u8]> {
+        self.fields.username_bytes()
+    }
+
+    /// Returns the group name of the owner of this entry.
+    ///
+    /// A return value of `Ok(Some(..))` indicates that the group name was
+    /// present and was valid utf-8, `Ok(None)` indicates that the group name is
+    /// not present, and `Err` indicates that the group name was present but was
+    /// not valid utf-8.
+    ///
+    /// A pax `gname` record takes precedence over the header.
+    pub fn groupname(&self) -> Result<Option<&str>, str::Utf8Error> {
+        self.fields.groupname()
+    }
+
+    /// Returns the group name of the owner of this entry in bytes, if present.
+    ///
+    /// A pax `gname` record takes precedence over the header.
+    pub fn groupname_bytes(&self) -> Option<&[u8]> {
+        self.fields.groupname_bytes()
+    }
+
     /// Returns the starting position, in bytes, of the header of this entry in
     /// the archive.
     ///
@@ -214,6 +317,11 @@ impl<'a, R: Read> Entry<'a, R> {
     /// This function carefully avoids writing outside of `dst`. If the file has
     /// a '..' in its path, this function will skip it and return false.
     ///
+    /// The path is rewritten first as configured with
+    /// `Archive::set_strip_components`, `Archive::map_path_prefix` and
+    /// `Archive::set_path_rewriter`. Entries skipped by the rewriting aren't
+    /// unpacked either and false is returned for them.
+    ///
     /// # Examples
     ///
     /// ```no_run
@@ -231,6 +339,46 @@ impl<'a, R: Read> Entry<'a, R> {
         self.fields.unpack_in(dst.as_ref())
     }
 
+    /// Extracts this file into the given `UnpackTarget`, avoiding security
+    /// issues.
+    ///
+    /// The path of this file is cleaned up and rewritten like `unpack_in`
+    /// does, and the file is skipped, returning false, if it has a '..' in its
+    /// path or is skipped by the path rewriting. Otherwise its parent
+    /// directories and then the file itself are created in `target`, followed
+    /// by its metadata as configured on this entry.
+    pub fn unpack_into<T: UnpackTarget>(&mut self, target: &mut T) -> io::Result<bool> {
+        self.fields.unpack_into(target)
+    }
+
+    /// Adds what `unpack_in` would do with this file to `plan`, without
+    /// writing anything.
+    ///
+    /// The same path rewriting and safety checks as in `unpack_in` are done,
+    /// taking into account the entries added to `plan` before. Like
+    /// `unpack_in`, false is returned if the file would be skipped because of
+    /// its path.
+    ///
+    /// Note that the data of this entry isn't read.
+    pub fn plan_unpack_in(&mut self, plan: &mut UnpackPlan) -> io::Result<bool> {
+        self.fields.plan_unpack_in(plan)
+    }
+
+    /// Compares this entry against the file `unpack_in` would create for it
+    /// below `root`, returning how the file differs, see `Archive::compare`.
+    ///
+    /// The path of this entry is resolved like `unpack_in` does, and nothing
+    /// is returned for entries it would skip. The contents of regular files
+    /// are only compared if `contents` is set, which reads the data of this
+    /// entry.
+    pub fn compare_in<P: AsRef<Path>>(
+        &mut self,
+        root: P,
+        contents: bool,
+    ) -> io::Result<Vec<Difference>> {
+        self.fields.compare_in(root.as_ref(), contents)
+    }
+
     /// Indicate whether extended file attributes (xattrs on Unix) are preserved
     /// when unpacking this entry.
     ///
@@ -242,6 +390,15 @@ impl<'a, R: Read> Entry<'a, R> {
         self.fields.unpack_xattrs = unpack_xattrs;
     }
 
+    /// Indicate whether POSIX ACLs are restored when unpacking this entry,
+    /// see `Archive::set_unpack_acls`.
+    ///
+    /// This flag is disabled by default and is currently only implemented on
+    /// Linux using xattr support.
+    pub fn set_unpack_acls(&mut self, unpack_acls: bool) {
+        self.fields.unpack_acls = unpack_acls;
+    }
+
     /// Indicate whether extended permissions (like suid on Unix) are preserved
     /// when unpacking this entry.
     ///
@@ -258,6 +415,27 @@ impl<'a, R: Read> Entry<'a, R> {
     pub fn set_preserve_mtime(&mut self, preserve: bool) {
         self.fields.preserve_mtime = preserve;
     }
+
+    /// Indicate whether `unpack_in` creates this entry relative to directory
+    /// file descriptors, see `Archive::set_unpack_beneath`.
+    ///
+    /// This flag is disabled by default and is currently only implemented on
+    /// Linux.
+    pub fn set_unpack_beneath(&mut self, beneath: bool) {
+        self.fields.unpack_beneath = beneath;
+    }
+
+    /// Maps the uid of this entry through `map` when preserving ownerships,
+    /// see `Archive::set_uid_map`.
+    pub fn set_uid_map(&mut self, map: IdMap) {
+        self.fields.uid_map = Some(Arc::new(map));
+    }
+
+    /// Maps the gid of this entry through `map` when preserving ownerships,
+    /// see `Archive::set_gid_map`.
+    pub fn set_gid_map(&mut self, map: IdMap) {
+        self.fields.gid_map = Some(Arc::new(map));
+    }
 }
 
 impl<'a, R: Read> Read for Entry<'a, R> {
@@ -267,6 +445,34 @@ impl<'a, R: Read> Read for Entry<'a, R> {
 }
 
 impl<'a> EntryFields<'a> {
+    /// Creates the fields of the member with the given header, without any
+    /// data and with the default unpacking options of an `Archive`.
+    pub fn new(header: Header, size: u64, header_pos: u64, file_pos: u64) -> EntryFields<'a> {
+        EntryFields {
+            long_pathname: None,
+            long_linkname: None,
+            pax_extensions: None,
+            header,
+            size,
+            header_pos,
+            file_pos,
+            data: Vec::new(),
+            unpack_xattrs: false,
+            unpack_acls: false,
+            preserve_permissions: false,
+            preserve_ownerships: false,
+            numeric_owner: false,
+            owner_resolver: None,
+            uid_map: None,
+            gid_map: None,
+            ownership_xattr: None,
+            preserve_mtime: true,
+            overwrite: OverwritePolicy::Replace,
+            unpack_beneath: false,
+            rewrite: None,
+        }
+    }
+
     pub fn from<R: Read>(entry: Entry<R>) -> EntryFields {
         entry.fields
     }
@@ -285,11 +491,11 @@ impl<'a> EntryFields<'a> {
         self.read_to_end(&mut v).map(|_| v)
     }
 
-    fn path(&self) -> io::Result<Cow<Path>> {
+    pub fn path(&self) -> io::Result<Cow<Path>> {
         bytes2path(self.path_bytes())
     }
 
-    fn path_bytes(&self) -> Cow<[u8]> {
+    pub fn path_bytes(&self) -> Cow<[u8]> {
         match self.long_pathname {
             Some(ref bytes) => {
                 if let Some(&0) = bytes.last() {
@@ -299,33 +505,230 @@ impl<'a> EntryFields<'a> {
                 }
             }
             None => {
-                if let Some(ref pax) = self.pax_extensions {
-                    let pax = PaxExtensions::new(pax)
-                        .filter_map(|f| f.ok())
-                        .find(|f| f.key_bytes() == b"path")
-                        .map(|f| f.value_bytes());
-                    if let Some(field) = pax {
-                        return Cow::Borrowed(field);
-                    }
+                // GNU sparse files in the pax format store the real name of
+                // the file separately, the `path` being a placeholder.
+                if let Some(field) = self.pax_value(b"GNU.sparse.name") {
+                    return Cow::Borrowed(field);
+                }
+                if let Some(field) = self.pax_value(b"path") {
+                    return Cow::Borrowed(field);
                 }
                 self.header.path_bytes()
             }
         }
     }
 
+    /// Returns the value of the last well-formed pax record with the given
+    /// key describing this entry, if any.
+    pub fn pax_value(&self, key: &[u8]) -> Option<&[u8]> {
+        let pax = self.pax_extensions.as_ref()?;
+        PaxExtensions::new(pax)
+            .filter_map(|f| f.ok())
+            .filter(|f| f.key_bytes() == key)
+            .last()
+            .map(|f| f.value_bytes())
+    }
+
+    pub fn pax_num(&self, key: &[u8]) -> io::Result<Option<u64>> {
+        match self.pax_value(key) {
+            Some(value) => parse_pax_num(value).map(Some).ok_or_else(|| {
+                other(&format!(
+                    "invalid pax `{}` record for {}: {}",
+                    String::from_utf8_lossy(key),
+                    self.path_lossy(),
+                    String::from_utf8_lossy(value)
+                ))
+            }),
+            None => Ok(None),
+        }
+    }
+
+    fn pax_time(&self, key: &[u8]) -> io::Result<Option<FileTime>> {
+        match self.pax_value(key) {
+            Some(value) => parse_pax_time(value).map(Some).ok_or_else(|| {
+                other(&format!(
+                    "invalid pax `{}` record for {}: {}",
+                    String::from_utf8_lossy(key),
+                    self.path_lossy(),
+                    String::from_utf8_lossy(value)
+                ))
+            }),
+            None => Ok(None),
+        }
+    }
+
+    pub fn uid(&self) -> io::Result<u64> {
+        match self.pax_num(b"uid")? {
+            Some(uid) => Ok(uid),
+            None => self.header.uid(),
+        }
+    }
+
+    pub fn gid(&self) -> io::Result<u64> {
+        match self.pax_num(b"gid")? {
+            Some(gid) => Ok(gid),
+            None => self.header.gid(),
+        }
+    }
+
+    /// Returns the uid and gid to restore when preserving ownerships, see
+    /// `Archive::set_preserve_ownerships`.
+    fn owner_ids(&self) -> io::Result<(u64, u64)> {
+        let (uid, gid) = self.named_owner_ids()?;
+        let map = |map: &Option<Arc<IdMap>>, id: u64, what: &str| match map {
+            Some(map) => map.map(id).ok_or_else(|| {
+                other(&format!(
+                    "{} {} of {} is not mapped",
+                    what,
+                    id,
+                    self.path_lossy()
+                ))
+            }),
+            None => Ok(id),
+        };
+        Ok((
+            map(&self.uid_map, uid, "uid")?,
+            map(&self.gid_map, gid, "gid")?,
+        ))
+    }
+
+    /// Returns the value of the extended attribute recording the ownership,
+    /// mode and type of this entry, see `Archive::set_ownership_xattr`.
+    fn stat_override(&self, uid: u64, gid: u64) -> io::Result<String> {
+        let old_dir = self.header.as_ustar().is_none() && self.path_bytes().ends_with(b"/");
+        let entry_type = if old_dir {
+            EntryType::Directory
+        } else {
+            self.header.entry_type()
+        };
+        let major = self.header.device_major().ok().flatten().unwrap_or(0);
+        let minor = self.header.device_minor().ok().flatten().unwrap_or(0);
+        let mode = self.header.mode()?;
+        Ok(StatOverride::new(uid, gid, mode, entry_type, major, minor).encode())
+    }
+
+    /// Returns the numeric uid and gid of this entry. Malformed pax records
+    /// fall back to the header like the mtime does, see `unpack_mtime`.
+    fn unpack_ids(&self) -> io::Result<(u64, u64)> {
+        let uid = match self.pax_num(b"uid") {
+            Ok(Some(uid)) => uid,
+            Ok(None) | Err(_) => self.header.uid()?,
+        };
+        let gid = match self.pax_num(b"gid") {
+            Ok(Some(gid)) => gid,
+            Ok(None) | Err(_) => self.header.gid()?,
+        };
+        Ok((uid, gid))
+    }
+
+    /// Returns the uid and gid of this entry, looked up by name unless
+    /// `Archive::set_numeric_owner` is set.
+    fn named_owner_ids(&self) -> io::Result<(u64, u64)> {
+        let (mut uid, mut gid) = self.unpack_ids()?;
+        if self.numeric_owner {
+            return Ok((uid, gid));
+        }
+        let resolver = match self.owner_resolver {
+            Some(ref resolver) => &**resolver,
+            None => &SystemOwnerResolver as &dyn OwnerResolver,
+        };
+        match self.username() {
+            Ok(Some(name)) if !name.is_empty() => uid = resolver.uid(name).unwrap_or(uid),
+            _ => {}
+        }
+        match self.groupname() {
+            Ok(Some(name)) if !name.is_empty() => gid = resolver.gid(name).unwrap_or(gid),
+            _ => {}
+        }
+        Ok((uid, gid))
+    }
+
+    pub fn mtime(&self) -> io::Result<u64> {
+        match self.pax_time(b"mtime")? {
+            Some(mtime) => time_to_secs(mtime, "mtime"),
+            None => self.header.mtime(),
+        }
+    }
+
+    pub fn atime(&self) -> io::Result<Option<u64>> {
+        if let Some(atime) = self.pax_time(b"atime")? {
+            return time_to_secs(atime, "atime").map(Some);
+        }
+        match self.header.as_gnu() {
+            Some(gnu) if gnu.atime[0] != 0 => gnu.atime().map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn username(&self) -> Result<Option<&str>, str::Utf8Error> {
+        match self.username_bytes() {
+            Some(bytes) => str::from_utf8(bytes).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn username_bytes(&self) -> Option<&[u8]> {
+        self.pax_value(b"uname")
+            .or_else(|| self.header.username_bytes())
+    }
+
+    pub fn groupname(&self) -> Result<Option<&str>, str::Utf8Error> {
+        match self.groupname_bytes() {
+            Some(bytes) => str::from_utf8(bytes).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn groupname_bytes(&self) -> Option<&[u8]> {
+        self.pax_value(b"gname")
+            .or_else(|| self.header.groupname_bytes())
+    }
+
+    /// Returns the modification time to restore when unpacking, with pax
+    /// overrides and sub-second precision applied.
+    fn unpack_mtime(&self) -> Option<FileTime> {
+        // A malformed pax mtime falls back to the header like a missing one,
+        // rather than leaving the file with the current time. The same goes
+        // for the uid and gid, see `unpack_ids`.
+        let mtime = match self.pax_time(b"mtime") {
+            Ok(Some(mtime)) => mtime,
+            Ok(None) | Err(_) => FileTime::from_unix_time(self.header.mtime().ok()? as i64, 0),
+        };
+        // For some more information on this see the comments in
+        // `Header::fill_platform_from`, but the general idea is that
+        // we're trying to avoid 0-mtime files coming out of archives
+        // since some tools don't ingest them well. Perhaps one day
+        // when Cargo stops working with 0-mtime archives we can remove
+        // this.
+        if mtime.unix_seconds() == 0 && mtime.nanoseconds() == 0 {
+            Some(FileTime::from_unix_time(1, 0))
+        } else {
+            Some(mtime)
+        }
+    }
+
+    /// Returns the access time to restore when unpacking. Archives rarely
+    /// record one, in which case the modification time is used.
+    fn unpack_atime(&self, mtime: FileTime) -> FileTime {
+        match self.pax_time(b"atime") {
+            Ok(Some(atime)) => atime,
+            _ => mtime,
+        }
+    }
+
     /// Gets the path in a "lossy" way, used for error reporting ONLY.
     fn path_lossy(&self) -> String {
         String::from_utf8_lossy(&self.path_bytes()).to_string()
     }
 
-    fn link_name(&self) -> io::Result<Option<Cow<Path>>> {
+    pub fn link_name(&self) -> io::Result<Option<Cow<Path>>> {
         match self.link_name_bytes() {
             Some(bytes) => bytes2path(bytes).map(Some),
             None => Ok(None),
         }
     }
 
-    fn link_name_bytes(&self) -> Option<Cow<[u8]>> {
+    pub fn link_name_bytes(&self) -> Option<Cow<[u8]>> {
         match self.long_linkname {
             Some(ref bytes) => {
                 if let Some(&0) = bytes.last() {
@@ -335,14 +738,8 @@ impl<'a> EntryFields<'a> {
                 }
             }
             None => {
-                if let Some(ref pax) = self.pax_extensions {
-                    let pax = PaxExtensions::new(pax)
-                        .filter_map(|f| f.ok())
-                        .find(|f| f.key_bytes() == b"linkpath")
-                        .map(|f| f.value_bytes());
-                    if let Some(field) = pax {
-                        return Some(Cow::Borrowed(field));
-                    }
+                if let Some(field) = self.pax_value(b"linkpath") {
+                    return Some(Cow::Borrowed(field));
                 }
                 self.header.link_name_bytes()
             }
@@ -377,53 +774,636 @@ impl<'a> EntryFields<'a> {
         // Most of this is handled by the `path` module of the standard
         // library, but we specially handle a few cases here as well.
 
-        let mut file_dst = dst.to_path_buf();
+        let rel = match self.unpack_path()? {
+            Some(rel) => rel,
+            None => return Ok(false),
+        };
+
+        // Skip cases where only slashes or '.' parts were seen, because
+        // this is effectively an empty filename.
+        if rel.as_os_str().is_empty() {
+            return Ok(true);
+        }
+        let file_dst = dst.join(rel);
+
+        // Skip entries without a parent (i.e. outside of FS root)
+        let parent = match file_dst.parent() {
+            Some(p) => p,
+            None => return Ok(false),
+        };
+
+        #[cfg(target_os = "linux")]
         {
-            let path = self.path().map_err(|e| {
+            if self.unpack_beneath {
+                self.unpack_beneath(dst, &file_dst).map_err(|e| {
+                    TarError::new(format!("failed to unpack `{}`", file_dst.display()), e)
+                })?;
+                return Ok(true);
+            }
+        }
+
+        self.ensure_dir_created(&dst, parent)
+            .map_err(|e| TarError::new(format!("failed to create `{}`", parent.display()), e))?;
+
+        let canon_target = self.validate_inside_dst(&dst, parent)?;
+
+        self.unpack(Some(&canon_target), &file_dst)
+            .map_err(|e| TarError::new(format!("failed to unpack `{}`", file_dst.display()), e))?;
+
+        Ok(true)
+    }
+
+    /// Applies this entry to `dst` if it's an OCI whiteout, returning whether
+    /// it is one. Other entries are recorded in `layer`, which holds the
+    /// entries of the archive unpacked so far. See `Archive::set_whiteouts`.
+    pub(crate) fn unpack_whiteout(
+        &self,
+        dst: &Path,
+        whiteouts: Whiteouts,
+        layer: &mut Layer,
+    ) -> io::Result<bool> {
+        let rel = match self.unpack_path()? {
+            Some(rel) => rel,
+            None => return Ok(false),
+        };
+        let whiteout = match rel.file_name().and_then(Whiteout::parse) {
+            Some(whiteout) if whiteouts != Whiteouts::Keep => whiteout,
+            _ => {
+                layer.insert(&rel);
+                return Ok(false);
+            }
+        };
+        let dir = rel.parent().unwrap_or_else(|| Path::new(""));
+        let dir_dst = dst.join(dir);
+        let ret = (|| -> io::Result<()> {
+            if whiteouts == Whiteouts::Apply {
+                // There's nothing to hide if the directory doesn't exist.
+                if dir_dst.symlink_metadata().is_err() {
+                    return Ok(());
+                }
+            } else {
+                self.ensure_dir_created(dst, &dir_dst)?;
+            }
+            self.validate_inside_dst(dst, &dir_dst)?;
+            // The hidden file must be a child of the directory checked above.
+            if let Whiteout::Remove(name) = whiteout {
+                if Path::new(name).file_name() != Some(name) {
+                    return Err(other("whiteout doesn't name a file"));
+                }
+            }
+            match (whiteouts, whiteout) {
+                // Whiteouts only hide the files of lower layers.
+                (_, Whiteout::Remove(name)) if layer.contains(&dir.join(name)) => Ok(()),
+                (Whiteouts::Apply, Whiteout::Opaque) => layer.remove_lower(dst, dir),
+                (Whiteouts::Apply, Whiteout::Remove(name)) => whiteout::remove(&dir_dst.join(name)),
+                (_, Whiteout::Opaque) => whiteout::set_overlay_opaque(&dir_dst),
+                (_, Whiteout::Remove(name)) => {
+                    whiteout::create_overlay_whiteout(&dir_dst.join(name))
+                }
+            }
+        })();
+        ret.map_err(|e| {
+            TarError::new(
+                format!("failed to apply whiteout `{}`", dst.join(&rel).display()),
+                e,
+            )
+        })?;
+        Ok(true)
+    }
+
+    pub fn unpack_into(&mut self, target: &mut dyn UnpackTarget) -> io::Result<bool> {
+        let path = match self.unpack_path()? {
+            Some(path) => path,
+            None => return Ok(false),
+        };
+        if path.as_os_str().is_empty() {
+            return Ok(true);
+        }
+        self.unpack_into_target(target, &path)
+            .map_err(|e| TarError::new(format!("failed to unpack `{}`", path.display()), e))?;
+        Ok(true)
+    }
+
+    /// Unpacks this entry to the relative `path` in `target`, see
+    /// `Entry::unpack_into`.
+    fn unpack_into_target(&mut self, target: &mut dyn UnpackTarget, path: &Path) -> io::Result<()> {
+        let kind = self.header.entry_type();
+        if kind.is_pax_global_extensions()
+            || kind.is_pax_local_extensions()
+            || kind.is_gnu_longname()
+            || kind.is_gnu_longlink()
+            || kind.is_gnu_volume_label()
+            || kind.is_gnu_multi_volume()
+        {
+            return Ok(());
+        }
+
+        // Targets can't tell whether an existing file is newer or back it up,
+        // so they are only told whether it may be replaced.
+        let overwrite = !matches!(
+            self.overwrite,
+            OverwritePolicy::Error | OverwritePolicy::KeepOld
+        );
+        let mut parent = PathBuf::new();
+        if let Some(parents) = path.parent() {
+            for part in parents.iter() {
+                parent.push(part);
+                target.create_dir(&parent)?;
+            }
+        }
+
+        // Old BSD-tar compatibility, see `unpack`.
+        let old_dir = self.header.as_ustar().is_none() && self.path_bytes().ends_with(b"/");
+        if kind.is_dir() || old_dir {
+            target.create_dir(path)?;
+            self.unpack_target_perms_ownerships(target, path)?;
+            return self.unpack_target_acls(target, path, true);
+        }
+
+        if kind.is_hard_link() || kind.is_symlink() {
+            let src = match self.link_name()? {
+                Some(name) => name.into_owned(),
+                None => {
+                    return Err(other(&format!(
+                        "hard link listed for {} but no link name found",
+                        String::from_utf8_lossy(self.header.as_bytes())
+                    )));
+                }
+            };
+            if src.iter().count() == 0 {
+                return Err(other(&format!(
+                    "symlink destination for {} is empty",
+                    String::from_utf8_lossy(self.header.as_bytes())
+                )));
+            }
+
+            if kind.is_hard_link() {
+                // Hard links are resolved from the root of the target like
+                // the paths of entries, and can't leave it.
+                let src = self.hard_link_target(Cow::Owned(src))?;
+                let mut link_src = PathBuf::new();
+                for part in src.components() {
+                    match part {
+                        Component::Prefix(..) | Component::RootDir | Component::CurDir => {}
+                        Component::Normal(part) => link_src.push(part),
+                        Component::ParentDir => {
+                            return Err(other(&format!(
+                                "hard link target `{}` is outside of the destination",
+                                src.display()
+                            )));
+                        }
+                    }
+                }
+                if link_src.as_os_str().is_empty() {
+                    return Err(other("hard link target is empty"));
+                }
+                return target.hard_link(&link_src, path);
+            }
+
+            target.symlink(&src, path, overwrite)?;
+            return self.unpack_target_times(target, path);
+        }
+
+        if kind.is_character_special() || kind.is_block_special() || kind.is_fifo() {
+            let mode = self.header.mode()?;
+            // FIFOs often leave the device numbers empty.
+            let (major, minor) = if kind.is_fifo() {
+                (0, 0)
+            } else {
+                let major = self.header.device_major()?.unwrap_or(0);
+                let minor = self.header.device_minor()?.unwrap_or(0);
+                (major, minor)
+            };
+            target.mknod(path, kind, mode, major, minor)?;
+        } else {
+            target.create_file(path, self, overwrite)?;
+        }
+        self.unpack_target_times(target, path)?;
+        self.unpack_target_perms_ownerships(target, path)?;
+        if self.unpack_xattrs {
+            if let Ok(Some(exts)) = self.pax_extensions() {
+                let prefix = b"SCHILY.xattr.";
+                for ext in exts.filter_map(|e| e.ok()) {
+                    let key = ext.key_bytes();
+                    if key.starts_with(prefix) {
+                        target.set_xattr(path, &key[prefix.len()..], ext.value_bytes())?;
+                    }
+                }
+            }
+        }
+        self.unpack_target_acls(target, path, false)
+    }
+
+    fn unpack_target_acls(
+        &self,
+        target: &mut dyn UnpackTarget,
+        path: &Path,
+        is_dir: bool,
+    ) -> io::Result<()> {
+        for (name, value) in self.acl_xattrs(is_dir)? {
+            target.set_xattr(path, name.as_bytes(), &value)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the extended attributes restoring the POSIX ACLs of this
+    /// entry, see `Archive::set_unpack_acls`.
+    #[cfg(all(target_os = "linux", feature = "xattr"))]
+    fn acl_xattrs(&self, is_dir: bool) -> io::Result<Vec<(&'static str, Vec<u8>)>> {
+        let mut xattrs = Vec::new();
+        if !self.unpack_acls {
+            return Ok(xattrs);
+        }
+        let records: [(&[u8], &'static str); 2] = [
+            (b"SCHILY.acl.access", acl::ACCESS_XATTR),
+            (b"SCHILY.acl.default", acl::DEFAULT_XATTR),
+        ];
+        for &(key, name) in records.iter() {
+            // Only directories have default ACLs.
+            if name == acl::DEFAULT_XATTR && !is_dir {
+                continue;
+            }
+            if let Some(text) = self.pax_value(key) {
+                let value = acl::text_to_xattr(text)?;
+                if !value.is_empty() {
+                    xattrs.push((name, value));
+                }
+            }
+        }
+        Ok(xattrs)
+    }
+
+    #[cfg(not(all(target_os = "linux", feature = "xattr")))]
+    fn acl_xattrs(&self, _is_dir: bool) -> io::Result<Vec<(&'static str, Vec<u8>)>> {
+        Ok(Vec::new())
+    }
+
+    /// Restores the POSIX ACLs of this entry unpacked to `dst`, through `f`
+    /// if given.
+    #[cfg(all(target_os = "linux", feature = "xattr"))]
+    fn set_acls(&self, dst: &Path, f: Option<&fs::File>, is_dir: bool) -> io::Result<()> {
+        for (name, value) in self.acl_xattrs(is_dir)? {
+            let ret = match f {
+                Some(f) => xattr::FileExt::set_xattr(f, name, &value),
+                None => xattr::set(dst, name, &value),
+            };
+            ret.map_err(|e| {
                 TarError::new(
-                    format!("invalid path in entry header: {}", self.path_lossy()),
+                    format!("failed to set ACL `{}` of `{}`", name, dst.display()),
                     e,
                 )
             })?;
-            for part in path.components() {
-                match part {
-                    // Leading '/' characters, root paths, and '.'
-                    // components are just ignored and treated as "empty
-                    // components"
-                    Component::Prefix(..) | Component::RootDir | Component::CurDir => continue,
-
-                    // If any part of the filename is '..', then skip over
-                    // unpacking the file to prevent directory traversal
-                    // security issues.  See, e.g.: CVE-2001-1267,
-                    // CVE-2002-0399, CVE-2005-1918, CVE-2007-4131
-                    Component::ParentDir => return Ok(false),
-
-                    Component::Normal(part) => file_dst.push(part),
+        }
+        Ok(())
+    }
+
+    #[cfg(not(all(target_os = "linux", feature = "xattr")))]
+    fn set_acls(&self, _: &Path, _: Option<&fs::File>, _: bool) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn unpack_target_times(&self, target: &mut dyn UnpackTarget, path: &Path) -> io::Result<()> {
+        if self.preserve_mtime {
+            if let Some(mtime) = self.unpack_mtime() {
+                let atime = self.unpack_atime(mtime);
+                target.set_times(path, system_time(atime), system_time(mtime))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn unpack_target_perms_ownerships(
+        &self,
+        target: &mut dyn UnpackTarget,
+        path: &Path,
+    ) -> io::Result<()> {
+        // Like `set_perms_ownerships`, ownership comes first so that setting
+        // it doesn't clear the SUID bits.
+        if self.preserve_ownerships {
+            let (uid, gid) = self.owner_ids()?;
+            match self.ownership_xattr {
+                Some(ref name) => {
+                    let value = self.stat_override(uid, gid)?;
+                    target.set_xattr(path, name.as_bytes(), value.as_bytes())?;
                 }
+                None => target.set_ownership(path, uid, gid)?,
+            }
+        }
+        if let Ok(mode) = self.header.mode() {
+            let mode = if self.preserve_permissions {
+                mode
+            } else {
+                mode & 0o777
+            };
+            target.set_permissions(path, mode)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the path this entry is unpacked to relative to the
+    /// destination, after rewriting it and removing leading `/` and `.`
+    /// components, or `None` if the entry is to be skipped.
+    fn unpack_path(&self) -> io::Result<Option<PathBuf>> {
+        Ok(self.resolve_unpack_path()?.ok())
+    }
+
+    /// Like `unpack_path`, returning why the entry is skipped.
+    fn resolve_unpack_path(&self) -> io::Result<Result<PathBuf, PlannedAction>> {
+        let path = self.path().map_err(|e| {
+            TarError::new(
+                format!("invalid path in entry header: {}", self.path_lossy()),
+                e,
+            )
+        })?;
+        let path = match self.rewrite {
+            Some(ref rewrite) => match rewrite.rewrite(&path) {
+                Some(path) => Cow::Owned(path),
+                None => return Ok(Err(PlannedAction::SkipRewrite)),
+            },
+            None => path,
+        };
+        let mut rel = PathBuf::new();
+        for part in path.components() {
+            match part {
+                // Leading '/' characters, root paths, and '.'
+                // components are just ignored and treated as "empty
+                // components"
+                Component::Prefix(..) | Component::RootDir | Component::CurDir => continue,
+
+                // If any part of the filename is '..', then skip over
+                // unpacking the file to prevent directory traversal
+                // security issues.  See, e.g.: CVE-2001-1267,
+                // CVE-2002-0399, CVE-2005-1918, CVE-2007-4131
+                Component::ParentDir => return Ok(Err(PlannedAction::SkipTraversal)),
+
+                Component::Normal(part) => rel.push(part),
+            }
+        }
+        Ok(Ok(rel))
+    }
+
+    pub fn compare_in(&mut self, root: &Path, contents: bool) -> io::Result<Vec<Difference>> {
+        let kind = self.header.entry_type();
+        if kind.is_pax_global_extensions()
+            || kind.is_pax_local_extensions()
+            || kind.is_gnu_longname()
+            || kind.is_gnu_longlink()
+            || kind.is_gnu_volume_label()
+            || kind.is_gnu_multi_volume()
+        {
+            return Ok(Vec::new());
+        }
+        let rel = match self.unpack_path()? {
+            Some(ref rel) if rel.as_os_str().is_empty() => return Ok(Vec::new()),
+            Some(rel) => rel,
+            None => return Ok(Vec::new()),
+        };
+        let path = root.join(&rel);
+
+        // Like `unpack_in`, the parent directories may lead through symbolic
+        // links as long as they stay inside the root.
+        let canon_root = root.canonicalize()?;
+        let meta = match path.parent().unwrap_or(root).canonicalize() {
+            Ok(ref parent) if !parent.starts_with(&canon_root) => {
+                return Ok(vec![Difference::new(path, DifferenceKind::Outside)]);
+            }
+            Ok(_) => path.symlink_metadata(),
+            Err(e) => Err(e),
+        };
+        let meta = match meta {
+            Ok(meta) => meta,
+            Err(ref e) if compare::is_missing(e) => {
+                return Ok(vec![Difference::new(path, DifferenceKind::Missing)]);
+            }
+            Err(e) => return Err(e),
+        };
+
+        let mut differences = Vec::new();
+        if kind.is_hard_link() {
+            let target = match self.link_name()? {
+                Some(target) => root.join(self.hard_link_target(target)?),
+                None => return Ok(differences),
+            };
+            if !same_file(&meta, &target) {
+                differences.push(Difference::new(path, DifferenceKind::NotLinked { target }));
+            }
+            return Ok(differences);
+        }
+        if !compare::matches_type(kind, meta.file_type()) {
+            let kind = DifferenceKind::Type {
+                archive: kind,
+                fs: meta.file_type(),
+            };
+            return Ok(vec![Difference::new(path, kind)]);
+        }
+
+        let mut differ = |kind| differences.push(Difference::new(path.clone(), kind));
+        if kind.is_symlink() {
+            let archive = self.link_name()?.map(Cow::into_owned).unwrap_or_default();
+            let fs = fs::read_link(&path)?;
+            if archive != fs {
+                differ(DifferenceKind::LinkTarget { archive, fs });
+            }
+        } else if meta.is_file() {
+            // Malformed pax records fall back to the header, as when unpacking.
+            let archive = match self.pax_time(b"mtime") {
+                Ok(Some(mtime)) => time_to_secs(mtime, "mtime")?,
+                Ok(None) | Err(_) => self.header.mtime()?,
+            };
+            let fs = FileTime::from_last_modification_time(&meta).unix_seconds();
+            if i64::try_from(archive) != Ok(fs) {
+                differ(DifferenceKind::Mtime { archive, fs });
+            }
+        }
+        if meta.is_file() && self.size != meta.len() {
+            differ(DifferenceKind::Size {
+                archive: self.size,
+                fs: meta.len(),
+            });
+        } else if meta.is_file() && contents && !same_contents(self, &path)? {
+            differ(DifferenceKind::Contents);
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::prelude::*;
+
+            if !kind.is_symlink() {
+                let archive = self.header.mode()? & 0o7777;
+                let fs = meta.mode() & 0o7777;
+                if archive != fs {
+                    differ(DifferenceKind::Mode { archive, fs });
+                }
+            }
+            let (uid, gid) = self.owner_ids()?;
+            if uid != u64::from(meta.uid()) {
+                differ(DifferenceKind::Uid {
+                    archive: uid,
+                    fs: meta.uid().into(),
+                });
+            }
+            if gid != u64::from(meta.gid()) {
+                differ(DifferenceKind::Gid {
+                    archive: gid,
+                    fs: meta.gid().into(),
+                });
+            }
+        }
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::unix::prelude::*;
+
+            if kind.is_character_special() || kind.is_block_special() {
+                let archive = (
+                    self.header.device_major()?.unwrap_or(0),
+                    self.header.device_minor()?.unwrap_or(0),
+                );
+                let dev_id = meta.rdev();
+                let dev_major = ((dev_id >> 32) & 0xffff_f000) | ((dev_id >> 8) & 0x0000_0fff);
+                let dev_minor = ((dev_id >> 12) & 0xffff_ff00) | ((dev_id) & 0x0000_00ff);
+                let fs = (dev_major as u32, dev_minor as u32);
+                if archive != fs {
+                    differ(DifferenceKind::Device { archive, fs });
+                }
+            }
+        }
+        Ok(differences)
+    }
+
+    pub fn plan_unpack_in(&mut self, plan: &mut UnpackPlan) -> io::Result<bool> {
+        let kind = self.header.entry_type();
+        if kind.is_pax_global_extensions()
+            || kind.is_pax_local_extensions()
+            || kind.is_gnu_longname()
+            || kind.is_gnu_longlink()
+            || kind.is_gnu_volume_label()
+            || kind.is_gnu_multi_volume()
+        {
+            return Ok(true);
+        }
+
+        let path = self.path_bytes().into_owned();
+        let link_name = if kind.is_hard_link() || kind.is_symlink() {
+            self.link_name()?.map(Cow::into_owned)
+        } else {
+            None
+        };
+        let rel = match self.resolve_unpack_path()? {
+            Ok(rel) => rel,
+            Err(action) => {
+                plan.push(path, kind, link_name, None, action, self.size);
+                return Ok(false);
+            }
+        };
+        if rel.as_os_str().is_empty() {
+            return Ok(true);
+        }
+
+        let action = match plan.resolve(&rel) {
+            Some(resolved) => self.plan_action(plan, &resolved, link_name.as_deref())?,
+            None => PlannedAction::RejectOutside,
+        };
+        plan.push(path, kind, link_name, Some(&rel), action, self.size);
+        Ok(true)
+    }
+
+    /// Returns what unpacking this entry to `rel`, already resolved by
+    /// `plan`, would do and records the result in `plan`.
+    fn plan_action(
+        &self,
+        plan: &mut UnpackPlan,
+        rel: &Path,
+        link_name: Option<&Path>,
+    ) -> io::Result<PlannedAction> {
+        let kind = self.header.entry_type();
+        let existing = plan.existing(rel);
+
+        // `unpack_in` creates missing parent directories.
+        for parent in rel.ancestors().skip(1) {
+            if parent.as_os_str().is_empty() || plan.existing(parent).is_some() {
+                break;
+            }
+            plan.create(parent, true, None, None);
+        }
+
+        // Old BSD-tar compatibility, see `unpack`.
+        let old_dir = self.header.as_ustar().is_none() && self.path_bytes().ends_with(b"/");
+        if kind.is_dir() || old_dir {
+            return Ok(match existing {
+                Some((true, _)) => PlannedAction::Existing,
+                Some((false, _)) => PlannedAction::Conflict,
+                None => {
+                    plan.create(rel, true, None, None);
+                    PlannedAction::Create
+                }
+            });
+        }
+
+        let link_name = match link_name {
+            Some(name) if name.iter().count() == 0 => {
+                return Err(other(&format!(
+                    "symlink destination for {} is empty",
+                    String::from_utf8_lossy(self.header.as_bytes())
+                )));
+            }
+            Some(name) => Some(name),
+            None if kind.is_hard_link() || kind.is_symlink() => {
+                return Err(other(&format!(
+                    "hard link listed for {} but no link name found",
+                    String::from_utf8_lossy(self.header.as_bytes())
+                )));
+            }
+            None => None,
+        };
+
+        if let (true, Some(link_name)) = (kind.is_hard_link(), link_name) {
+            let src = self.hard_link_target(Cow::Borrowed(link_name))?;
+            let src = match plan.resolve_link(&src) {
+                Some(src) => src,
+                None => return Ok(PlannedAction::RejectOutside),
+            };
+            let src_mtime = match plan.existing(&src) {
+                Some((_, mtime)) => mtime,
+                None => return Ok(PlannedAction::MissingLinkTarget),
+            };
+            if existing.is_some() {
+                return Ok(PlannedAction::Conflict);
             }
+            plan.create(rel, false, src_mtime, None);
+            return Ok(PlannedAction::Create);
         }
 
-        // Skip cases where only slashes or '.' parts were seen, because
-        // this is effectively an empty filename.
-        if *dst == *file_dst {
-            return Ok(true);
-        }
-
-        // Skip entries without a parent (i.e. outside of FS root)
-        let parent = match file_dst.parent() {
-            Some(p) => p,
-            None => return Ok(false),
+        let action = match existing {
+            None => PlannedAction::Create,
+            Some((is_dir, existing_mtime)) => {
+                let action = match self.overwrite {
+                    OverwritePolicy::Error => PlannedAction::Conflict,
+                    OverwritePolicy::KeepOld => PlannedAction::Keep,
+                    OverwritePolicy::KeepNewer => match (existing_mtime, self.unpack_mtime()) {
+                        (Some(existing), Some(mtime)) if existing > mtime => PlannedAction::Keep,
+                        _ => PlannedAction::Overwrite,
+                    },
+                    OverwritePolicy::Backup => PlannedAction::Backup,
+                    OverwritePolicy::Replace | OverwritePolicy::Atomic => PlannedAction::Overwrite,
+                };
+                // Only backups move directories out of the way.
+                if is_dir && action == PlannedAction::Overwrite {
+                    PlannedAction::Conflict
+                } else {
+                    action
+                }
+            }
         };
-
-        self.ensure_dir_created(&dst, parent)
-            .map_err(|e| TarError::new(format!("failed to create `{}`", parent.display()), e))?;
-
-        let canon_target = self.validate_inside_dst(&dst, parent)?;
-
-        self.unpack(Some(&canon_target), &file_dst)
-            .map_err(|e| TarError::new(format!("failed to unpack `{}`", file_dst.display()), e))?;
-
-        Ok(true)
+        if let PlannedAction::Create | PlannedAction::Overwrite | PlannedAction::Backup = action {
+            let mtime = if self.preserve_mtime {
+                self.unpack_mtime()
+            } else {
+                Some(FileTime::now())
+            };
+            let link = if kind.is_symlink() { link_name } else { None };
+            plan.create(rel, false, mtime, link);
+        }
+        Ok(action)
     }
 
     /// Unpack as destination directory `dst`.
@@ -445,38 +1425,6 @@ impl<'a> EntryFields<'a> {
 
     /// Returns access to the header of this entry in the archive.
     fn unpack(&mut self, target_base: Option<&Path>, dst: &Path) -> io::Result<Unpacked> {
-        fn set_perms_ownerships(
-            dst: &Path,
-            f: Option<&mut std::fs::File>,
-            header: &Header,
-            perms: bool,
-            ownerships: bool,
-        ) -> io::Result<()> {
-            // ownerships need to be set first to avoid stripping SUID bits in the permissions ...
-            if ownerships {
-                set_ownerships(dst, &f, header.uid()?, header.gid()?)?;
-            }
-            // ... then set permissions, SUID bits set here is kept
-            if let Ok(mode) = header.mode() {
-                set_perms(dst, f, mode, perms)?;
-            }
-
-            Ok(())
-        }
-
-        fn get_mtime(header: &Header) -> Option<FileTime> {
-            header.mtime().ok().map(|mtime| {
-                // For some more information on this see the comments in
-                // `Header::fill_platform_from`, but the general idea is that
-                // we're trying to avoid 0-mtime files coming out of archives
-                // since some tools don't ingest them well. Perhaps one day
-                // when Cargo stops working with 0-mtime archives we can remove
-                // this.
-                let mtime = if mtime == 0 { 1 } else { mtime };
-                FileTime::from_unix_time(mtime as i64, 0)
-            })
-        }
-
         let kind = self.header.entry_type();
 
         if kind.is_dir() {
@@ -484,10 +1432,11 @@ impl<'a> EntryFields<'a> {
             set_perms_ownerships(
                 dst,
                 None,
-                &self.header,
+                self,
                 self.preserve_permissions,
                 self.preserve_ownerships,
             )?;
+            self.set_acls(dst, None, true)?;
             return Ok(Unpacked::__Nonexhaustive);
         } else if kind.is_hard_link() || kind.is_symlink() {
             let src = match self.link_name()? {
@@ -521,29 +1470,48 @@ impl<'a> EntryFields<'a> {
                     // links though they're canonicalized to their existing path
                     // so we need to validate at this time.
                     Some(ref p) => {
-                        let link_src = p.join(src);
+                        let link_src = p.join(self.hard_link_target(src)?);
                         self.validate_inside_dst(p, &link_src)?;
                         link_src
                     }
                     None => src.into_owned(),
                 };
-                fs::hard_link(&link_src, dst).map_err(|err| {
-                    Error::new(
-                        err.kind(),
-                        format!(
-                            "{} when hard linking {} to {}",
-                            err,
-                            link_src.display(),
-                            dst.display()
-                        ),
-                    )
-                })?;
+                let path = match self.prepare_overwrite_path(dst)? {
+                    Some(path) => path,
+                    None => return Ok(Unpacked::__Nonexhaustive),
+                };
+                let overwrite = path == dst && self.overwrite.replaces();
+                let ret = fs::hard_link(&link_src, &path)
+                    .or_else(|err_io| {
+                        if err_io.kind() == io::ErrorKind::AlreadyExists && overwrite {
+                            fs::remove_file(&path).and_then(|()| fs::hard_link(&link_src, &path))
+                        } else {
+                            Err(err_io)
+                        }
+                    })
+                    .map_err(|err| {
+                        Error::new(
+                            err.kind(),
+                            format!(
+                                "{} when hard linking {} to {}",
+                                err,
+                                link_src.display(),
+                                dst.display()
+                            ),
+                        )
+                    });
+                finish_overwrite(&path, dst, ret)?;
             } else {
-                symlink(&src, dst)
+                let path = match self.prepare_overwrite_path(dst)? {
+                    Some(path) => path,
+                    None => return Ok(Unpacked::__Nonexhaustive),
+                };
+                let overwrite = path == dst && self.overwrite.replaces();
+                symlink(&src, &path)
                     .or_else(|err_io| {
-                        if err_io.kind() == io::ErrorKind::AlreadyExists && self.overwrite {
+                        if err_io.kind() == io::ErrorKind::AlreadyExists && overwrite {
                             // remove dest and try once more
-                            std::fs::remove_file(dst).and_then(|()| symlink(&src, dst))
+                            std::fs::remove_file(&path).and_then(|()| symlink(&src, &path))
                         } else {
                             Err(err_io)
                         }
@@ -559,13 +1527,21 @@ impl<'a> EntryFields<'a> {
                             ),
                         )
                     })?;
-                if self.preserve_mtime {
-                    if let Some(mtime) = get_mtime(&self.header) {
-                        filetime::set_symlink_file_times(dst, mtime, mtime).map_err(|e| {
-                            TarError::new(format!("failed to set mtime for `{}`", dst.display()), e)
-                        })?;
+                let ret = (|| -> io::Result<()> {
+                    if self.preserve_mtime {
+                        if let Some(mtime) = self.unpack_mtime() {
+                            let atime = self.unpack_atime(mtime);
+                            filetime::set_symlink_file_times(&path, atime, mtime).map_err(|e| {
+                                TarError::new(
+                                    format!("failed to set mtime for `{}`", dst.display()),
+                                    e,
+                                )
+                            })?;
+                        }
                     }
-                }
+                    Ok(())
+                })();
+                finish_overwrite(&path, dst, ret)?;
             }
             return Ok(Unpacked::__Nonexhaustive);
 
@@ -588,6 +1564,8 @@ impl<'a> EntryFields<'a> {
             || kind.is_pax_local_extensions()
             || kind.is_gnu_longname()
             || kind.is_gnu_longlink()
+            || kind.is_gnu_volume_label()
+            || kind.is_gnu_multi_volume()
         {
             return Ok(Unpacked::__Nonexhaustive);
         };
@@ -600,10 +1578,11 @@ impl<'a> EntryFields<'a> {
             set_perms_ownerships(
                 dst,
                 None,
-                &self.header,
+                self,
                 self.preserve_permissions,
                 self.preserve_ownerships,
             )?;
+            self.set_acls(dst, None, true)?;
             return Ok(Unpacked::__Nonexhaustive);
         }
 
@@ -616,41 +1595,39 @@ impl<'a> EntryFields<'a> {
         // As a result if we don't recognize the kind we just write out the file
         // as we would normally.
 
+        let path = match self.prepare_overwrite_path(dst)? {
+            Some(path) => path,
+            None => return Ok(Unpacked::__Nonexhaustive),
+        };
+        let ret = self.unpack_file(&path, dst);
+        let f = finish_overwrite(&path, dst, ret)?;
+        Ok(Unpacked::File(f))
+    }
+
+    /// Unpacks this entry as a regular file to `path`, which is `dst` or a
+    /// temporary name to rename to `dst` afterwards.
+    fn unpack_file(&mut self, path: &Path, dst: &Path) -> io::Result<fs::File> {
         // Ensure we write a new file rather than overwriting in-place which
         // is attackable; if an existing file is found unlink it.
         fn open(dst: &Path) -> io::Result<std::fs::File> {
             OpenOptions::new().write(true).create_new(true).open(dst)
         }
+        let overwrite = path == dst && self.overwrite.replaces();
         let mut f = (|| -> io::Result<std::fs::File> {
-            let mut f = open(dst).or_else(|err| {
+            let mut f = open(path).or_else(|err| {
                 if err.kind() != ErrorKind::AlreadyExists {
                     Err(err)
-                } else if self.overwrite {
-                    match fs::remove_file(dst) {
-                        Ok(()) => open(dst),
-                        Err(ref e) if e.kind() == io::ErrorKind::NotFound => open(dst),
+                } else if overwrite {
+                    match fs::remove_file(path) {
+                        Ok(()) => open(path),
+                        Err(ref e) if e.kind() == io::ErrorKind::NotFound => open(path),
                         Err(e) => Err(e),
                     }
                 } else {
                     Err(err)
                 }
             })?;
-            for io in self.data.drain(..) {
-                match io {
-                    EntryIo::Data(mut d) => {
-                        let expected = d.limit();
-                        if io::copy(&mut d, &mut f)? != expected {
-                            return Err(other("failed to write entire file"));
-                        }
-                    }
-                    EntryIo::Pad(d) => {
-                        // TODO: checked cast to i64
-                        let to = SeekFrom::Current(d.limit() as i64);
-                        let size = f.seek(to)?;
-                        f.set_len(size)?;
-                    }
-                }
-            }
+            self.unpack_data(&mut f)?;
             Ok(f)
         })()
         .map_err(|e| {
@@ -665,214 +1642,313 @@ impl<'a> EntryFields<'a> {
             )
         })?;
 
+        self.unpack_file_metadata(&mut f, path, false)?;
+        Ok(f)
+    }
+
+    /// Applies the overwrite policy to an existing file named `name`, whose
+    /// modification time is `existing`, before this entry is unpacked to it.
+    ///
+    /// Returns `None` if the existing file is kept, and otherwise the name to
+    /// create the entry under, which is a temporary one to rename into place
+    /// with `OverwritePolicy::Atomic`.
+    fn prepare_overwrite(
+        &self,
+        name: &OsStr,
+        existing: Option<FileTime>,
+        exists: &dyn Fn(&OsStr) -> bool,
+        rename: &dyn Fn(&OsStr, &OsStr) -> io::Result<()>,
+    ) -> io::Result<Option<OsString>> {
+        match self.overwrite {
+            OverwritePolicy::Atomic => return Ok(Some(temp_name(name))),
+            _ if existing.is_none() => {}
+            OverwritePolicy::KeepOld => return Ok(None),
+            OverwritePolicy::KeepNewer => {
+                if let (Some(existing), Some(mtime)) = (existing, self.unpack_mtime()) {
+                    if existing > mtime {
+                        return Ok(None);
+                    }
+                }
+            }
+            OverwritePolicy::Backup => rename(name, &backup_name(name, exists))?,
+            _ => {}
+        }
+        Ok(Some(name.to_os_string()))
+    }
+
+    /// Like `prepare_overwrite`, for unpacking this entry to `dst`.
+    fn prepare_overwrite_path(&self, dst: &Path) -> io::Result<Option<PathBuf>> {
+        let (parent, name) = match (dst.parent(), dst.file_name()) {
+            (Some(parent), Some(name)) => (parent, name),
+            _ => return Ok(Some(dst.to_path_buf())),
+        };
+        let existing = fs::symlink_metadata(dst)
+            .ok()
+            .map(|m| FileTime::from_last_modification_time(&m));
+        let name = self
+            .prepare_overwrite(
+                name,
+                existing,
+                &|name| parent.join(name).symlink_metadata().is_ok(),
+                &|from, to| fs::rename(parent.join(from), parent.join(to)),
+            )
+            .map_err(|e| TarError::new(format!("failed to back up `{}`", dst.display()), e))?;
+        Ok(name.map(|name| parent.join(name)))
+    }
+
+    /// Applies the path rewriting of `unpack_in` to the target of a hard link,
+    /// so that it points to the rewritten entry.
+    fn hard_link_target<'b>(&self, src: Cow<'b, Path>) -> io::Result<Cow<'b, Path>> {
+        match self.rewrite {
+            Some(ref rewrite) => rewrite.rewrite(&src).map(Cow::Owned).ok_or_else(|| {
+                other(&format!(
+                    "hard link target `{}` is skipped by path rewriting",
+                    src.display()
+                ))
+            }),
+            None => Ok(src),
+        }
+    }
+
+    /// Writes the data of this entry to `f`.
+    fn unpack_data(&mut self, f: &mut fs::File) -> io::Result<()> {
+        for io in self.data.drain(..) {
+            match io {
+                EntryIo::Data(mut d) => {
+                    let expected = d.limit();
+                    if io::copy(&mut d, f)? != expected {
+                        return Err(other("failed to write entire file"));
+                    }
+                }
+                EntryIo::Pad(d) => {
+                    // TODO: checked cast to i64
+                    let to = SeekFrom::Current(d.limit() as i64);
+                    let size = f.seek(to)?;
+                    f.set_len(size)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets the times, permissions, ownership and extended attributes of the
+    /// file `f` unpacked to `dst`. Extended attributes are set through `f`
+    /// rather than `dst` if `xattrs_by_handle` is set.
+    fn unpack_file_metadata(
+        &mut self,
+        f: &mut fs::File,
+        dst: &Path,
+        xattrs_by_handle: bool,
+    ) -> io::Result<()> {
         if self.preserve_mtime {
-            if let Some(mtime) = get_mtime(&self.header) {
-                filetime::set_file_handle_times(&f, Some(mtime), Some(mtime)).map_err(|e| {
+            if let Some(mtime) = self.unpack_mtime() {
+                let atime = self.unpack_atime(mtime);
+                filetime::set_file_handle_times(f, Some(atime), Some(mtime)).map_err(|e| {
                     TarError::new(format!("failed to set mtime for `{}`", dst.display()), e)
                 })?;
             }
         }
         set_perms_ownerships(
             dst,
-            Some(&mut f),
-            &self.header,
+            Some(f),
+            self,
             self.preserve_permissions,
             self.preserve_ownerships,
         )?;
+        let handle = if xattrs_by_handle { Some(&*f) } else { None };
         if self.unpack_xattrs {
-            set_xattrs(self, dst)?;
-        }
-        return Ok(Unpacked::File(f));
-
-        fn set_ownerships(
-            dst: &Path,
-            f: &Option<&mut std::fs::File>,
-            uid: u64,
-            gid: u64,
-        ) -> Result<(), TarError> {
-            _set_ownerships(dst, f, uid, gid).map_err(|e| {
-                TarError::new(
-                    format!(
-                        "failed to set ownerships to uid={:?}, gid={:?} \
-                         for `{}`",
-                        uid,
-                        gid,
-                        dst.display()
-                    ),
-                    e,
-                )
-            })
+            set_xattrs(self, dst, handle)?;
         }
+        self.set_acls(dst, handle, false)
+    }
 
-        #[cfg(unix)]
-        fn _set_ownerships(
-            dst: &Path,
-            f: &Option<&mut std::fs::File>,
-            uid: u64,
-            gid: u64,
-        ) -> io::Result<()> {
-            use std::convert::TryInto;
-            use std::os::unix::prelude::*;
-
-            let uid: libc::uid_t = uid.try_into().map_err(|_| {
-                io::Error::new(io::ErrorKind::Other, format!("UID {} is too large!", uid))
-            })?;
-            let gid: libc::gid_t = gid.try_into().map_err(|_| {
-                io::Error::new(io::ErrorKind::Other, format!("GID {} is too large!", gid))
-            })?;
-            match f {
-                Some(f) => unsafe {
-                    let fd = f.as_raw_fd();
-                    if libc::fchown(fd, uid, gid) != 0 {
-                        Err(io::Error::last_os_error())
-                    } else {
-                        Ok(())
-                    }
-                },
-                None => unsafe {
-                    let path = std::ffi::CString::new(dst.as_os_str().as_bytes()).map_err(|e| {
-                        io::Error::new(
-                            io::ErrorKind::Other,
-                            format!("path contains null character: {:?}", e),
-                        )
-                    })?;
-                    if libc::lchown(path.as_ptr(), uid, gid) != 0 {
-                        Err(io::Error::last_os_error())
-                    } else {
-                        Ok(())
-                    }
-                },
-            }
-        }
+    /// Unpacks this entry to `file_dst`, which is inside `dst`, creating
+    /// everything relative to directory file descriptors opened beneath
+    /// `dst`, see `Archive::set_unpack_beneath`.
+    #[cfg(target_os = "linux")]
+    fn unpack_beneath(&mut self, dst: &Path, file_dst: &Path) -> io::Result<()> {
+        let components = file_dst
+            .strip_prefix(dst)
+            .unwrap()
+            .iter()
+            .collect::<Vec<_>>();
+        let (name, parents) = components.split_last().unwrap();
+        let root = Dir::open(dst)
+            .map_err(|e| TarError::new(format!("failed to open `{}`", dst.display()), e))?;
+        let parent = root.open_beneath(parents, true).map_err(|e| {
+            let parent = file_dst.parent().unwrap();
+            TarError::new(format!("failed to create `{}`", parent.display()), e)
+        })?;
 
-        // Windows does not support posix numeric ownership IDs
-        #[cfg(any(windows, target_arch = "wasm32"))]
-        fn _set_ownerships(
-            _: &Path,
-            _: &Option<&mut std::fs::File>,
-            _: u64,
-            _: u64,
-        ) -> io::Result<()> {
-            Ok(())
+        let kind = self.header.entry_type();
+        if kind.is_pax_global_extensions()
+            || kind.is_pax_local_extensions()
+            || kind.is_gnu_longname()
+            || kind.is_gnu_longlink()
+            || kind.is_gnu_volume_label()
+            || kind.is_gnu_multi_volume()
+        {
+            return Ok(());
         }
 
-        fn set_perms(
-            dst: &Path,
-            f: Option<&mut std::fs::File>,
-            mode: u32,
-            preserve: bool,
-        ) -> Result<(), TarError> {
-            _set_perms(dst, f, mode, preserve).map_err(|e| {
-                TarError::new(
-                    format!(
-                        "failed to set permissions to {:o} \
-                         for `{}`",
-                        mode,
-                        dst.display()
-                    ),
-                    e,
-                )
-            })
+        // Old BSD-tar compatibility, see `unpack`.
+        let old_dir = self.header.as_ustar().is_none() && self.path_bytes().ends_with(b"/");
+        if kind.is_dir() || old_dir {
+            parent.mkdir(name)?;
+            let dir = parent.open_dir(name)?;
+            let mut f = dir.file().try_clone()?;
+            set_perms_ownerships(
+                file_dst,
+                Some(&mut f),
+                self,
+                self.preserve_permissions,
+                self.preserve_ownerships,
+            )?;
+            return self.set_acls(file_dst, Some(&f), true);
         }
 
-        #[cfg(unix)]
-        fn _set_perms(
-            dst: &Path,
-            f: Option<&mut std::fs::File>,
-            mode: u32,
-            preserve: bool,
-        ) -> io::Result<()> {
-            use std::os::unix::prelude::*;
-
-            let mode = if preserve { mode } else { mode & 0o777 };
-            let perm = fs::Permissions::from_mode(mode as _);
-            match f {
-                Some(f) => f.set_permissions(perm),
-                None => fs::set_permissions(dst, perm),
-            }
-        }
-
-        #[cfg(windows)]
-        fn _set_perms(
-            dst: &Path,
-            f: Option<&mut std::fs::File>,
-            mode: u32,
-            _preserve: bool,
-        ) -> io::Result<()> {
-            if mode & 0o200 == 0o200 {
-                return Ok(());
-            }
-            match f {
-                Some(f) => {
-                    let mut perm = f.metadata()?.permissions();
-                    perm.set_readonly(true);
-                    f.set_permissions(perm)
-                }
+        if kind.is_hard_link() || kind.is_symlink() {
+            let src = match self.link_name()? {
+                Some(name) => name.into_owned(),
                 None => {
-                    let mut perm = fs::metadata(dst)?.permissions();
-                    perm.set_readonly(true);
-                    fs::set_permissions(dst, perm)
+                    return Err(other(&format!(
+                        "hard link listed for {} but no link name found",
+                        String::from_utf8_lossy(self.header.as_bytes())
+                    )));
                 }
+            };
+            if src.iter().count() == 0 {
+                return Err(other(&format!(
+                    "symlink destination for {} is empty",
+                    String::from_utf8_lossy(self.header.as_bytes())
+                )));
             }
-        }
-
-        #[cfg(target_arch = "wasm32")]
-        #[allow(unused_variables)]
-        fn _set_perms(
-            dst: &Path,
-            f: Option<&mut std::fs::File>,
-            mode: u32,
-            _preserve: bool,
-        ) -> io::Result<()> {
-            Err(io::Error::new(io::ErrorKind::Other, "Not implemented"))
-        }
 
-        #[cfg(all(unix, feature = "xattr"))]
-        fn set_xattrs(me: &mut EntryFields, dst: &Path) -> io::Result<()> {
-            use std::ffi::OsStr;
-            use std::os::unix::prelude::*;
-
-            let exts = match me.pax_extensions() {
-                Ok(Some(e)) => e,
-                _ => return Ok(()),
-            };
-            let exts = exts
-                .filter_map(|e| e.ok())
-                .filter_map(|e| {
-                    let key = e.key_bytes();
-                    let prefix = b"SCHILY.xattr.";
-                    if key.starts_with(prefix) {
-                        Some((&key[prefix.len()..], e))
-                    } else {
-                        None
+            if kind.is_hard_link() {
+                // Hard links are resolved from `dst` like the paths of
+                // entries, and can't leave it. Absolute targets inside `dst`
+                // are accepted as well, like `unpack_in` does.
+                let src = self.hard_link_target(Cow::Owned(src))?;
+                let rel = src.strip_prefix(dst).unwrap_or(&src);
+                let mut src_components = Vec::new();
+                for part in rel.components() {
+                    match part {
+                        Component::Prefix(..) | Component::RootDir | Component::CurDir => {}
+                        Component::Normal(part) => src_components.push(part),
+                        Component::ParentDir => {
+                            return Err(other(&format!(
+                                "hard link target `{}` is outside of the destination",
+                                src.display()
+                            )));
+                        }
                     }
-                })
-                .map(|(key, e)| (OsStr::from_bytes(key), e.value_bytes()));
-
-            for (key, value) in exts {
-                xattr::set(dst, key, value).map_err(|e| {
-                    TarError::new(
+                }
+                let (src_name, src_parents) = match src_components.split_last() {
+                    Some(split) => split,
+                    None => return Err(other("hard link target is empty")),
+                };
+                let src_dir = root.open_beneath(src_parents, false)?;
+                let name: &OsStr = name;
+                let path = match self.prepare_overwrite_beneath(&parent, name, file_dst)? {
+                    Some(path) => path,
+                    None => return Ok(()),
+                };
+                let overwrite = path == name && self.overwrite.replaces();
+                let ret = parent
+                    .hard_link(&src_dir, src_name, &path, overwrite)
+                    .map_err(|err| {
+                        Error::new(
+                            err.kind(),
+                            format!(
+                                "{} when hard linking {} to {}",
+                                err,
+                                src.display(),
+                                file_dst.display()
+                            ),
+                        )
+                    });
+                finish_overwrite_beneath(&parent, &path, name, file_dst, ret)?;
+            } else {
+                let name: &OsStr = name;
+                let path = match self.prepare_overwrite_beneath(&parent, name, file_dst)? {
+                    Some(path) => path,
+                    None => return Ok(()),
+                };
+                let overwrite = path == name && self.overwrite.replaces();
+                parent.symlink(&src, &path, overwrite).map_err(|err| {
+                    Error::new(
+                        err.kind(),
                         format!(
-                            "failed to set extended \
-                             attributes to {}. \
-                             Xattrs: key={:?}, value={:?}.",
-                            dst.display(),
-                            key,
-                            String::from_utf8_lossy(value)
+                            "{} when symlinking {} to {}",
+                            err,
+                            src.display(),
+                            file_dst.display()
                         ),
-                        e,
                     )
                 })?;
+                let ret = (|| -> io::Result<()> {
+                    if self.preserve_mtime {
+                        if let Some(mtime) = self.unpack_mtime() {
+                            let atime = self.unpack_atime(mtime);
+                            parent.set_symlink_times(&path, atime, mtime).map_err(|e| {
+                                TarError::new(
+                                    format!("failed to set mtime for `{}`", file_dst.display()),
+                                    e,
+                                )
+                            })?;
+                        }
+                    }
+                    Ok(())
+                })();
+                finish_overwrite_beneath(&parent, &path, name, file_dst, ret)?;
             }
-
-            Ok(())
-        }
-        // Windows does not completely support posix xattrs
-        // https://en.wikipedia.org/wiki/Extended_file_attributes#Windows_NT
-        #[cfg(any(windows, not(feature = "xattr"), target_arch = "wasm32"))]
-        fn set_xattrs(_: &mut EntryFields, _: &Path) -> io::Result<()> {
-            Ok(())
+            return Ok(());
         }
+
+        let name: &OsStr = name;
+        let path = match self.prepare_overwrite_beneath(&parent, name, file_dst)? {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let overwrite = path == name && self.overwrite.replaces();
+        let ret = (|| -> io::Result<()> {
+            let mut f = (|| -> io::Result<fs::File> {
+                let mut f = parent.create_file(&path, overwrite)?;
+                self.unpack_data(&mut f)?;
+                Ok(f)
+            })()
+            .map_err(|e| {
+                let header = self.header.path_bytes();
+                TarError::new(
+                    format!(
+                        "failed to unpack `{}` into `{}`",
+                        String::from_utf8_lossy(&header),
+                        file_dst.display()
+                    ),
+                    e,
+                )
+            })?;
+            self.unpack_file_metadata(&mut f, file_dst, true)
+        })();
+        finish_overwrite_beneath(&parent, &path, name, file_dst, ret)
+    }
+
+    /// Like `prepare_overwrite`, for unpacking this entry to `name` in
+    /// `parent`.
+    #[cfg(target_os = "linux")]
+    fn prepare_overwrite_beneath(
+        &self,
+        parent: &Dir,
+        name: &OsStr,
+        file_dst: &Path,
+    ) -> io::Result<Option<OsString>> {
+        self.prepare_overwrite(
+            name,
+            parent.mtime(name),
+            &|name| parent.mtime(name).is_some(),
+            &|from, to| parent.rename(from, to),
+        )
+        .map_err(|e| TarError::new(format!("failed to back up `{}`", file_dst.display()), e).into())
     }
 
     fn ensure_dir_created(&self, dst: &Path, dir: &Path) -> io::Result<()> {
@@ -924,6 +2000,327 @@ impl<'a> EntryFields<'a> {
     }
 }
 
+/// Renames the entry unpacked to `path` to `dst` if it was unpacked to a
+/// temporary name, or removes it if unpacking it failed.
+fn finish_overwrite<T>(path: &Path, dst: &Path, ret: io::Result<T>) -> io::Result<T> {
+    if path == dst {
+        return ret;
+    }
+    let ret = ret.and_then(|t| {
+        fs::rename(path, dst)
+            .map_err(|e| TarError::new(format!("failed to rename to `{}`", dst.display()), e))?;
+        Ok(t)
+    });
+    if ret.is_err() {
+        let _ = fs::remove_file(path);
+    }
+    ret
+}
+
+/// Like `finish_overwrite`, for an entry unpacked to `path` in `parent`.
+#[cfg(target_os = "linux")]
+fn finish_overwrite_beneath<T>(
+    parent: &Dir,
+    path: &OsStr,
+    name: &OsStr,
+    file_dst: &Path,
+    ret: io::Result<T>,
+) -> io::Result<T> {
+    if path == name {
+        return ret;
+    }
+    let ret = ret.and_then(|t| {
+        parent.rename(path, name).map_err(|e| {
+            TarError::new(format!("failed to rename to `{}`", file_dst.display()), e)
+        })?;
+        Ok(t)
+    });
+    if ret.is_err() {
+        let _ = parent.remove_file(path);
+    }
+    ret
+}
+
+fn set_perms_ownerships(
+    dst: &Path,
+    f: Option<&mut std::fs::File>,
+    me: &EntryFields,
+    perms: bool,
+    ownerships: bool,
+) -> io::Result<()> {
+    // ownerships need to be set first to avoid stripping SUID bits in the permissions ...
+    if ownerships {
+        let (uid, gid) = me.owner_ids()?;
+        match me.ownership_xattr {
+            Some(ref name) => {
+                let value = me.stat_override(uid, gid)?;
+                set_ownership_xattr(dst, &f, name, &value)?;
+            }
+            None => set_ownerships(dst, &f, uid, gid)?,
+        }
+    }
+    // ... then set permissions, SUID bits set here is kept
+    if let Ok(mode) = me.header.mode() {
+        set_perms(dst, f, mode, perms)?;
+    }
+
+    Ok(())
+}
+
+/// Records the ownership of `dst` in the extended attribute `name` rather
+/// than changing it, see `Archive::set_ownership_xattr`.
+fn set_ownership_xattr(
+    dst: &Path,
+    f: &Option<&mut std::fs::File>,
+    name: &str,
+    value: &str,
+) -> Result<(), TarError> {
+    _set_ownership_xattr(dst, f, name, value).map_err(|e| {
+        TarError::new(
+            format!(
+                "failed to set ownerships to `{}` in xattr `{}` for `{}`",
+                value,
+                name,
+                dst.display()
+            ),
+            e,
+        )
+    })
+}
+
+#[cfg(all(unix, feature = "xattr"))]
+fn _set_ownership_xattr(
+    dst: &Path,
+    f: &Option<&mut std::fs::File>,
+    name: &str,
+    value: &str,
+) -> io::Result<()> {
+    match f {
+        Some(f) => xattr::FileExt::set_xattr(&**f, name, value.as_bytes()),
+        None => xattr::set(dst, name, value.as_bytes()),
+    }
+}
+
+#[cfg(not(all(unix, feature = "xattr")))]
+fn _set_ownership_xattr(
+    _dst: &Path,
+    _f: &Option<&mut std::fs::File>,
+    _name: &str,
+    _value: &str,
+) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "extended attributes are not supported",
+    ))
+}
+
+fn set_ownerships(
+    dst: &Path,
+    f: &Option<&mut std::fs::File>,
+    uid: u64,
+    gid: u64,
+) -> Result<(), TarError> {
+    _set_ownerships(dst, f, uid, gid).map_err(|e| {
+        TarError::new(
+            format!(
+                "failed to set ownerships to uid={:?}, gid={:?} \
+                 for `{}`",
+                uid,
+                gid,
+                dst.display()
+            ),
+            e,
+        )
+    })
+}
+
+#[cfg(unix)]
+fn _set_ownerships(
+    dst: &Path,
+    f: &Option<&mut std::fs::File>,
+    uid: u64,
+    gid: u64,
+) -> io::Result<()> {
+    use std::convert::TryInto;
+    use std::os::unix::prelude::*;
+
+    let uid: libc::uid_t = uid
+        .try_into()
+        .map_err(|_| io::Error::new(io::ErrorKind::Other, format!("UID {} is too large!", uid)))?;
+    let gid: libc::gid_t = gid
+        .try_into()
+        .map_err(|_| io::Error::new(io::ErrorKind::Other, format!("GID {} is too large!", gid)))?;
+    match f {
+        Some(f) => unsafe {
+            let fd = f.as_raw_fd();
+            if libc::fchown(fd, uid, gid) != 0 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(())
+            }
+        },
+        None => unsafe {
+            let path = std::ffi::CString::new(dst.as_os_str().as_bytes()).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("path contains null character: {:?}", e),
+                )
+            })?;
+            if libc::lchown(path.as_ptr(), uid, gid) != 0 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(())
+            }
+        },
+    }
+}
+
+// Windows does not support posix numeric ownership IDs
+#[cfg(any(windows, target_arch = "wasm32"))]
+fn _set_ownerships(_: &Path, _: &Option<&mut std::fs::File>, _: u64, _: u64) -> io::Result<()> {
+    Ok(())
+}
+
+fn set_perms(
+    dst: &Path,
+    f: Option<&mut std::fs::File>,
+    mode: u32,
+    preserve: bool,
+) -> Result<(), TarError> {
+    _set_perms(dst, f, mode, preserve).map_err(|e| {
+        TarError::new(
+            format!(
+                "failed to set permissions to {:o} \
+                 for `{}`",
+                mode,
+                dst.display()
+            ),
+            e,
+        )
+    })
+}
+
+#[cfg(unix)]
+fn _set_perms(
+    dst: &Path,
+    f: Option<&mut std::fs::File>,
+    mode: u32,
+    preserve: bool,
+) -> io::Result<()> {
+    use std::os::unix::prelude::*;
+
+    let mode = if preserve { mode } else { mode & 0o777 };
+    let perm = fs::Permissions::from_mode(mode as _);
+    match f {
+        Some(f) => f.set_permissions(perm),
+        None => fs::set_permissions(dst, perm),
+    }
+}
+
+#[cfg(windows)]
+fn _set_perms(
+    dst: &Path,
+    f: Option<&mut std::fs::File>,
+    mode: u32,
+    _preserve: bool,
+) -> io::Result<()> {
+    if mode & 0o200 == 0o200 {
+        return Ok(());
+    }
+    match f {
+        Some(f) => {
+            let mut perm = f.metadata()?.permissions();
+            perm.set_readonly(true);
+            f.set_permissions(perm)
+        }
+        None => {
+            let mut perm = fs::metadata(dst)?.permissions();
+            perm.set_readonly(true);
+            fs::set_permissions(dst, perm)
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+#[allow(unused_variables)]
+fn _set_perms(
+    dst: &Path,
+    f: Option<&mut std::fs::File>,
+    mode: u32,
+    _preserve: bool,
+) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Other, "Not implemented"))
+}
+
+#[cfg(all(unix, feature = "xattr"))]
+fn set_xattrs(me: &mut EntryFields, dst: &Path, f: Option<&fs::File>) -> io::Result<()> {
+    use std::ffi::OsStr;
+    use std::os::unix::prelude::*;
+
+    let exts = match me.pax_extensions() {
+        Ok(Some(e)) => e,
+        _ => return Ok(()),
+    };
+    let exts = exts
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let key = e.key_bytes();
+            let prefix = b"SCHILY.xattr.";
+            if key.starts_with(prefix) {
+                Some((&key[prefix.len()..], e))
+            } else {
+                None
+            }
+        })
+        .map(|(key, e)| (OsStr::from_bytes(key), e.value_bytes()));
+
+    for (key, value) in exts {
+        let ret = match f {
+            Some(f) => xattr::FileExt::set_xattr(f, key, value),
+            None => xattr::set(dst, key, value),
+        };
+        ret.map_err(|e| {
+            TarError::new(
+                format!(
+                    "failed to set extended \
+                     attributes to {}. \
+                     Xattrs: key={:?}, value={:?}.",
+                    dst.display(),
+                    key,
+                    String::from_utf8_lossy(value)
+                ),
+                e,
+            )
+        })?;
+    }
+
+    Ok(())
+}
+// Windows does not completely support posix xattrs
+// https://en.wikipedia.org/wiki/Extended_file_attributes#Windows_NT
+#[cfg(any(windows, not(feature = "xattr"), target_arch = "wasm32"))]
+fn set_xattrs(_: &mut EntryFields, _: &Path, _: Option<&fs::File>) -> io::Result<()> {
+    Ok(())
+}
+
+/// Converts a time read from an archive for `UnpackTarget::set_times`.
+fn system_time(time: FileTime) -> SystemTime {
+    let secs = time.unix_seconds();
+    let nanos = Duration::new(0, time.nanoseconds());
+    if secs >= 0 {
+        UNIX_EPOCH + Duration::from_secs(secs as u64) + nanos
+    } else {
+        UNIX_EPOCH - Duration::from_secs(secs.wrapping_neg() as u64) + nanos
+    }
+}
+
+fn time_to_secs(time: FileTime, field: &str) -> io::Result<u64> {
+    if time.unix_seconds() < 0 {
+        return Err(other(&format!("{} is before the Unix epoch", field)));
+    }
+    Ok(time.unix_seconds() as u64)
+}
+
 impl<'a> Read for EntryFields<'a> {
     fn read(&mut self, into: &mut [u8]) -> io::Result<usize> {
         loop {
@@ -946,3 +2343,39 @@ impl<'a> Read for EntryIo<'a> {
         }
     }
 }
+
+/// Returns whether `meta` is the metadata of the file at `path`.
+#[cfg(unix)]
+fn same_file(meta: &fs::Metadata, path: &Path) -> bool {
+    use std::os::unix::prelude::*;
+
+    match path.symlink_metadata() {
+        Ok(other) => meta.dev() == other.dev() && meta.ino() == other.ino(),
+        Err(_) => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn same_file(_meta: &fs::Metadata, path: &Path) -> bool {
+    path.symlink_metadata().is_ok()
+}
+
+/// Returns whether the data read from `data` is the contents of the file at
+/// `path`, which has the same size.
+fn same_contents(data: &mut dyn Read, path: &Path) -> io::Result<bool> {
+    let mut file = fs::File::open(path)?;
+    let mut expected = [0; 8192];
+    let mut found = [0; 8192];
+    loop {
+        let n = data.read(&mut expected)?;
+        if n == 0 {
+            return Ok(true);
+        }
+        match file.read_exact(&mut found[..n]) {
+            Ok(()) if expected[..n] == found[..n] => {}
+            Ok(()) => return Ok(false),
+            Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(false),
+            Err(e) => return Err(e),
+        }
+    }
+}
@@ -29,6 +29,11 @@ pub enum EntryType {
     GNULongLink,
     /// GNU extension - sparse file
     GNUSparse,
+    /// GNU extension - volume label of a multi-volume archive
+    GNUVolumeLabel,
+    /// GNU extension - continuation of a file from the previous volume of a
+    /// multi-volume archive
+    GNUMultiVolume,
     /// Global extended header
     XGlobalHeader,
     /// Extended Header
@@ -62,6 +67,8 @@ impl EntryType {
             b'L' => EntryType::GNULongName,
             b'K' => EntryType::GNULongLink,
             b'S' => EntryType::GNUSparse,
+            b'V' => EntryType::GNUVolumeLabel,
+            b'M' => EntryType::GNUMultiVolume,
             b => EntryType::__Nonexhaustive(b),
         }
     }
@@ -82,6 +89,8 @@ impl EntryType {
             EntryType::GNULongName => b'L',
             EntryType::GNULongLink => b'K',
             EntryType::GNUSparse => b'S',
+            EntryType::GNUVolumeLabel => b'V',
+            EntryType::GNUMultiVolume => b'M',
             EntryType::__Nonexhaustive(b) => b,
         }
     }
@@ -181,6 +190,17 @@ impl EntryType {
         self == &EntryType::GNULongLink
     }
 
+    /// Returns whether this type represents a GNU volume label header.
+    pub fn is_gnu_volume_label(&self) -> bool {
+        self == &EntryType::GNUVolumeLabel
+    }
+
+    /// Returns whether this type represents a GNU multi-volume continuation
+    /// header.
+    pub fn is_gnu_multi_volume(&self) -> bool {
+        self == &EntryType::GNUMultiVolume
+    }
+
     /// Returns whether this type represents a GNU long name header.
     pub fn is_pax_global_extensions(&self) -> bool {
         self == &EntryType::XGlobalHeader
@@ -186,7 +186,12 @@ impl Header {
 
     fn is_gnu(&self) -> bool {
         let ustar = unsafe { cast::<_, UstarHeader>(self) };
-        ustar.magic[..] == b"ustar "[..] && ustar.version[..] == b" \0"[..]
+        if ustar.magic[..] == b"ustar "[..] && ustar.version[..] == b" \0"[..] {
+            return true;
+        }
+        // GNU tar writes the continuation headers of multi-volume archives
+        // without any magic.
+        ustar.typeflag[0] == b'M' && ustar.magic == [0; 6] && ustar.version == [0; 2]
     }
 
     /// View this archive header as a raw "old" archive header.
@@ -1259,6 +1264,25 @@ impl GnuHeader {
         })
     }
 
+    /// Returns the offset within the file at which the data of this header
+    /// starts.
+    ///
+    /// This is applicable for GNU multi-volume continuation headers, which
+    /// describe the remainder of a file split off from the previous volume.
+    pub fn offset(&self) -> io::Result<u64> {
+        num_field_wrapper_from(&self.offset).map_err(|err| {
+            io::Error::new(
+                err.kind(),
+                format!("{} when getting offset for {}", err, self.fullname_lossy()),
+            )
+        })
+    }
+
+    /// Encodes the `offset` provided into this header.
+    pub fn set_offset(&mut self, offset: u64) {
+        num_field_wrapper_into(&mut self.offset, offset);
+    }
+
     /// Indicates whether this header will be followed by additional
     /// sparse-header records.
     ///
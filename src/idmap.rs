@@ -0,0 +1,83 @@
+/// A mapping of user or group ids applied when preserving ownerships, like
+/// the `uid_map` and `gid_map` of a Linux user namespace. See
+/// `Archive::set_uid_map` and `Archive::set_gid_map`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::fs::File;
+/// use tar::{Archive, IdMap};
+///
+/// // Shift the ids 0-65535 of a container layer to 100000-165535.
+/// let mut map = IdMap::new();
+/// map.add_range(0, 100000, 65536);
+///
+/// let mut ar = Archive::new(File::open("layer.tar").unwrap());
+/// ar.set_preserve_ownerships(true);
+/// ar.set_numeric_owner(true);
+/// ar.set_uid_map(map.clone());
+/// ar.set_gid_map(map);
+/// ar.unpack("rootfs").unwrap();
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IdMap {
+    ranges: Vec<(u64, u64, u64)>,
+    unmapped: UnmappedId,
+}
+
+/// What to do with ids which aren't covered by any range of an `IdMap`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum UnmappedId {
+    /// Fail to unpack the entry. This is the default.
+    Error,
+    /// Use the given id instead, such as the overflow id `65534`.
+    Fallback(u64),
+}
+
+impl IdMap {
+    /// Creates a mapping without any ranges, under which every id is
+    /// unmapped.
+    pub fn new() -> IdMap {
+        IdMap {
+            ranges: Vec::new(),
+            unmapped: UnmappedId::Error,
+        }
+    }
+
+    /// Maps the `count` ids starting at `inside`, as found in archives, to
+    /// the ones starting at `outside`.
+    ///
+    /// Ranges are checked in the order they were added and the first one
+    /// containing an id is used.
+    pub fn add_range(&mut self, inside: u64, outside: u64, count: u64) -> &mut IdMap {
+        self.ranges.push((inside, outside, count));
+        self
+    }
+
+    /// Sets what happens to ids which aren't covered by any range.
+    pub fn set_unmapped(&mut self, unmapped: UnmappedId) -> &mut IdMap {
+        self.unmapped = unmapped;
+        self
+    }
+
+    /// Returns the id `id` is mapped to, or `None` if it isn't covered by
+    /// any range and there's no fallback.
+    pub fn map(&self, id: u64) -> Option<u64> {
+        let mapped = self.ranges.iter().find_map(|&(inside, outside, count)| {
+            let offset = id.checked_sub(inside).filter(|&offset| offset < count)?;
+            outside.checked_add(offset)
+        });
+        match (mapped, self.unmapped) {
+            (Some(mapped), _) => Some(mapped),
+            (None, UnmappedId::Fallback(id)) => Some(id),
+            (None, UnmappedId::Error) => None,
+        }
+    }
+}
+
+impl Default for IdMap {
+    fn default() -> IdMap {
+        IdMap::new()
+    }
+}
@@ -23,23 +23,66 @@
 
 use std::io::{Error, ErrorKind};
 
-pub use crate::archive::{Archive, Entries};
+pub use crate::archive::{Archive, Entries, SkippedRegion};
+#[cfg(feature = "async")]
+pub use crate::async_archive::{AsyncArchive, AsyncEntry};
+#[cfg(feature = "async")]
+pub use crate::async_builder::AsyncBuilder;
+#[cfg(feature = "async")]
+pub use crate::async_streamer::AsyncStreamer;
 pub use crate::builder::Builder;
+pub use crate::compare::{Difference, DifferenceKind};
+pub use crate::diff::{ArchiveChange, ChangeKind, MetadataChange};
 pub use crate::entry::{Entry, Unpacked};
 pub use crate::entry_type::EntryType;
 pub use crate::header::GnuExtSparseHeader;
 pub use crate::header::{GnuHeader, GnuSparseHeader, Header, HeaderMode, OldHeader, UstarHeader};
+pub use crate::idmap::{IdMap, UnmappedId};
+pub use crate::limits::{LimitError, LimitKind};
+pub use crate::overwrite::OverwritePolicy;
+pub use crate::owner::{OwnerResolver, SystemOwnerResolver};
 pub use crate::pax::{PaxExtension, PaxExtensions};
+pub use crate::plan::{PlannedAction, PlannedEntry, UnpackPlan};
+pub use crate::portability::{PortabilityChecker, PortabilityIssue, PortabilityIssueKind};
 pub use crate::streamer::Streamer;
+pub use crate::target::UnpackTarget;
+pub use crate::validate::{Finding, FindingKind, ValidationReport};
+pub use crate::volume::{MultiVolume, MultiVolumeWriter};
+pub use crate::whiteout::Whiteouts;
 
+#[cfg(all(target_os = "linux", feature = "xattr"))]
+mod acl;
 mod archive;
+#[cfg(feature = "async")]
+mod async_archive;
+#[cfg(feature = "async")]
+mod async_builder;
+#[cfg(feature = "async")]
+mod async_streamer;
+#[cfg(target_os = "linux")]
+mod beneath;
 mod builder;
+mod compare;
+mod diff;
 mod entry;
 mod entry_type;
 mod error;
 mod header;
+mod idmap;
+mod limits;
+mod overwrite;
+mod owner;
 mod pax;
+mod plan;
+mod portability;
+mod rewrite;
+mod squash;
+mod stat_override;
 mod streamer;
+mod target;
+mod validate;
+mod volume;
+mod whiteout;
 
 fn other(msg: &str) -> Error {
     Error::new(ErrorKind::Other, msg)
@@ -0,0 +1,154 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+/// Limits on the resources an archive may use while it's read, see the
+/// `set_max_*` methods of `Archive`.
+#[derive(Clone, Debug)]
+pub(crate) struct Limits {
+    pub(crate) metadata_size: u64,
+    pub(crate) entries: u64,
+    pub(crate) total_size: u64,
+    pub(crate) sparse_ratio: u64,
+    pub(crate) path_len: u64,
+    pub(crate) path_depth: u64,
+}
+
+/// The error returned when an archive exceeds one of the limits set on an
+/// `Archive`.
+///
+/// It's returned wrapped in an `io::Error`, from which it can be retrieved
+/// with `get_ref` and `downcast_ref`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LimitError {
+    kind: LimitKind,
+    path: Vec<u8>,
+    position: u64,
+    value: u64,
+    limit: u64,
+}
+
+/// The kinds of limits which can be set on an `Archive`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum LimitKind {
+    /// The size of the GNU long name, pax extension or sparse map entries
+    /// describing a member, see `Archive::set_max_metadata_size`.
+    MetadataSize,
+    /// The number of entries, see `Archive::set_max_entries`.
+    Entries,
+    /// The total size of the data of all members, see
+    /// `Archive::set_max_total_size`.
+    TotalSize,
+    /// The ratio of the size of a sparse file to the size it takes up in the
+    /// archive, see `Archive::set_max_sparse_ratio`.
+    SparseRatio,
+    /// The length of the path or link name of a member, see
+    /// `Archive::set_max_path_len`.
+    PathLength,
+    /// The number of components of the path of a member, see
+    /// `Archive::set_max_path_depth`.
+    PathDepth,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            metadata_size: u64::MAX,
+            entries: u64::MAX,
+            total_size: u64::MAX,
+            sparse_ratio: u64::MAX,
+            path_len: u64::MAX,
+            path_depth: u64::MAX,
+        }
+    }
+}
+
+impl Limits {
+    /// Returns an error if `value` exceeds the limit of the given kind for the
+    /// entry with the path `path` whose header is at `position`.
+    pub(crate) fn check(
+        &self,
+        kind: LimitKind,
+        value: u64,
+        path: &[u8],
+        position: u64,
+    ) -> io::Result<()> {
+        let limit = match kind {
+            LimitKind::MetadataSize => self.metadata_size,
+            LimitKind::Entries => self.entries,
+            LimitKind::TotalSize => self.total_size,
+            LimitKind::SparseRatio => self.sparse_ratio,
+            LimitKind::PathLength => self.path_len,
+            LimitKind::PathDepth => self.path_depth,
+        };
+        if value <= limit {
+            return Ok(());
+        }
+        let err = LimitError {
+            kind,
+            path: path.to_vec(),
+            position,
+            value,
+            limit,
+        };
+        Err(io::Error::new(io::ErrorKind::Other, err))
+    }
+}
+
+impl LimitError {
+    /// Returns the kind of the limit which was exceeded.
+    pub fn kind(&self) -> LimitKind {
+        self.kind
+    }
+
+    /// Returns the path of the entry which exceeded the limit.
+    pub fn path_bytes(&self) -> &[u8] {
+        &self.path
+    }
+
+    /// Returns the position, in bytes, of the header of the entry which
+    /// exceeded the limit.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Returns the value which exceeded the limit.
+    pub fn value(&self) -> u64 {
+        self.value
+    }
+
+    /// Returns the limit which was exceeded.
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    /// Returns whether `err` was caused by exceeding a limit.
+    pub(crate) fn is_limit_error(err: &io::Error) -> bool {
+        matches!(err.get_ref(), Some(e) if e.is::<LimitError>())
+    }
+}
+
+impl error::Error for LimitError {}
+
+impl fmt::Display for LimitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let what = match self.kind {
+            LimitKind::MetadataSize => "metadata size",
+            LimitKind::Entries => "number of entries",
+            LimitKind::TotalSize => "total size",
+            LimitKind::SparseRatio => "sparse expansion ratio",
+            LimitKind::PathLength => "path length",
+            LimitKind::PathDepth => "path depth",
+        };
+        write!(
+            f,
+            "entry `{}` at offset {} exceeds the {} limit: {} > {}",
+            String::from_utf8_lossy(&self.path),
+            self.position,
+            what,
+            self.value,
+            self.limit
+        )
+    }
+}
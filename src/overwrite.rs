@@ -0,0 +1,69 @@
+use std::cmp;
+use std::ffi::{OsStr, OsString};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// What to do when a file or symbolic link being unpacked already exists,
+/// see `Archive::set_overwrite_policy`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum OverwritePolicy {
+    /// Return an error.
+    Error,
+    /// Remove the existing file and create a new one in its place. This is
+    /// the default.
+    Replace,
+    /// Keep the existing file if it was modified more recently than the
+    /// entry, and replace it otherwise.
+    KeepNewer,
+    /// Keep the existing file, silently skipping the entry.
+    KeepOld,
+    /// Rename the existing file to a numbered backup, `name.~1~`,
+    /// `name.~2~` and so on, using the first number which isn't taken yet.
+    Backup,
+    /// Unpack to a temporary name in the same directory and rename the file
+    /// into place once it is complete, so that readers never see a partially
+    /// written file.
+    Atomic,
+}
+
+impl OverwritePolicy {
+    /// Returns whether an existing file is removed before creating a new one.
+    pub(crate) fn replaces(self) -> bool {
+        matches!(self, OverwritePolicy::Replace | OverwritePolicy::KeepNewer)
+    }
+}
+
+/// Returns the first free numbered backup name for `name`.
+pub(crate) fn backup_name(name: &OsStr, exists: &dyn Fn(&OsStr) -> bool) -> OsString {
+    let mut n = 1u64;
+    loop {
+        let mut backup = name.to_os_string();
+        backup.push(format!(".~{}~", n));
+        if !exists(&backup) {
+            return backup;
+        }
+        n += 1;
+    }
+}
+
+/// The longest file name most filesystems accept, in bytes.
+const NAME_MAX: usize = 255;
+
+/// Returns a hidden name to unpack `name` to before it's renamed into place.
+pub(crate) fn temp_name(name: &OsStr) -> OsString {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let suffix = format!(
+        ".tar-tmp-{}-{}",
+        process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    );
+    // The suffix makes the name unique, `name` only makes it recognizable,
+    // so it's shortened to keep names near `NAME_MAX` from becoming too long.
+    let name = name.to_string_lossy();
+    let mut end = cmp::min(name.len(), NAME_MAX - 1 - suffix.len());
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!(".{}{}", &name[..end], suffix).into()
+}
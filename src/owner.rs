@@ -0,0 +1,113 @@
+#[cfg(unix)]
+use std::ffi::{CStr, CString};
+
+/// Looks up the numeric ids of users and groups by name when restoring the
+/// ownership of unpacked files, see `Archive::set_owner_resolver`.
+///
+/// Returning `None` makes unpacking fall back to the numeric id recorded in
+/// the archive.
+pub trait OwnerResolver: Send + Sync {
+    /// Returns the id of the user called `name`.
+    fn uid(&self, name: &str) -> Option<u64>;
+
+    /// Returns the id of the group called `name`.
+    fn gid(&self, name: &str) -> Option<u64>;
+}
+
+/// Resolves names with the passwd and group databases of this system, like
+/// `getpwnam` and `getgrnam` do. This is the resolver used by default.
+///
+/// No names are resolved on platforms other than Unix.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemOwnerResolver;
+
+impl OwnerResolver for SystemOwnerResolver {
+    fn uid(&self, name: &str) -> Option<u64> {
+        lookup_id(name, true).map(u64::from)
+    }
+
+    fn gid(&self, name: &str) -> Option<u64> {
+        lookup_id(name, false).map(u64::from)
+    }
+}
+
+/// Returns the id of the user, or group if `user` isn't set, called `name`.
+#[cfg(unix)]
+pub(crate) fn lookup_id(name: &str, user: bool) -> Option<u32> {
+    let name = CString::new(name).ok()?;
+    let mut buf = vec![0 as libc::c_char; 4096];
+    loop {
+        let ret = unsafe {
+            if user {
+                let mut pwd: libc::passwd = std::mem::zeroed();
+                let mut result = std::ptr::null_mut();
+                let ret = libc::getpwnam_r(
+                    name.as_ptr(),
+                    &mut pwd,
+                    buf.as_mut_ptr(),
+                    buf.len(),
+                    &mut result,
+                );
+                if ret == 0 && !result.is_null() {
+                    return Some(pwd.pw_uid);
+                }
+                ret
+            } else {
+                let mut grp: libc::group = std::mem::zeroed();
+                let mut result = std::ptr::null_mut();
+                let ret = libc::getgrnam_r(
+                    name.as_ptr(),
+                    &mut grp,
+                    buf.as_mut_ptr(),
+                    buf.len(),
+                    &mut result,
+                );
+                if ret == 0 && !result.is_null() {
+                    return Some(grp.gr_gid);
+                }
+                ret
+            }
+        };
+        if ret != libc::ERANGE || buf.len() >= 1 << 20 {
+            return None;
+        }
+        buf.resize(buf.len() * 2, 0);
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn lookup_id(_name: &str, _user: bool) -> Option<u32> {
+    None
+}
+
+/// Returns the name of the user, or group if `user` isn't set, with the id
+/// `id`.
+#[cfg(unix)]
+pub(crate) fn lookup_name(id: u32, user: bool) -> Option<String> {
+    let mut buf = vec![0 as libc::c_char; 4096];
+    loop {
+        let ret = unsafe {
+            if user {
+                let mut pwd: libc::passwd = std::mem::zeroed();
+                let mut result = std::ptr::null_mut();
+                let ret = libc::getpwuid_r(id, &mut pwd, buf.as_mut_ptr(), buf.len(), &mut result);
+                if ret == 0 && !result.is_null() {
+                    return CStr::from_ptr(pwd.pw_name).to_str().ok().map(String::from);
+                }
+                ret
+            } else {
+                let mut grp: libc::group = std::mem::zeroed();
+                let mut result = std::ptr::null_mut();
+                let ret = libc::getgrgid_r(id, &mut grp, buf.as_mut_ptr(), buf.len(), &mut result);
+                if ret == 0 && !result.is_null() {
+                    return CStr::from_ptr(grp.gr_name).to_str().ok().map(String::from);
+                }
+                ret
+            }
+        };
+        if ret != libc::ERANGE || buf.len() >= 1 << 20 {
+            return None;
+        }
+        buf.resize(buf.len() * 2, 0);
+    }
+}
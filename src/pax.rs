@@ -2,6 +2,8 @@ use std::io;
 use std::slice;
 use std::str;
 
+use filetime::FileTime;
+
 use crate::other;
 
 /// An iterator over the pax extensions in an archive entry.
@@ -53,6 +55,60 @@ pub fn pax_extensions_size(a: &[u8]) -> Option<u64> {
     None
 }
 
+/// Encodes a pax record, `<length> <key>=<value>\n`, where the length
+/// counts the whole record including its own digits.
+pub(crate) fn pax_record(key: &str, value: &[u8]) -> Vec<u8> {
+    // One byte each for the space, `=` and newline.
+    let rest = key.len() + value.len() + 3;
+    let mut len = rest + rest.to_string().len();
+    if len.to_string().len() > rest.to_string().len() {
+        len += 1;
+    }
+    let mut record = format!("{} {}=", len, key).into_bytes();
+    record.extend_from_slice(value);
+    record.push(b'\n');
+    record
+}
+
+/// Parses the decimal value of a numeric pax record such as `uid` or `size`.
+pub fn parse_pax_num(value: &[u8]) -> Option<u64> {
+    str::from_utf8(value).ok()?.parse::<u64>().ok()
+}
+
+/// Parses the value of a pax time record such as `mtime`, which is a decimal
+/// number of seconds since the epoch with an optional fractional part.
+pub fn parse_pax_time(value: &[u8]) -> Option<FileTime> {
+    let value = str::from_utf8(value).ok()?;
+    let (negative, value) = match value.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, value),
+    };
+    let (secs, frac) = match value.find('.') {
+        Some(i) => (&value[..i], &value[i + 1..]),
+        None => (value, ""),
+    };
+    if secs.is_empty() || !secs.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    if !frac.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let secs = secs.parse::<i64>().ok()?;
+    // Only nanosecond precision is kept, extra digits are truncated.
+    let mut nanos = 0u32;
+    for i in 0..9 {
+        let digit = frac.as_bytes().get(i).map(|b| b - b'0').unwrap_or(0);
+        nanos = nanos * 10 + digit as u32;
+    }
+    if !negative {
+        Some(FileTime::from_unix_time(secs, nanos))
+    } else if nanos == 0 {
+        Some(FileTime::from_unix_time(-secs, 0))
+    } else {
+        Some(FileTime::from_unix_time(-secs - 1, 1_000_000_000 - nanos))
+    }
+}
+
 impl<'entry> Iterator for PaxExtensions<'entry> {
     type Item = io::Result<PaxExtension<'entry>>;
 
@@ -0,0 +1,333 @@
+use std::collections::{HashMap, VecDeque};
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use filetime::FileTime;
+
+use crate::EntryType;
+
+/// The most symbolic links followed while resolving a path, like the
+/// `MAXSYMLINKS` limit of Linux.
+const MAX_SYMLINKS: usize = 40;
+
+/// The changes unpacking an archive would make to a destination directory,
+/// as returned by `Archive::plan_unpack`.
+///
+/// A plan is built without writing anything to the destination. Entries are
+/// planned in the order `Archive::unpack` unpacks them, and entries planned
+/// earlier are taken into account for later ones, for example when a file is
+/// unpacked twice or a path leads through a symbolic link from the archive.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::fs::File;
+/// use tar::Archive;
+///
+/// let mut ar = Archive::new(File::open("foo.tar").unwrap());
+/// let plan = ar.plan_unpack("foo").unwrap();
+/// for entry in plan.entries() {
+///     println!("{:?} {:?}", entry.action(), entry.dst());
+/// }
+/// println!("{} bytes", plan.total_size());
+/// ```
+#[derive(Clone, Debug)]
+pub struct UnpackPlan {
+    dst: PathBuf,
+    canon_dst: Option<PathBuf>,
+    fresh: bool,
+    entries: Vec<PlannedEntry>,
+    total_size: u64,
+    /// Files planned so far, relative to the destination, with whether
+    /// they're directories and their modification times if they're preserved.
+    created: HashMap<PathBuf, (bool, Option<FileTime>)>,
+    /// Symbolic links planned so far and their targets.
+    symlinks: HashMap<PathBuf, PathBuf>,
+}
+
+/// What unpacking an entry would do, see `UnpackPlan`.
+#[derive(Clone, Debug)]
+pub struct PlannedEntry {
+    path: Vec<u8>,
+    entry_type: EntryType,
+    link_name: Option<PathBuf>,
+    dst: Option<PathBuf>,
+    action: PlannedAction,
+    size: u64,
+}
+
+/// The action planned for an entry, see `PlannedEntry::action`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum PlannedAction {
+    /// The entry would be created.
+    Create,
+    /// The entry is a directory which exists already. Its metadata would be
+    /// updated.
+    Existing,
+    /// An existing file would be replaced by the entry.
+    Overwrite,
+    /// An existing file would be renamed to a backup and the entry created
+    /// in its place, see `OverwritePolicy::Backup`.
+    Backup,
+    /// An existing file would be kept and the entry skipped, see
+    /// `OverwritePolicy::KeepOld` and `OverwritePolicy::KeepNewer`.
+    Keep,
+    /// Unpacking would fail because a file exists already.
+    Conflict,
+    /// Unpacking would fail because the target of a hard link doesn't exist.
+    MissingLinkTarget,
+    /// The entry would be skipped because its path contains `..`.
+    SkipTraversal,
+    /// The entry would be skipped by the path rewriting configured on the
+    /// archive.
+    SkipRewrite,
+    /// Unpacking would fail because the entry, or the target of a hard link,
+    /// resolves to a location outside of the destination.
+    RejectOutside,
+}
+
+impl UnpackPlan {
+    /// Creates an empty plan for unpacking into `dst`.
+    ///
+    /// Entries are added to it with `Entry::plan_unpack_in`.
+    pub fn new<P: AsRef<Path>>(dst: P) -> UnpackPlan {
+        let dst = dst.as_ref();
+        UnpackPlan {
+            dst: dst.to_path_buf(),
+            canon_dst: dst.canonicalize().ok(),
+            fresh: false,
+            entries: Vec::new(),
+            total_size: 0,
+            created: HashMap::new(),
+            symlinks: HashMap::new(),
+        }
+    }
+
+    /// Returns the destination this plan unpacks into.
+    pub fn dst(&self) -> &Path {
+        &self.dst
+    }
+
+    /// Returns the planned entries, in the order they would be unpacked.
+    pub fn entries(&self) -> &[PlannedEntry] {
+        &self.entries
+    }
+
+    /// Returns the number of bytes of file data which would be written.
+    pub fn total_size(&self) -> u64 {
+        self.total_size
+    }
+
+    /// Returns whether unpacking would fail, because an entry is planned as
+    /// `PlannedAction::Conflict`, `PlannedAction::MissingLinkTarget` or
+    /// `PlannedAction::RejectOutside`.
+    pub fn would_fail(&self) -> bool {
+        self.entries.iter().any(|e| {
+            matches!(
+                e.action,
+                PlannedAction::Conflict
+                    | PlannedAction::MissingLinkTarget
+                    | PlannedAction::RejectOutside
+            )
+        })
+    }
+
+    /// Plans for a destination which starts out empty, as with
+    /// `Archive::set_transactional`.
+    pub(crate) fn set_fresh(&mut self, fresh: bool) {
+        self.fresh = fresh;
+    }
+
+    pub(crate) fn push(
+        &mut self,
+        path: Vec<u8>,
+        entry_type: EntryType,
+        link_name: Option<PathBuf>,
+        rel: Option<&Path>,
+        action: PlannedAction,
+        size: u64,
+    ) {
+        let writes_data = !entry_type.is_dir()
+            && !entry_type.is_symlink()
+            && !entry_type.is_hard_link()
+            && matches!(
+                action,
+                PlannedAction::Create | PlannedAction::Overwrite | PlannedAction::Backup
+            );
+        if writes_data {
+            self.total_size = self.total_size.saturating_add(size);
+        }
+        self.entries.push(PlannedEntry {
+            path,
+            entry_type,
+            link_name,
+            dst: rel.map(|rel| self.dst.join(rel)),
+            action,
+            size,
+        });
+    }
+
+    /// Resolves `rel` through the symbolic links planned so far and those in
+    /// the destination, returning `None` if it leads outside of the
+    /// destination. The last component isn't resolved, like unpacking
+    /// doesn't follow it either.
+    pub(crate) fn resolve(&self, rel: &Path) -> Option<PathBuf> {
+        let mut resolved = PathBuf::new();
+        let mut pending = rel
+            .iter()
+            .map(|c| c.to_os_string())
+            .collect::<VecDeque<OsString>>();
+        let mut followed = 0;
+        while let Some(part) = pending.pop_front() {
+            if part == ".." {
+                if !resolved.pop() {
+                    return None;
+                }
+                continue;
+            }
+            if part == "." {
+                continue;
+            }
+            let next = resolved.join(&part);
+            let target = match self.symlinks.get(&next) {
+                Some(target) if !pending.is_empty() => target,
+                _ => {
+                    resolved = next;
+                    continue;
+                }
+            };
+            followed += 1;
+            if followed > MAX_SYMLINKS {
+                return None;
+            }
+            let target = if target.is_absolute() {
+                resolved = PathBuf::new();
+                self.strip_dst(target)?
+            } else {
+                target.as_path()
+            };
+            for part in target.iter().rev() {
+                pending.push_front(part.to_os_string());
+            }
+        }
+
+        if !self.fresh && !self.inside_dst(resolved.parent().unwrap_or(&resolved)) {
+            return None;
+        }
+        Some(resolved)
+    }
+
+    /// Returns the path of the target of a hard link relative to the
+    /// destination, or `None` if it's outside of it.
+    pub(crate) fn resolve_link(&self, target: &Path) -> Option<PathBuf> {
+        let target = if target.is_absolute() {
+            self.strip_dst(target)?
+        } else {
+            target
+        };
+        let mut rel = PathBuf::new();
+        for part in target.components() {
+            match part {
+                Component::Prefix(..) | Component::RootDir | Component::CurDir => {}
+                Component::Normal(part) => rel.push(part),
+                Component::ParentDir => {
+                    if !rel.pop() {
+                        return None;
+                    }
+                }
+            }
+        }
+        self.resolve(&rel)
+    }
+
+    /// Returns whether something exists at `rel`, whether it's a directory
+    /// and its modification time if it's known.
+    pub(crate) fn existing(&self, rel: &Path) -> Option<(bool, Option<FileTime>)> {
+        if let Some(existing) = self.created.get(rel) {
+            return Some(*existing);
+        }
+        if self.fresh {
+            return None;
+        }
+        let path = self.dst.join(rel);
+        let meta = path.symlink_metadata().ok()?;
+        // Existing directories are used through symbolic links as well.
+        let is_dir = path.metadata().map(|m| m.is_dir()).unwrap_or(false);
+        Some((is_dir, Some(FileTime::from_last_modification_time(&meta))))
+    }
+
+    /// Records that `rel` would be created, as a symbolic link to `link` if
+    /// given.
+    pub(crate) fn create(
+        &mut self,
+        rel: &Path,
+        is_dir: bool,
+        mtime: Option<FileTime>,
+        link: Option<&Path>,
+    ) {
+        self.created.insert(rel.to_path_buf(), (is_dir, mtime));
+        match link {
+            Some(link) => self.symlinks.insert(rel.to_path_buf(), link.to_path_buf()),
+            None => self.symlinks.remove(rel),
+        };
+    }
+
+    fn strip_dst<'a>(&self, path: &'a Path) -> Option<&'a Path> {
+        path.strip_prefix(&self.dst)
+            .ok()
+            .or_else(|| path.strip_prefix(self.canon_dst.as_ref()?).ok())
+    }
+
+    /// Checks that the closest existing ancestor of `rel` in the destination
+    /// is inside of it once symbolic links are resolved.
+    fn inside_dst(&self, rel: &Path) -> bool {
+        let canon_dst = match self.canon_dst {
+            Some(ref canon_dst) => canon_dst,
+            None => return true,
+        };
+        let mut ancestor = self.dst.join(rel);
+        loop {
+            if let Ok(canon) = fs::canonicalize(&ancestor) {
+                return canon.starts_with(canon_dst);
+            }
+            if !ancestor.pop() {
+                return true;
+            }
+        }
+    }
+}
+
+impl PlannedEntry {
+    /// Returns the path of the entry in the archive.
+    pub fn path_bytes(&self) -> &[u8] {
+        &self.path
+    }
+
+    /// Returns the type of the entry.
+    pub fn entry_type(&self) -> EntryType {
+        self.entry_type
+    }
+
+    /// Returns the target of the entry if it's a symbolic or hard link.
+    pub fn link_name(&self) -> Option<&Path> {
+        self.link_name.as_deref()
+    }
+
+    /// Returns the path the entry would be unpacked to, which is `None` for
+    /// entries which are skipped because of their path.
+    pub fn dst(&self) -> Option<&Path> {
+        self.dst.as_deref()
+    }
+
+    /// Returns what unpacking the entry would do.
+    pub fn action(&self) -> PlannedAction {
+        self.action
+    }
+
+    /// Returns the size of the data of the entry.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
@@ -0,0 +1,309 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Read;
+use std::str;
+
+#[cfg(feature = "unicode-normalization")]
+use unicode_normalization::UnicodeNormalization;
+
+use crate::{Entry, EntryType};
+
+/// The longest path, in UTF-16 code units, Windows accepts without the `\\?\`
+/// prefix. `MAX_PATH` is 260 including the terminating NUL.
+const WINDOWS_MAX_PATH: usize = 259;
+
+/// The longest file name, in bytes, most Unix filesystems accept.
+const NAME_MAX: usize = 255;
+
+/// The longest path, in bytes, macOS accepts.
+const MACOS_PATH_MAX: usize = 1023;
+
+/// Names Windows reserves for devices, in any case and with any extension.
+const WINDOWS_RESERVED: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Checks the members of an archive for names and types which can't be
+/// extracted faithfully on Windows or macOS.
+///
+/// A checker remembers the paths it has seen so that it can report members
+/// whose paths only differ in case or Unicode normalization, which would
+/// overwrite each other on case-insensitive or normalizing filesystems.
+/// Differences in normalization are only found with the
+/// `unicode-normalization` feature enabled.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::fs::File;
+/// use tar::{Archive, PortabilityChecker};
+///
+/// let mut ar = Archive::new(File::open("foo.tar").unwrap());
+/// let mut checker = PortabilityChecker::new();
+/// for entry in ar.entries().unwrap() {
+///     checker.check_entry(&entry.unwrap());
+/// }
+/// for issue in checker.issues() {
+///     println!("{}", issue);
+/// }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct PortabilityChecker {
+    seen: HashMap<String, Vec<u8>>,
+    issues: Vec<PortabilityIssue>,
+}
+
+/// A portability problem found by a `PortabilityChecker`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PortabilityIssue {
+    kind: PortabilityIssueKind,
+    path: Vec<u8>,
+    message: String,
+}
+
+/// The kinds of problems reported by a `PortabilityChecker`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum PortabilityIssueKind {
+    /// The path only differs in case from the path of a previous member.
+    CaseCollision,
+    /// The path only differs in Unicode normalization, and possibly case,
+    /// from the path of a previous member. Only reported with the
+    /// `unicode-normalization` feature enabled.
+    NormalizationCollision,
+    /// A component of the path is a name Windows reserves for devices, such
+    /// as `CON` or `aux.c`.
+    ReservedName,
+    /// The path contains a character which isn't allowed in Windows file
+    /// names, such as `:` or `\`.
+    ReservedCharacter,
+    /// A component of the path ends with a dot or a space, which Windows
+    /// strips.
+    TrailingDotOrSpace,
+    /// The path isn't valid UTF-8, so it can't be represented on Windows.
+    NonUnicode,
+    /// The path, or one of its components, is longer than common limits.
+    PathTooLong,
+    /// The member is a symbolic link, device or FIFO, which can't be created
+    /// on Windows without special privileges, or at all.
+    UnsupportedEntryType,
+}
+
+impl PortabilityChecker {
+    /// Creates a checker which hasn't seen any members yet.
+    pub fn new() -> PortabilityChecker {
+        PortabilityChecker::default()
+    }
+
+    /// Checks the path and type of an entry read from an archive.
+    pub fn check_entry<R: Read>(&mut self, entry: &Entry<R>) {
+        let entry_type = entry.header().entry_type();
+        self.check(&entry.path_bytes(), entry_type);
+    }
+
+    /// Checks a member with the given path and type.
+    pub fn check(&mut self, path: &[u8], entry_type: EntryType) {
+        let path = trim_path(path);
+        if path.is_empty() || is_extension(entry_type) {
+            return;
+        }
+
+        match entry_type {
+            EntryType::Symlink => self.push(
+                PortabilityIssueKind::UnsupportedEntryType,
+                path,
+                "symbolic links require special privileges on Windows".to_string(),
+            ),
+            EntryType::Char | EntryType::Block | EntryType::Fifo => self.push(
+                PortabilityIssueKind::UnsupportedEntryType,
+                path,
+                format!("{:?} entries can't be created on Windows", entry_type),
+            ),
+            _ => {}
+        }
+
+        let s = match str::from_utf8(path) {
+            Ok(s) => s,
+            Err(_) => {
+                self.push(
+                    PortabilityIssueKind::NonUnicode,
+                    path,
+                    "path is not valid UTF-8".to_string(),
+                );
+                return;
+            }
+        };
+        self.check_components(path, s);
+        self.check_length(path, s);
+        self.check_collisions(path, s);
+    }
+
+    /// Returns all problems found so far, in the order of the members they
+    /// were found in.
+    pub fn issues(&self) -> &[PortabilityIssue] {
+        &self.issues
+    }
+
+    fn check_components(&mut self, path: &[u8], s: &str) {
+        for component in s.split('/') {
+            if component.is_empty() || component == "." || component == ".." {
+                continue;
+            }
+            if let Some(c) = component
+                .chars()
+                .find(|c| (*c as u32) < 0x20 || "<>:\"\\|?*".contains(*c))
+            {
+                let msg = format!("{:?} is not allowed in Windows file names", c);
+                self.push(PortabilityIssueKind::ReservedCharacter, path, msg);
+            }
+            if component.ends_with('.') || component.ends_with(' ') {
+                let msg = format!("`{}` ends with a dot or a space", component);
+                self.push(PortabilityIssueKind::TrailingDotOrSpace, path, msg);
+            }
+            let stem = component.split('.').next().unwrap().trim_end();
+            if WINDOWS_RESERVED
+                .iter()
+                .any(|name| stem.eq_ignore_ascii_case(name))
+            {
+                let msg = format!("`{}` is a reserved name on Windows", component);
+                self.push(PortabilityIssueKind::ReservedName, path, msg);
+            }
+            if component.len() > NAME_MAX {
+                let msg = format!("a component is longer than {} bytes", NAME_MAX);
+                self.push(PortabilityIssueKind::PathTooLong, path, msg);
+            }
+        }
+    }
+
+    fn check_length(&mut self, path: &[u8], s: &str) {
+        let wide = s.encode_utf16().count();
+        if wide > WINDOWS_MAX_PATH {
+            let msg = format!(
+                "path is {} characters long, Windows accepts at most {}",
+                wide, WINDOWS_MAX_PATH
+            );
+            self.push(PortabilityIssueKind::PathTooLong, path, msg);
+        } else if path.len() > MACOS_PATH_MAX {
+            let msg = format!(
+                "path is {} bytes long, macOS accepts at most {}",
+                path.len(),
+                MACOS_PATH_MAX
+            );
+            self.push(PortabilityIssueKind::PathTooLong, path, msg);
+        }
+    }
+
+    /// Checks the path and all of its parent directories against the paths
+    /// seen so far, after folding case and normalizing it.
+    fn check_collisions(&mut self, path: &[u8], s: &str) {
+        let mut end = 0;
+        loop {
+            end = match s[end..].find('/') {
+                Some(i) => end + i,
+                None => s.len(),
+            };
+            let prefix = &s[..end];
+            let case_key = prefix.to_lowercase();
+            let key = decompose(&case_key);
+            match self.seen.get(&key) {
+                Some(other) if other.as_slice() != prefix.as_bytes() => {
+                    let kind = if case_key == String::from_utf8_lossy(other).to_lowercase() {
+                        PortabilityIssueKind::CaseCollision
+                    } else {
+                        PortabilityIssueKind::NormalizationCollision
+                    };
+                    let msg = format!(
+                        "`{}` collides with `{}`",
+                        prefix,
+                        String::from_utf8_lossy(other)
+                    );
+                    self.push(kind, path, msg);
+                    // Parents which collide were reported already, and the
+                    // children of a colliding directory collide as well.
+                    return;
+                }
+                Some(_) => {}
+                None => {
+                    self.seen.insert(key, prefix.as_bytes().to_vec());
+                }
+            }
+            if end == s.len() {
+                return;
+            }
+            end += 1;
+        }
+    }
+
+    fn push(&mut self, kind: PortabilityIssueKind, path: &[u8], message: String) {
+        self.issues.push(PortabilityIssue {
+            kind,
+            path: path.to_vec(),
+            message,
+        });
+    }
+}
+
+impl PortabilityIssue {
+    /// Returns the kind of this problem.
+    pub fn kind(&self) -> PortabilityIssueKind {
+        self.kind
+    }
+
+    /// Returns the path of the member this problem was found in.
+    pub fn path_bytes(&self) -> &[u8] {
+        &self.path
+    }
+
+    /// Returns a description of this problem.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for PortabilityIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {}",
+            String::from_utf8_lossy(&self.path),
+            self.message
+        )
+    }
+}
+
+/// Returns whether entries of this type describe the following member rather
+/// than being members of their own.
+fn is_extension(entry_type: EntryType) -> bool {
+    matches!(
+        entry_type,
+        EntryType::GNULongName
+            | EntryType::GNULongLink
+            | EntryType::XHeader
+            | EntryType::XGlobalHeader
+    )
+}
+
+/// Strips leading `./` components and trailing slashes, which don't change
+/// where a member is extracted to.
+fn trim_path(mut path: &[u8]) -> &[u8] {
+    while path.starts_with(b"./") {
+        path = &path[2..];
+    }
+    while path.ends_with(b"/") {
+        path = &path[..path.len() - 1];
+    }
+    path
+}
+
+/// Decomposes `s` so that paths which only differ in Unicode normalization
+/// have the same key.
+#[cfg(feature = "unicode-normalization")]
+fn decompose(s: &str) -> String {
+    s.nfd().collect()
+}
+
+#[cfg(not(feature = "unicode-normalization"))]
+fn decompose(s: &str) -> String {
+    s.to_string()
+}
@@ -0,0 +1,49 @@
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+/// A callback rewriting the path of an entry, see `Archive::set_path_rewriter`.
+pub(crate) type Rewriter = dyn Fn(&Path) -> Option<PathBuf> + Send + Sync;
+
+/// Rewrites the paths of entries when unpacking, see
+/// `Archive::set_strip_components`, `Archive::map_path_prefix` and
+/// `Archive::set_path_rewriter`.
+#[derive(Clone, Default)]
+pub(crate) struct PathRewrite {
+    pub(crate) strip_components: usize,
+    pub(crate) prefixes: Vec<(PathBuf, PathBuf)>,
+    pub(crate) rewriter: Option<Arc<Rewriter>>,
+}
+
+impl PathRewrite {
+    /// Returns the rewritten `path`, or `None` if the entry should be skipped.
+    pub(crate) fn rewrite(&self, path: &Path) -> Option<PathBuf> {
+        // Leading `/` and `.` components don't count as components to strip,
+        // `..` does so that it isn't moved to the front of the path.
+        let mut stripped = PathBuf::new();
+        let mut skip = self.strip_components;
+        for component in path.components() {
+            match component {
+                Component::Prefix(..) | Component::RootDir | Component::CurDir => {}
+                Component::Normal(_) | Component::ParentDir if skip > 0 => skip -= 1,
+                Component::Normal(_) | Component::ParentDir => stripped.push(component),
+            }
+        }
+        if stripped.as_os_str().is_empty() {
+            return None;
+        }
+
+        let mapped = self
+            .prefixes
+            .iter()
+            .find_map(|(from, to)| stripped.strip_prefix(from).ok().map(|rest| to.join(rest)))
+            .unwrap_or(stripped);
+        if mapped.as_os_str().is_empty() {
+            return None;
+        }
+
+        match self.rewriter {
+            Some(ref rewriter) => rewriter(&mapped),
+            None => Some(mapped),
+        }
+    }
+}
@@ -0,0 +1,350 @@
+//! Flattening of a stack of layer archives into one, see
+//! `Builder::append_squashed` and `Streamer::append_squashed`.
+
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::builder::{append_pax_records, prepare_header_link, prepare_header_path};
+use crate::entry::{EntryFields, EntryIo};
+use crate::pax::pax_record;
+use crate::whiteout::Whiteout;
+use crate::{other, Archive, EntryType, Header, PaxExtensions};
+
+/// An entry of the squashed archive.
+pub(crate) struct Squashed {
+    pub(crate) path: PathBuf,
+    /// The extension headers preceding `header`, such as pax records and GNU
+    /// long names.
+    pub(crate) prefix: Vec<u8>,
+    pub(crate) header: Header,
+    /// The layer the data is read from.
+    pub(crate) layer: usize,
+    pub(crate) chunks: Vec<Chunk>,
+}
+
+/// A piece of the data of an entry.
+#[derive(Clone, Copy)]
+pub(crate) enum Chunk {
+    /// `len` bytes at position `pos` of the layer.
+    Data { pos: u64, len: u64 },
+    /// A hole of a sparse file.
+    Hole(u64),
+}
+
+/// An entry of a layer.
+struct Member {
+    /// The normalized path of the entry.
+    key: PathBuf,
+    path: PathBuf,
+    link: Option<PathBuf>,
+    /// The path hidden by this entry if it's a whiteout, and whether it's an
+    /// opaque directory.
+    whiteout: Option<(PathBuf, bool)>,
+    header: Header,
+    /// The pax records to carry over.
+    pax: Vec<u8>,
+    layer: usize,
+    chunks: Vec<Chunk>,
+}
+
+/// Reads the entries of `layers`, given from the lowest to the topmost, and
+/// returns those making up the final state of the stack.
+pub(crate) fn squash<R: Read + Seek>(layers: &mut [Archive<R>]) -> io::Result<Vec<Squashed>> {
+    let mut members = Vec::with_capacity(layers.len());
+    for (layer, archive) in layers.iter_mut().enumerate() {
+        let mut list = Vec::new();
+        archive.scan(|fields, pos| {
+            list.push(Member::new(layer, fields, pos)?);
+            Ok(())
+        })?;
+        members.push(list);
+    }
+    let kept = select(&members);
+    emit(&members, &kept)
+}
+
+impl Member {
+    fn new(layer: usize, fields: &EntryFields, pos: u64) -> io::Result<Member> {
+        let path = fields.path()?.into_owned();
+        let key = normalize(&path);
+        let link = fields.link_name()?.map(|link| link.into_owned());
+        let whiteout = key.file_name().and_then(Whiteout::parse).map(|whiteout| {
+            let dir = key.parent().unwrap_or_else(|| Path::new(""));
+            match whiteout {
+                Whiteout::Remove(name) => (dir.join(name), false),
+                Whiteout::Opaque => (dir.to_path_buf(), true),
+            }
+        });
+
+        // Sparse files are written out in full.
+        let mut header = fields.header.clone();
+        if header.entry_type().is_gnu_sparse() {
+            header.set_entry_type(EntryType::Regular);
+            if let Some(gnu) = header.as_gnu_mut() {
+                for block in gnu.sparse.iter_mut() {
+                    block.offset = [0; 12];
+                    block.numbytes = [0; 12];
+                }
+                gnu.isextended = [0];
+                gnu.realsize = [0; 12];
+            }
+        }
+        header.set_size(fields.size);
+
+        // Paths are encoded anew and the records describing the data no
+        // longer apply.
+        let mut pax = Vec::new();
+        if let Some(ref records) = fields.pax_extensions {
+            for record in PaxExtensions::new(records) {
+                let record = record?;
+                let key = record
+                    .key()
+                    .map_err(|_| other("pax key is not valid UTF-8"))?;
+                if key == "path"
+                    || key == "linkpath"
+                    || key == "size"
+                    || key.starts_with("GNU.sparse.")
+                {
+                    continue;
+                }
+                pax.extend_from_slice(&pax_record(key, record.value_bytes()));
+            }
+        }
+
+        let mut chunks = Vec::with_capacity(fields.data.len());
+        let mut pos = pos;
+        for io in fields.data.iter() {
+            chunks.push(match *io {
+                EntryIo::Data(ref data) => {
+                    let len = data.limit();
+                    pos += len;
+                    Chunk::Data {
+                        pos: pos - len,
+                        len,
+                    }
+                }
+                EntryIo::Pad(ref pad) => Chunk::Hole(pad.limit()),
+            });
+        }
+
+        Ok(Member {
+            key,
+            path,
+            link,
+            whiteout,
+            header,
+            pax,
+            layer,
+            chunks,
+        })
+    }
+
+    fn is_hard_link(&self) -> bool {
+        self.header.entry_type() == EntryType::Link
+    }
+
+    /// Encodes this member under the name `path`, linking to `link`.
+    fn encode(&self, path: &Path, link: Option<&Path>) -> io::Result<Squashed> {
+        let mut prefix = Vec::new();
+        let mut header = self.header.clone();
+        append_pax_records(&mut prefix, &self.pax)?;
+        prepare_header_path(&mut prefix, &mut header, path)?;
+        if let Some(link) = link {
+            prepare_header_link(&mut prefix, &mut header, link)?;
+        }
+        header.set_cksum();
+        Ok(Squashed {
+            path: path.to_path_buf(),
+            prefix,
+            header,
+            layer: self.layer,
+            chunks: self.chunks.clone(),
+        })
+    }
+}
+
+/// Returns the path without `.` components and leading `/`.
+pub(crate) fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|c| matches!(c, Component::Normal(_) | Component::ParentDir))
+        .collect()
+}
+
+/// Marks the members which make up the final state, walking the layers from
+/// the top: a member is kept unless a layer above has an entry at the same
+/// path, hides it with a whiteout or opaque directory, or has something
+/// other than a directory at one of its parents. Within a layer the last
+/// entry for a path wins.
+fn select(layers: &[Vec<Member>]) -> Vec<Vec<bool>> {
+    let mut kept = layers
+        .iter()
+        .map(|members| vec![false; members.len()])
+        .collect::<Vec<_>>();
+    // The paths above the current layer and whether they're directories.
+    let mut upper: HashMap<&Path, bool> = HashMap::new();
+    let mut removed: HashSet<&Path> = HashSet::new();
+    let mut opaque: HashSet<&Path> = HashSet::new();
+    for (n, members) in layers.iter().enumerate().rev() {
+        let mut paths = HashMap::new();
+        let mut layer_removed = Vec::new();
+        let mut layer_opaque = Vec::new();
+        for (i, member) in members.iter().enumerate().rev() {
+            match member.whiteout {
+                Some((ref path, true)) => layer_opaque.push(&**path),
+                Some((ref path, false)) => layer_removed.push(&**path),
+                None => {
+                    let key = &*member.key;
+                    let hidden = upper.contains_key(key)
+                        || removed.contains(key)
+                        || key.ancestors().skip(1).any(|dir| {
+                            removed.contains(dir)
+                                || opaque.contains(dir)
+                                || upper.get(dir) == Some(&false)
+                        });
+                    if hidden || paths.contains_key(key) {
+                        continue;
+                    }
+                    paths.insert(key, member.header.entry_type().is_dir());
+                    kept[n][i] = true;
+                }
+            }
+        }
+        upper.extend(paths);
+        removed.extend(layer_removed);
+        opaque.extend(layer_opaque);
+    }
+    kept
+}
+
+/// Encodes the kept members from the lowest layer up.
+///
+/// Hard links are resolved like unpacking the layers in order would: a link
+/// whose target doesn't make it into the final state is written out as a
+/// copy of the target, to which later links to the same target then point.
+fn emit(layers: &[Vec<Member>], kept: &[Vec<bool>]) -> io::Result<Vec<Squashed>> {
+    // The member holding the data of each path, following hard links.
+    let mut latest: HashMap<&Path, (usize, usize)> = HashMap::new();
+    // Where the data of a member ends up in the squashed archive.
+    let mut written: HashMap<(usize, usize), &Path> = HashMap::new();
+    let mut squashed = Vec::new();
+    for (n, members) in layers.iter().enumerate() {
+        for (i, member) in members.iter().enumerate() {
+            if let Some((ref path, opaque)) = member.whiteout {
+                let path = &**path;
+                latest.retain(|key, _| !key.starts_with(path) || (opaque && *key == path));
+                continue;
+            }
+
+            let origin = match member.link {
+                Some(ref link) if member.is_hard_link() => latest.get(&*normalize(link)).copied(),
+                _ => Some((n, i)),
+            };
+            match origin {
+                Some(origin) => latest.insert(&member.key, origin),
+                None => latest.remove(&*member.key),
+            };
+            if !kept[n][i] {
+                continue;
+            }
+
+            let origin = match origin {
+                Some(origin) if member.is_hard_link() => origin,
+                _ => {
+                    written.insert((n, i), &member.path);
+                    squashed.push(member.encode(&member.path, member.link.as_deref())?);
+                    continue;
+                }
+            };
+            match written.get(&origin) {
+                Some(&target) => squashed.push(member.encode(&member.path, Some(target))?),
+                None => {
+                    let target = &layers[origin.0][origin.1];
+                    written.insert(origin, &member.path);
+                    squashed.push(target.encode(&member.path, target.link.as_deref())?);
+                }
+            }
+        }
+    }
+    Ok(squashed)
+}
+
+/// Reads the data of an entry from its layer.
+pub(crate) struct ChunkReader<S> {
+    obj: S,
+    chunks: Vec<Chunk>,
+    index: usize,
+    /// Bytes read of the current chunk.
+    done: u64,
+}
+
+impl<S: Read + Seek> ChunkReader<S> {
+    pub(crate) fn new(obj: S, chunks: Vec<Chunk>) -> ChunkReader<S> {
+        ChunkReader {
+            obj,
+            chunks,
+            index: 0,
+            done: 0,
+        }
+    }
+}
+
+impl<S: Read + Seek> Read for ChunkReader<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let (pos, len) = match self.chunks.get(self.index) {
+                Some(&Chunk::Data { pos, len }) => (Some(pos), len),
+                Some(&Chunk::Hole(len)) => (None, len),
+                None => return Ok(0),
+            };
+            if self.done == len {
+                self.index += 1;
+                self.done = 0;
+                continue;
+            }
+            let max = cmp::min(buf.len() as u64, len - self.done) as usize;
+            let n = match pos {
+                Some(pos) => {
+                    self.obj.seek(SeekFrom::Start(pos + self.done))?;
+                    let n = self.obj.read(&mut buf[..max])?;
+                    if n == 0 && max > 0 {
+                        return Err(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            "layer ended before the data of an entry",
+                        ));
+                    }
+                    n
+                }
+                None => {
+                    buf[..max].iter_mut().for_each(|b| *b = 0);
+                    max
+                }
+            };
+            self.done += n as u64;
+            return Ok(n);
+        }
+    }
+}
+
+/// A layer shared by the entries queued in a `Streamer`.
+pub(crate) struct SharedLayer<R>(pub(crate) Arc<Mutex<R>>);
+
+impl<R: Read> Read for SharedLayer<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0
+            .lock()
+            .map_err(|_| other("layer lock poisoned"))?
+            .read(buf)
+    }
+}
+
+impl<R: Seek> Seek for SharedLayer<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.0
+            .lock()
+            .map_err(|_| other("layer lock poisoned"))?
+            .seek(pos)
+    }
+}
@@ -0,0 +1,133 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str;
+
+use crate::{EntryType, Header, HeaderMode};
+
+/// The ownership, permissions and file type of a file recorded in an
+/// extended attribute by rootless container tools, which can't change the
+/// owner of files or create device nodes. See `Archive::set_ownership_xattr`.
+///
+/// The value has the form `uid:gid:mode:type` used by containers/storage and
+/// fuse-overlayfs for `user.containers.override_stat`, with the mode in octal
+/// and the type being one of `file`, `dir`, `symlink`, `pipe`, `socket`,
+/// `char-<major>-<minor>` and `block-<major>-<minor>`. The type is optional.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct StatOverride {
+    uid: u64,
+    gid: u64,
+    mode: u32,
+    kind: Option<(EntryType, u32, u32)>,
+}
+
+impl StatOverride {
+    pub(crate) fn new(
+        uid: u64,
+        gid: u64,
+        mode: u32,
+        entry_type: EntryType,
+        major: u32,
+        minor: u32,
+    ) -> StatOverride {
+        StatOverride {
+            uid,
+            gid,
+            mode,
+            kind: Some((entry_type, major, minor)),
+        }
+    }
+
+    pub(crate) fn parse(value: &[u8]) -> Option<StatOverride> {
+        let value = str::from_utf8(value).ok()?;
+        let mut fields = value.splitn(4, ':');
+        let uid = fields.next()?.parse().ok()?;
+        let gid = fields.next()?.parse().ok()?;
+        let mode = u32::from_str_radix(fields.next()?, 8).ok()?;
+        // Types which can't be represented, like sockets, are ignored.
+        let kind = fields.next().and_then(parse_kind);
+        Some(StatOverride {
+            uid,
+            gid,
+            mode,
+            kind,
+        })
+    }
+
+    pub(crate) fn encode(&self) -> String {
+        let mut value = format!("{}:{}:0{:o}", self.uid, self.gid, self.mode & 0o7777);
+        if let Some((entry_type, major, minor)) = self.kind {
+            let kind = match entry_type {
+                EntryType::Directory => "dir".to_string(),
+                EntryType::Symlink => "symlink".to_string(),
+                EntryType::Fifo => "pipe".to_string(),
+                EntryType::Char => format!("char-{}-{}", major, minor),
+                EntryType::Block => format!("block-{}-{}", major, minor),
+                _ => "file".to_string(),
+            };
+            value.push(':');
+            value.push_str(&kind);
+        }
+        value
+    }
+
+    /// Applies this override to the header of the file it was read from,
+    /// turning empty regular files back into the devices and FIFOs they
+    /// stand in for. The ownership and permissions are only applied in
+    /// `HeaderMode::Complete`, other modes keep the normalized ones.
+    pub(crate) fn apply(&self, header: &mut Header, mode: HeaderMode) -> io::Result<()> {
+        if let HeaderMode::Complete = mode {
+            header.set_uid(self.uid);
+            header.set_gid(self.gid);
+            header.set_mode(self.mode & 0o7777);
+        }
+        if let Some((entry_type, major, minor)) = self.kind {
+            let special = matches!(
+                entry_type,
+                EntryType::Char | EntryType::Block | EntryType::Fifo
+            );
+            if special && header.entry_type().is_file() && header.size()? == 0 {
+                header.set_entry_type(entry_type);
+                header.set_device_major(major)?;
+                header.set_device_minor(minor)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_kind(kind: &str) -> Option<(EntryType, u32, u32)> {
+    let (entry_type, dev) = match kind {
+        "file" => return Some((EntryType::Regular, 0, 0)),
+        "dir" => return Some((EntryType::Directory, 0, 0)),
+        "symlink" => return Some((EntryType::Symlink, 0, 0)),
+        "pipe" => return Some((EntryType::Fifo, 0, 0)),
+        _ => match kind.strip_prefix("char-") {
+            Some(dev) => (EntryType::Char, dev),
+            None => (EntryType::Block, kind.strip_prefix("block-")?),
+        },
+    };
+    let mut numbers = dev.splitn(2, '-');
+    let major = numbers.next()?.parse().ok()?;
+    let minor = numbers.next()?.parse().ok()?;
+    Some((entry_type, major, minor))
+}
+
+/// Reads the override recorded in the extended attribute `name` of the file
+/// at `path`, through `file` if it's open already. Symbolic links in `path`
+/// are followed.
+#[cfg(all(unix, feature = "xattr"))]
+pub(crate) fn read(file: Option<&fs::File>, path: &Path, name: &str) -> Option<StatOverride> {
+    use xattr::FileExt;
+
+    let value = match file {
+        Some(file) => file.get_xattr(name),
+        None => xattr::get(path.canonicalize().ok()?, name),
+    };
+    StatOverride::parse(&value.ok()??)
+}
+
+#[cfg(not(all(unix, feature = "xattr")))]
+pub(crate) fn read(_file: Option<&fs::File>, _path: &Path, _name: &str) -> Option<StatOverride> {
+    None
+}
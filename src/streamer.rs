@@ -10,17 +10,21 @@ use std::fs::{self};
 use std::io::{self, Read, Result, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
 use std::str;
+use std::sync::{Arc, Mutex};
 
 use crate::header::{prepare_header, Header, HeaderMode};
 #[cfg(windows)]
 use crate::other;
-use crate::EntryType;
+use crate::squash::{self, ChunkReader, SharedLayer};
+use crate::stat_override;
+use crate::{Archive, EntryType};
 
 struct StreamFile {
     path: PathBuf,
     alternative_name: Option<PathBuf>,
     follow: bool,
     mode: HeaderMode,
+    ownership_xattr: Option<String>,
     cached_header_bytes: Option<Vec<u8>>,
     read_bytes: usize, //needed to calculate padding;
     padding_bytes: Option<Vec<u8>>,
@@ -32,12 +36,14 @@ impl StreamFile {
         alternative_name: Option<PathBuf>,
         follow: bool,
         mode: HeaderMode,
+        ownership_xattr: Option<String>,
     ) -> Self {
         Self {
             path,
             alternative_name,
             follow,
             mode,
+            ownership_xattr,
             cached_header_bytes: None, //will be encoded while reading (to save memory)
             read_bytes: 0,
             padding_bytes: None, //will be calculated while using io::Read implementation.
@@ -144,6 +150,7 @@ impl Default for StreamerReadMetadata {
 pub struct Streamer {
     mode: HeaderMode,
     follow: bool,
+    ownership_xattr: Option<String>,
     streamer_metadata: StreamerReadMetadata,
     index_counter: usize,
     stream_files: HashMap<usize, StreamFile>, // <index_counter, StreamFile>
@@ -165,6 +172,7 @@ impl Streamer {
         Self {
             mode: HeaderMode::Complete,
             follow: true,
+            ownership_xattr: None,
             streamer_metadata: StreamerReadMetadata::default(),
             index_counter: 0,
             stream_files: HashMap::new(),
@@ -187,6 +195,12 @@ impl Streamer {
         self.follow = follow;
     }
 
+    /// Reads the ownerships of files and directories from the extended
+    /// attribute `name` if they have it, see `Builder::ownership_xattr`.
+    pub fn ownership_xattr<S: Into<String>>(&mut self, name: S) {
+        self.ownership_xattr = Some(name.into());
+    }
+
     /// Adds a new entry to the archive.
     ///
     /// This function will append the header specified, followed by contents of
@@ -414,6 +428,11 @@ impl Streamer {
             //self.long_name_extension_entries.insert(self.index_counter, long_name_extension_entry);
         }
         header.set_metadata_in_mode(&stat, self.mode);
+        if let Some(ref name) = self.ownership_xattr {
+            if let Some(stat) = stat_override::read(Some(file), path.as_ref(), name) {
+                stat.apply(&mut header, self.mode)?;
+            }
+        }
         header.set_cksum();
         encoded_header.append(&mut header.as_bytes().to_vec());
         self.append_stream_data(StreamData::new_with_encoded_header(
@@ -501,6 +520,48 @@ impl Streamer {
         Ok(())
     }
 
+    /// Appends the final state of a stack of layered archives, given from the
+    /// lowest to the topmost, see `Builder::append_squashed`.
+    ///
+    /// The headers of all layers are read right away, the data of the entries
+    /// is read from the layers while streaming.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::{self, File};
+    /// use std::io;
+    /// use tar::{Archive, Streamer};
+    ///
+    /// let layers = vec![
+    ///     Archive::new(File::open("base.tar").unwrap()),
+    ///     Archive::new(File::open("app.tar").unwrap()),
+    /// ];
+    /// let mut ar = Streamer::new();
+    /// ar.append_squashed(layers).unwrap();
+    /// let mut target_archive = fs::File::create("rootfs.tar").unwrap();
+    /// io::copy(&mut ar, &mut target_archive).unwrap();
+    /// ```
+    pub fn append_squashed<R: Read + Seek + Send + 'static>(
+        &mut self,
+        layers: Vec<Archive<R>>,
+    ) -> io::Result<()> {
+        let mut layers = layers;
+        let squashed = squash::squash(&mut layers)?;
+        let layers = layers
+            .into_iter()
+            .map(|ar| Arc::new(Mutex::new(ar.into_inner())))
+            .collect::<Vec<_>>();
+        for entry in squashed {
+            let mut encoded_header = entry.prefix;
+            encoded_header.extend_from_slice(entry.header.as_bytes());
+            let layer = SharedLayer(layers[entry.layer].clone());
+            let data = ChunkReader::new(layer, entry.chunks);
+            self.append_stream_data(StreamData::new_with_encoded_header(encoded_header, data));
+        }
+        Ok(())
+    }
+
     fn append_stream_data(&mut self, stream_data: StreamData) {
         self.stream_data.insert(self.index_counter, stream_data);
         self.index_counter += 1;
@@ -519,12 +580,14 @@ impl Streamer {
     }
 
     fn append_stream_file(&mut self, path: &Path, name: Option<&Path>) -> Result<()> {
-        prepare_file_header(path, name, self.mode, self.follow)?;
+        let ownership_xattr = self.ownership_xattr.as_deref();
+        prepare_file_header(path, name, self.mode, self.follow, ownership_xattr)?;
         let stream_file = StreamFile::new(
             path.to_path_buf(),
             name.map(|x| x.to_path_buf()),
             self.follow,
             self.mode,
+            self.ownership_xattr.clone(),
         );
         self.stream_files.insert(self.index_counter, stream_file);
         self.index_counter += 1;
@@ -568,6 +631,7 @@ impl Read for Streamer {
                         stream_file.alternative_name.as_deref(),
                         stream_file.mode,
                         stream_file.follow,
+                        stream_file.ownership_xattr.as_deref(),
                     )?)
                 }
                 if let Some(ref mut encoded_header) = stream_file.cached_header_bytes {
@@ -755,6 +819,7 @@ fn prepare_file_header(
     name: Option<&Path>,
     mode: HeaderMode,
     follow: bool,
+    ownership_xattr: Option<&str>,
 ) -> io::Result<Vec<u8>> {
     let stat = get_stat(path, follow)?;
     let ar_name = name.unwrap_or(path);
@@ -772,6 +837,10 @@ fn prepare_file_header(
         if let Some(mut long_name_extension_entry) = prepare_header_link(&mut header, &link_name)? {
             encoded_header.append(&mut long_name_extension_entry);
         }
+    } else if let Some(name) = ownership_xattr {
+        if let Some(stat) = stat_override::read(None, path, name) {
+            stat.apply(&mut header, mode)?;
+        }
     }
     header.set_cksum();
     encoded_header.append(&mut header.as_bytes().to_vec());
@@ -0,0 +1,91 @@
+use std::io::{self, Read};
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::other;
+use crate::EntryType;
+
+/// A destination which archives can be unpacked into, see
+/// `Archive::unpack_into` and `Entry::unpack_into`.
+///
+/// This allows unpacking into something other than a directory on the local
+/// filesystem, such as an in-memory tree, a remote store or a sandboxed root.
+///
+/// All paths handed to a target are relative to its root. They only consist
+/// of normal components, as the entries are first cleaned up and checked the
+/// same way `Entry::unpack_in` does: leading `/` and `.` components are
+/// dropped, entries with `..` components are skipped and the targets of hard
+/// links must be inside the destination as well. The targets of symbolic
+/// links are passed as they are found in the archive though, so a target
+/// which resolves symbolic links itself must take care to not follow them
+/// outside of its root, or to not follow them at all when creating entries.
+///
+/// Parent directories are created with `create_dir` before anything is
+/// created in them. Only the methods creating entries need to be
+/// implemented, the metadata of entries is ignored by default.
+pub trait UnpackTarget {
+    /// Creates the directory `path`. This must succeed if a directory exists
+    /// at `path` already.
+    fn create_dir(&mut self, path: &Path) -> io::Result<()>;
+
+    /// Creates the regular file `path` with the contents read from `data`.
+    ///
+    /// If `path` exists already it should be replaced if `overwrite` is set,
+    /// and an error be returned otherwise.
+    fn create_file(&mut self, path: &Path, data: &mut dyn Read, overwrite: bool) -> io::Result<()>;
+
+    /// Creates the symbolic link `path` pointing to `target`.
+    ///
+    /// If `path` exists already it should be replaced if `overwrite` is set,
+    /// and an error be returned otherwise.
+    fn symlink(&mut self, target: &Path, path: &Path, overwrite: bool) -> io::Result<()>;
+
+    /// Creates the hard link `path` to the existing entry `target`, which is
+    /// relative to the root of the target as well.
+    fn hard_link(&mut self, target: &Path, path: &Path) -> io::Result<()>;
+
+    /// Creates the character device, block device or FIFO `path`.
+    ///
+    /// By default this returns an error, as there's no portable way to
+    /// represent these entries.
+    fn mknod(
+        &mut self,
+        path: &Path,
+        entry_type: EntryType,
+        mode: u32,
+        major: u32,
+        minor: u32,
+    ) -> io::Result<()> {
+        let _ = (mode, major, minor);
+        Err(other(&format!(
+            "cannot create {:?} entry `{}` in this target",
+            entry_type,
+            path.display()
+        )))
+    }
+
+    /// Sets the access and modification times of `path`, without following
+    /// symbolic links.
+    fn set_times(&mut self, path: &Path, atime: SystemTime, mtime: SystemTime) -> io::Result<()> {
+        let _ = (path, atime, mtime);
+        Ok(())
+    }
+
+    /// Sets the permission bits of `path`.
+    fn set_permissions(&mut self, path: &Path, mode: u32) -> io::Result<()> {
+        let _ = (path, mode);
+        Ok(())
+    }
+
+    /// Sets the numeric owner and group of `path`.
+    fn set_ownership(&mut self, path: &Path, uid: u64, gid: u64) -> io::Result<()> {
+        let _ = (path, uid, gid);
+        Ok(())
+    }
+
+    /// Sets the extended attribute `name` of `path` to `value`.
+    fn set_xattr(&mut self, path: &Path, name: &[u8], value: &[u8]) -> io::Result<()> {
+        let _ = (path, name, value);
+        Ok(())
+    }
+}
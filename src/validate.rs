@@ -0,0 +1,569 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::io::{self, Read};
+use std::mem;
+
+use crate::archive::{
+    block_size, entry_data_size, finish_gnu_sparse, gnu_sparse_map, pax_sparse_chunks,
+    pax_sparse_format, verify_cksum, MemberMeta, MetaKind, PaxSparse, PaxSparseMap,
+};
+use crate::entry::EntryFields;
+use crate::limits::{LimitKind, Limits};
+use crate::{GnuExtSparseHeader, Header, PaxExtensions};
+
+/// The size of a record, the unit GNU tar and pax pad archives to by default.
+const RECORD_SIZE: u64 = 20 * 512;
+
+/// The result of validating an archive with `Archive::validate`.
+#[derive(Clone, Debug, Default)]
+pub struct ValidationReport {
+    findings: Vec<Finding>,
+}
+
+/// A problem found while validating an archive.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Finding {
+    kind: FindingKind,
+    offset: u64,
+    path: Option<Vec<u8>>,
+    message: String,
+}
+
+/// The kinds of problems reported by `Archive::validate`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum FindingKind {
+    /// The checksum of a header doesn't match its contents. Following blocks
+    /// are skipped until the next valid header.
+    ChecksumMismatch,
+    /// A numeric header field, such as the mode or size, isn't a valid
+    /// number.
+    InvalidNumericField,
+    /// The archive ends before all the data a member claims to have.
+    Truncated,
+    /// A member has the same path as a previous member.
+    DuplicatePath,
+    /// A member has an absolute path.
+    AbsolutePath,
+    /// A member has a path containing a `..` component.
+    ParentDirPath,
+    /// A symbolic or hard link points outside of the directory the archive
+    /// would be unpacked in.
+    LinkOutsideRoot,
+    /// A pax extended header contains a malformed record, such as one with a
+    /// wrong length prefix.
+    InvalidPaxRecord,
+    /// Two GNU long name or pax extension entries describe the same member.
+    DuplicateExtension,
+    /// The sparse map of a sparse file is malformed or doesn't match the
+    /// data stored for it.
+    InvalidSparseMap,
+    /// The archive isn't terminated by two blocks of zeros.
+    MissingEndOfArchive,
+    /// The archive was padded to a multiple of the record size, but its last
+    /// record is incomplete.
+    IncompleteRecord,
+}
+
+impl ValidationReport {
+    /// Returns all problems found, in the order of their offsets.
+    pub fn findings(&self) -> &[Finding] {
+        &self.findings
+    }
+
+    /// Returns whether no problems were found.
+    pub fn is_valid(&self) -> bool {
+        self.findings.is_empty()
+    }
+}
+
+impl Finding {
+    /// Returns the kind of this problem.
+    pub fn kind(&self) -> FindingKind {
+        self.kind
+    }
+
+    /// Returns the position, in bytes, of the header of the member this
+    /// problem was found in, or of the problem itself if it's not part of a
+    /// member.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Returns the path of the member this problem was found in, if any.
+    pub fn path_bytes(&self) -> Option<&[u8]> {
+        self.path.as_deref()
+    }
+
+    /// Returns a description of this problem.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "offset {}", self.offset)?;
+        if let Some(ref path) = self.path {
+            write!(f, " ({})", String::from_utf8_lossy(path))?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// Validates the archive read from `r`, which is at position `pos` of the
+/// archive.
+pub(crate) fn validate(
+    r: &mut dyn Read,
+    pos: u64,
+    limits: &Limits,
+) -> io::Result<ValidationReport> {
+    let mut validator = Validator {
+        r,
+        pos,
+        limits,
+        findings: Vec::new(),
+        paths: HashSet::new(),
+    };
+    validator.run()?;
+    Ok(ValidationReport {
+        findings: validator.findings,
+    })
+}
+
+struct Validator<'a> {
+    r: &'a mut dyn Read,
+    pos: u64,
+    limits: &'a Limits,
+    findings: Vec<Finding>,
+    paths: HashSet<Vec<u8>>,
+}
+
+impl<'a> Validator<'a> {
+    fn run(&mut self) -> io::Result<()> {
+        let mut header = Header::new_old();
+        let mut meta = MemberMeta::default();
+        let mut meta_size = 0u64;
+        let mut damaged = false;
+        loop {
+            let header_pos = self.pos;
+            match self.read_block(header.as_mut_bytes())? {
+                512 => {}
+                0 => {
+                    self.push(
+                        FindingKind::MissingEndOfArchive,
+                        header_pos,
+                        None,
+                        "archive ends without two blocks of zeros",
+                    );
+                    return Ok(());
+                }
+                _ => {
+                    self.push(
+                        FindingKind::Truncated,
+                        header_pos,
+                        None,
+                        "archive ends in the middle of a header",
+                    );
+                    return Ok(());
+                }
+            }
+
+            if header.as_bytes().iter().all(|b| *b == 0) {
+                return self.check_end(header_pos);
+            }
+
+            if let Err(e) = verify_cksum(&header) {
+                // Only report the first of consecutive blocks which aren't
+                // headers, the following ones are likely to be data.
+                if !damaged {
+                    let path = Some(header.path_bytes().into_owned());
+                    self.push(FindingKind::ChecksumMismatch, header_pos, path, e);
+                }
+                damaged = true;
+                continue;
+            }
+            damaged = false;
+
+            // GNU tar leaves the numeric fields of volume labels and most of
+            // those of continuation headers empty.
+            let entry_type = header.entry_type();
+            let is_volume = entry_type.is_gnu_volume_label() || entry_type.is_gnu_multi_volume();
+            if !is_volume {
+                self.check_numeric(&header, header_pos);
+            }
+            let size = match entry_data_size(&header, meta.pax_size()) {
+                Ok(size) => size,
+                Err(e) => {
+                    let path = Some(header.path_bytes().into_owned());
+                    self.push(FindingKind::InvalidNumericField, header_pos, path, e);
+                    return Ok(());
+                }
+            };
+
+            let kind = match meta.kind(&header) {
+                Ok(kind) => kind,
+                Err(e) => {
+                    let path = Some(header.path_bytes().into_owned());
+                    self.push(FindingKind::DuplicateExtension, header_pos, path, e);
+                    // Carry on with the later entry.
+                    meta = MemberMeta::default();
+                    meta_size = 0;
+                    meta.kind(&header).ok().flatten()
+                }
+            };
+            if kind.is_some() || entry_type.is_pax_global_extensions() {
+                // The data is read into memory, so it's subject to the same
+                // limit as when reading the entries.
+                let total = match kind {
+                    Some(_) => meta_size.saturating_add(size),
+                    None => size,
+                };
+                let path = header.path_bytes();
+                self.limits
+                    .check(LimitKind::MetadataSize, total, &path, header_pos)?;
+                if kind.is_some() {
+                    meta_size = total;
+                }
+                let data = match self.read_data(size, header_pos, &header)? {
+                    Some(data) => data,
+                    None => return Ok(()),
+                };
+                let valid = match kind {
+                    Some(MetaKind::Pax) | None => self.check_pax(&data, header_pos, &header),
+                    Some(_) => true,
+                };
+                // Malformed pax extensions were reported already, don't
+                // report them again for the member they describe.
+                if let (Some(kind), true) = (kind, valid) {
+                    meta.add(kind, data);
+                }
+                continue;
+            }
+
+            let mut fields = EntryFields::new(header.clone(), size, header_pos, self.pos);
+            mem::take(&mut meta).apply(&mut fields);
+            meta_size = 0;
+            if !is_volume {
+                self.check_path(&fields);
+                self.check_link(&fields);
+            }
+            if !self.check_member_data(&fields)? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Checks the blocks following the first block of zeros at `pos`.
+    fn check_end(&mut self, pos: u64) -> io::Result<()> {
+        let mut block = [0; 512];
+        let n = self.read_block(&mut block)?;
+        if n < 512 || block.iter().any(|b| *b != 0) {
+            self.push(
+                FindingKind::MissingEndOfArchive,
+                pos,
+                None,
+                "archive ends with a single block of zeros",
+            );
+            return Ok(());
+        }
+
+        // Anything following the end of the archive is padding to a multiple
+        // of the record size, if the archive was padded at all.
+        let end = self.pos;
+        self.skip(u64::MAX)?;
+        let partial = self.pos % RECORD_SIZE;
+        if self.pos > end && partial > 0 {
+            let start = self.pos - partial;
+            let msg = format!(
+                "last record is {} bytes instead of {}",
+                self.pos - start,
+                RECORD_SIZE
+            );
+            self.push(FindingKind::IncompleteRecord, start, None, msg);
+        }
+        Ok(())
+    }
+
+    fn check_numeric(&mut self, header: &Header, pos: u64) {
+        let mut errors = vec![
+            header.mode().err(),
+            header.uid().err(),
+            header.gid().err(),
+            header.mtime().err(),
+        ];
+        let entry_type = header.entry_type();
+        if entry_type.is_character_special() || entry_type.is_block_special() {
+            errors.push(header.device_major().err());
+            errors.push(header.device_minor().err());
+        }
+        for e in errors.into_iter().flatten() {
+            let path = Some(header.path_bytes().into_owned());
+            self.push(FindingKind::InvalidNumericField, pos, path, e);
+        }
+    }
+
+    fn check_pax(&mut self, data: &[u8], pos: u64, header: &Header) -> bool {
+        for extension in PaxExtensions::new(data) {
+            if let Err(e) = extension {
+                let path = Some(header.path_bytes().into_owned());
+                self.push(FindingKind::InvalidPaxRecord, pos, path, e);
+                return false;
+            }
+        }
+        true
+    }
+
+    fn check_path(&mut self, fields: &EntryFields) {
+        let path = fields.path_bytes().into_owned();
+        let pos = fields.header_pos;
+        if is_absolute(&path) {
+            let msg = "path is absolute";
+            self.push(FindingKind::AbsolutePath, pos, Some(path.clone()), msg);
+        }
+        if path.split(|b| *b == b'/').any(|c| c == b"..") {
+            let msg = "path contains a `..` component";
+            self.push(FindingKind::ParentDirPath, pos, Some(path.clone()), msg);
+        }
+        let normalized = normalize(&path);
+        if !self.paths.insert(normalized) {
+            let msg = "path is listed more than once";
+            self.push(FindingKind::DuplicatePath, pos, Some(path), msg);
+        }
+    }
+
+    fn check_link(&mut self, fields: &EntryFields) {
+        let entry_type = fields.header.entry_type();
+        let target = match fields.link_name_bytes() {
+            Some(target) if entry_type.is_symlink() || entry_type.is_hard_link() => target,
+            _ => return,
+        };
+        let path = fields.path_bytes();
+        // Symbolic links are resolved relative to the directory containing
+        // them while hard links name another member of the archive.
+        let mut depth = 0;
+        if entry_type.is_symlink() {
+            let parent = normalize(&path);
+            let parent = match parent.iter().rposition(|b| *b == b'/') {
+                Some(i) => &parent[..i],
+                None => &[][..],
+            };
+            if escapes(parent, &mut depth) {
+                return;
+            }
+        }
+        if is_absolute(&target) || escapes(&target, &mut depth) {
+            let msg = format!(
+                "link to {} points outside of the archive",
+                String::from_utf8_lossy(&target)
+            );
+            let path = Some(path.into_owned());
+            self.push(FindingKind::LinkOutsideRoot, fields.header_pos, path, msg);
+        }
+    }
+
+    /// Checks the sparse map of a member, if any, and skips its data.
+    /// Returns `false` if the archive ended early.
+    fn check_member_data(&mut self, fields: &EntryFields) -> io::Result<bool> {
+        let pos = fields.header_pos;
+        let size = fields.size;
+        let mut consumed = 0;
+        if fields.header.entry_type().is_gnu_sparse() {
+            if !self.check_gnu_sparse(fields)? {
+                return Ok(false);
+            }
+        } else {
+            match pax_sparse_format(fields) {
+                Ok(Some(sparse)) => {
+                    let (real_size, blocks, map_len) = match sparse {
+                        PaxSparse::Records { real_size, blocks } => (real_size, blocks, 0),
+                        PaxSparse::Data { real_size } => {
+                            let mut map = PaxSparseMap::new();
+                            let mut block = [0; 512];
+                            loop {
+                                if map.len() >= size {
+                                    let msg = "sparse map is larger than the data of the file";
+                                    self.push_member(FindingKind::InvalidSparseMap, fields, msg);
+                                    break;
+                                }
+                                if self.read_block(&mut block)? < 512 {
+                                    self.truncated(fields, map.len());
+                                    return Ok(false);
+                                }
+                                match map.parse(&block) {
+                                    Ok(true) => break,
+                                    Ok(false) => {}
+                                    Err(e) => {
+                                        self.push_member(FindingKind::InvalidSparseMap, fields, e);
+                                        break;
+                                    }
+                                }
+                            }
+                            consumed = map.len();
+                            (real_size, map.blocks(), map.len())
+                        }
+                    };
+                    let data_size = size.saturating_sub(map_len);
+                    if let Err(e) = pax_sparse_chunks(fields, data_size, real_size, blocks) {
+                        self.push_member(FindingKind::InvalidSparseMap, fields, e);
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => self.push_member(FindingKind::InvalidSparseMap, fields, e),
+            }
+        }
+
+        let padded = match block_size(size) {
+            Ok(padded) => padded,
+            Err(e) => {
+                self.push(FindingKind::InvalidNumericField, pos, None, e);
+                return Ok(false);
+            }
+        };
+        if padded < consumed {
+            return Ok(true);
+        }
+        let skipped = self.skip(padded - consumed)?;
+        if skipped < padded - consumed {
+            self.truncated(fields, consumed + skipped);
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    fn check_gnu_sparse(&mut self, fields: &EntryFields) -> io::Result<bool> {
+        let gnu = match fields.header.as_gnu() {
+            Some(gnu) => gnu,
+            None => {
+                let msg = "sparse entry type listed but not GNU header";
+                self.push_member(FindingKind::InvalidSparseMap, fields, msg);
+                return Ok(true);
+            }
+        };
+        let mut map = gnu_sparse_map(gnu, fields.size);
+        if gnu.is_extended() {
+            let mut ext = GnuExtSparseHeader::new();
+            ext.isextended[0] = 1;
+            while ext.is_extended() {
+                if self.read_block(ext.as_mut_bytes())? < 512 {
+                    let msg = "archive ends in the middle of a sparse header";
+                    self.push_member(FindingKind::Truncated, fields, msg);
+                    return Ok(false);
+                }
+                if let Ok(ref mut map) = map {
+                    for block in ext.sparse.iter() {
+                        if let Err(e) = map.add_gnu_block(block) {
+                            self.push_member(FindingKind::InvalidSparseMap, fields, e);
+                            return Ok(true);
+                        }
+                    }
+                }
+            }
+        }
+        if let Err(e) = map.and_then(|map| finish_gnu_sparse(map, gnu)) {
+            self.push_member(FindingKind::InvalidSparseMap, fields, e);
+        }
+        Ok(true)
+    }
+
+    /// Reads the data of an entry, returning `None` if the archive ended
+    /// early.
+    fn read_data(&mut self, size: u64, pos: u64, header: &Header) -> io::Result<Option<Vec<u8>>> {
+        let mut data = Vec::new();
+        (&mut *self.r).take(size).read_to_end(&mut data)?;
+        self.pos += data.len() as u64;
+        let padding = block_size(size)? - size;
+        if (data.len() as u64) < size || self.skip(padding)? < padding {
+            let msg = format!(
+                "entry claims {} bytes of data but the archive ends after {}",
+                size,
+                data.len()
+            );
+            let path = Some(header.path_bytes().into_owned());
+            self.push(FindingKind::Truncated, pos, path, msg);
+            return Ok(None);
+        }
+        Ok(Some(data))
+    }
+
+    /// Reads up to a block, returning the number of bytes read.
+    fn read_block(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut read = 0;
+        while read < buf.len() {
+            match self.r.read(&mut buf[read..])? {
+                0 => break,
+                n => read += n,
+            }
+        }
+        self.pos += read as u64;
+        Ok(read)
+    }
+
+    /// Skips up to `amt` bytes, returning the number of bytes skipped.
+    fn skip(&mut self, amt: u64) -> io::Result<u64> {
+        let skipped = io::copy(&mut (&mut *self.r).take(amt), &mut io::sink())?;
+        self.pos += skipped;
+        Ok(skipped)
+    }
+
+    fn truncated(&mut self, fields: &EntryFields, read: u64) {
+        let msg = format!(
+            "member claims {} bytes of data but the archive ends after {}",
+            fields.size, read
+        );
+        self.push_member(FindingKind::Truncated, fields, msg);
+    }
+
+    fn push_member<M: ToString>(&mut self, kind: FindingKind, fields: &EntryFields, msg: M) {
+        let path = Some(fields.path_bytes().into_owned());
+        self.push(kind, fields.header_pos, path, msg);
+    }
+
+    fn push<M: ToString>(&mut self, kind: FindingKind, offset: u64, path: Option<Vec<u8>>, msg: M) {
+        self.findings.push(Finding {
+            kind,
+            offset,
+            path,
+            message: msg.to_string(),
+        });
+    }
+}
+
+fn is_absolute(path: &[u8]) -> bool {
+    match path {
+        [b'/', ..] | [b'\\', ..] => true,
+        [drive, b':', ..] => drive.is_ascii_alphabetic(),
+        _ => false,
+    }
+}
+
+/// Strips `.` components and redundant separators from a path.
+fn normalize(path: &[u8]) -> Vec<u8> {
+    let mut ret = Vec::with_capacity(path.len());
+    for component in path.split(|b| *b == b'/') {
+        if component.is_empty() || component == b"." {
+            continue;
+        }
+        if !ret.is_empty() {
+            ret.push(b'/');
+        }
+        ret.extend_from_slice(component);
+    }
+    ret
+}
+
+/// Walks the components of a relative path starting at `depth` directories
+/// below the root, returning whether it leaves the root.
+fn escapes(path: &[u8], depth: &mut usize) -> bool {
+    for component in path.split(|b| *b == b'/') {
+        match component {
+            b"" | b"." => {}
+            b".." => match depth.checked_sub(1) {
+                Some(d) => *depth = d,
+                None => return true,
+            },
+            _ => *depth += 1,
+        }
+    }
+    false
+}
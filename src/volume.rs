@@ -0,0 +1,310 @@
+use std::cmp;
+use std::error;
+use std::fmt;
+use std::io;
+use std::io::prelude::*;
+
+use crate::{other, EntryType, Header};
+
+/// A reader over the volumes of a GNU multi-volume archive.
+///
+/// GNU tar splits archives created with `--multi-volume` into several files,
+/// continuing a file which didn't fit into one volume in the next one behind
+/// a continuation (`M`) header. This reader chains the volumes together and,
+/// when used as the reader of an `Archive`, lets the archive stitch split
+/// files back together and record the labels of each volume.
+///
+/// Outside of an `Archive` the boundaries between volumes surface as errors
+/// from `read`, so this type is only useful in combination with one.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::fs::File;
+/// use tar::{Archive, MultiVolume};
+///
+/// let volumes = vec![
+///     File::open("foo-1.tar").unwrap(),
+///     File::open("foo-2.tar").unwrap(),
+/// ];
+/// let mut ar = Archive::new(MultiVolume::new(volumes));
+/// ar.unpack("foo").unwrap();
+/// ```
+pub struct MultiVolume<I: Iterator> {
+    volumes: I,
+    current: Option<I::Item>,
+}
+
+impl<I> MultiVolume<I>
+where
+    I: Iterator,
+    I::Item: Read,
+{
+    /// Creates a new reader over the given volumes, which are read in the
+    /// order they are yielded.
+    pub fn new<T>(volumes: T) -> MultiVolume<I>
+    where
+        T: IntoIterator<IntoIter = I, Item = I::Item>,
+    {
+        let mut volumes = volumes.into_iter();
+        MultiVolume {
+            current: volumes.next(),
+            volumes,
+        }
+    }
+}
+
+impl<I> Read for MultiVolume<I>
+where
+    I: Iterator,
+    I::Item: Read,
+{
+    fn read(&mut self, into: &mut [u8]) -> io::Result<usize> {
+        let n = match self.current {
+            Some(ref mut volume) => volume.read(into)?,
+            None => return Ok(0),
+        };
+        if n > 0 || into.is_empty() {
+            return Ok(n);
+        }
+        self.current = self.volumes.next();
+        match self.current {
+            Some(_) => Err(io::Error::new(io::ErrorKind::Other, EndOfVolume)),
+            None => Ok(0),
+        }
+    }
+}
+
+/// Error used by `MultiVolume` to tell the archive that the following bytes
+/// come from the next volume.
+#[derive(Debug)]
+struct EndOfVolume;
+
+impl fmt::Display for EndOfVolume {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        "end of tar volume".fmt(f)
+    }
+}
+
+impl error::Error for EndOfVolume {}
+
+pub(crate) fn is_end_of_volume(err: &io::Error) -> bool {
+    match err.get_ref() {
+        Some(e) => e.is::<EndOfVolume>(),
+        None => false,
+    }
+}
+
+/// A writer splitting an archive into the volumes of a GNU multi-volume
+/// archive.
+///
+/// Everything written to this writer is expected to be a tar archive, as
+/// produced by wrapping it in a `Builder`. Once a volume reaches the
+/// configured size, the next volume is opened by calling the provided
+/// function with its index (starting at zero) and a file which was split is
+/// continued behind a continuation (`M`) header.
+///
+/// Note that GNU sparse files (`S` headers) can't be split across volumes.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::fs::File;
+/// use tar::{Builder, MultiVolumeWriter};
+///
+/// let volumes = MultiVolumeWriter::new(1024 * 1024, |i| {
+///     File::create(format!("foo-{}.tar", i + 1))
+/// });
+/// let mut ar = Builder::new(volumes);
+/// ar.append_dir_all("foo", "foo").unwrap();
+/// ar.finish().unwrap();
+/// ```
+pub struct MultiVolumeWriter<W, F> {
+    open: F,
+    current: Option<W>,
+    volumes: usize,
+    volume_size: u64,
+    written: u64,
+    label: Option<Vec<u8>>,
+    header: Vec<u8>,
+    member: Option<Member>,
+}
+
+/// The file whose data is currently being written.
+struct Member {
+    name: [u8; 100],
+    size: u64,
+    written: u64,
+    remaining: u64,
+}
+
+impl<W, F> MultiVolumeWriter<W, F>
+where
+    W: Write,
+    F: FnMut(usize) -> io::Result<W>,
+{
+    /// Creates a new writer producing volumes of `volume_size` bytes each,
+    /// opening each volume with `open`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `volume_size` is not a multiple of 512 bytes or is smaller
+    /// than four blocks, the room needed for the headers starting a volume
+    /// and some data.
+    pub fn new(volume_size: u64, open: F) -> MultiVolumeWriter<W, F> {
+        assert!(
+            volume_size & (512 - 1) == 0 && volume_size >= 4 * 512,
+            "volume size must be a multiple of 512 bytes and at least 2048"
+        );
+        MultiVolumeWriter {
+            open,
+            current: None,
+            volumes: 0,
+            volume_size,
+            written: 0,
+            label: None,
+            header: Vec::with_capacity(512),
+            member: None,
+        }
+    }
+
+    /// Sets the label written at the start of each volume.
+    ///
+    /// Like GNU tar, the volume number is appended to the label, so the
+    /// first volume is labeled `<label> Volume 1` and so on. The label must
+    /// fit into the 100 bytes of a header's name field together with this
+    /// suffix, otherwise it's truncated.
+    pub fn label(&mut self, label: &str) {
+        self.label = Some(label.as_bytes().to_vec());
+    }
+
+    /// Returns the number of volumes opened so far.
+    pub fn volumes(&self) -> usize {
+        self.volumes
+    }
+
+    /// Unwrap this writer, returning the writer of the last volume if any
+    /// volume was opened.
+    pub fn into_inner(self) -> Option<W> {
+        self.current
+    }
+
+    fn next_volume(&mut self) -> io::Result<()> {
+        if let Some(mut volume) = self.current.take() {
+            volume.flush()?;
+        }
+        self.current = Some((self.open)(self.volumes)?);
+        self.volumes += 1;
+        self.written = 0;
+
+        if let Some(ref label) = self.label {
+            let mut name = label.clone();
+            name.extend_from_slice(format!(" Volume {}", self.volumes).as_bytes());
+            name.truncate(100);
+            let mut header = Header::new_gnu();
+            header.as_old_mut().name[..name.len()].copy_from_slice(&name);
+            header.set_entry_type(EntryType::GNUVolumeLabel);
+            header.set_size(0);
+            header.set_cksum();
+            self.write_header(&header)?;
+        }
+
+        if let Some(ref member) = self.member {
+            let mut header = Header::new_gnu();
+            header.as_old_mut().name = member.name;
+            header.set_entry_type(EntryType::GNUMultiVolume);
+            header.set_size(member.size - member.written);
+            header.set_mode(0);
+            header.set_mtime(0);
+            header.as_gnu_mut().unwrap().set_offset(member.written);
+            header.set_cksum();
+            self.write_header(&header)?;
+        }
+        Ok(())
+    }
+
+    fn write_header(&mut self, header: &Header) -> io::Result<()> {
+        self.current
+            .as_mut()
+            .unwrap()
+            .write_all(header.as_bytes())?;
+        self.written += 512;
+        Ok(())
+    }
+
+    fn start_member(&mut self) -> io::Result<()> {
+        // The blocks of zeros ending the archive aren't followed by any data.
+        if self.header.iter().all(|b| *b == 0) {
+            self.header.clear();
+            return Ok(());
+        }
+        let header = Header::from_byte_slice(&self.header);
+        let size = header.entry_size()?;
+        if header.entry_type().is_gnu_sparse() {
+            return Err(other("GNU sparse files can't be split across volumes"));
+        }
+        let padded = size
+            .checked_add(511)
+            .ok_or_else(|| other("size overflow"))?
+            & !511;
+        if padded > 0 {
+            let mut name = [0; 100];
+            name.copy_from_slice(&header.as_old().name);
+            self.member = Some(Member {
+                name,
+                size,
+                written: 0,
+                remaining: padded,
+            });
+        }
+        self.header.clear();
+        Ok(())
+    }
+}
+
+impl<W, F> Write for MultiVolumeWriter<W, F>
+where
+    W: Write,
+    F: FnMut(usize) -> io::Result<W>,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.current.is_none() || self.written == self.volume_size {
+            self.next_volume()?;
+        }
+        let room = self.volume_size - self.written;
+        let max = match self.member {
+            Some(ref member) => cmp::min(room, member.remaining),
+            None => cmp::min(room, 512 - self.header.len() as u64),
+        };
+        let len = cmp::min(buf.len() as u64, max) as usize;
+        let n = self.current.as_mut().unwrap().write(&buf[..len])?;
+        self.written += n as u64;
+
+        match self.member {
+            Some(ref mut member) => {
+                member.written += n as u64;
+                member.remaining -= n as u64;
+                if member.remaining == 0 {
+                    self.member = None;
+                }
+            }
+            None => {
+                self.header.extend_from_slice(&buf[..n]);
+                if self.header.len() == 512 {
+                    self.start_member()?;
+                }
+            }
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.current {
+            Some(ref mut volume) => volume.flush(),
+            None => Ok(()),
+        }
+    }
+}
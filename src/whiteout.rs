@@ -0,0 +1,147 @@
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// How the whiteout entries of OCI image layers are unpacked, see
+/// `Archive::set_whiteouts`.
+///
+/// A layer deletes a file of the layers below it with an empty file named
+/// `.wh.<name>` next to it, and hides everything below a directory with a
+/// `.wh..wh..opq` file in it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Whiteouts {
+    /// Unpack whiteouts as the empty regular files they are in the archive.
+    /// This is the default.
+    Keep,
+    /// Apply whiteouts to the destination, which holds the layers below:
+    /// `.wh.<name>` removes `<name>`, and `.wh..wh..opq` removes everything
+    /// below its directory which isn't unpacked from the same archive.
+    Apply,
+    /// Convert whiteouts to the form overlayfs uses, for unpacking a layer
+    /// into a directory of its own: `.wh.<name>` becomes a character device
+    /// `<name>` with the device number 0/0, and `.wh..wh..opq` sets the
+    /// `trusted.overlay.opaque` extended attribute of its directory to `y`.
+    ///
+    /// Creating these requires privileges and is only implemented on Linux.
+    Overlay,
+}
+
+/// A whiteout entry, see `Whiteouts`.
+pub(crate) enum Whiteout<'a> {
+    /// Hides the file with the given name.
+    Remove(&'a OsStr),
+    /// Hides everything in the directory of the entry.
+    Opaque,
+}
+
+impl<'a> Whiteout<'a> {
+    /// Returns the whiteout an entry named `name` stands for, if any. Only
+    /// plain file names can be hidden, so `.wh..` and `.wh...` aren't
+    /// whiteouts.
+    pub(crate) fn parse(name: &'a OsStr) -> Option<Whiteout<'a>> {
+        let name = name.to_str()?;
+        if name == ".wh..wh..opq" {
+            return Some(Whiteout::Opaque);
+        }
+        match name.strip_prefix(".wh.") {
+            Some(name)
+                if !name.is_empty()
+                    && name != "."
+                    && name != ".."
+                    && !name.chars().any(std::path::is_separator) =>
+            {
+                Some(Whiteout::Remove(OsStr::new(name)))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The paths unpacked from a layer so far along with their parents, which
+/// the whiteouts of the same layer don't remove.
+#[derive(Default)]
+pub(crate) struct Layer {
+    paths: HashSet<PathBuf>,
+}
+
+impl Layer {
+    pub(crate) fn insert(&mut self, rel: &Path) {
+        for ancestor in rel.ancestors() {
+            if !self.paths.insert(ancestor.to_path_buf()) {
+                break;
+            }
+        }
+    }
+
+    pub(crate) fn contains(&self, rel: &Path) -> bool {
+        self.paths.contains(rel)
+    }
+
+    /// Removes everything below the directory `rel` in `dst` which isn't
+    /// part of this layer.
+    pub(crate) fn remove_lower(&self, dst: &Path, rel: &Path) -> io::Result<()> {
+        for entry in fs::read_dir(dst.join(rel))? {
+            let entry = entry?;
+            let child = rel.join(entry.file_name());
+            if !self.contains(&child) {
+                remove(&dst.join(&child))?;
+            } else if entry.file_type()?.is_dir() {
+                self.remove_lower(dst, &child)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Removes the file or directory `path` without following symbolic links,
+/// succeeding if it doesn't exist.
+pub(crate) fn remove(path: &Path) -> io::Result<()> {
+    let ret = match path.symlink_metadata() {
+        Ok(ref m) if m.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(e) => Err(e),
+    };
+    match ret {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        ret => ret,
+    }
+}
+
+/// Creates the overlayfs whiteout `path`, a character device with the device
+/// number 0/0.
+#[cfg(target_os = "linux")]
+pub(crate) fn create_overlay_whiteout(path: &Path) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::prelude::*;
+
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    if unsafe { libc::mknod(path.as_ptr(), libc::S_IFCHR, 0) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn create_overlay_whiteout(_path: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "overlayfs whiteouts are only supported on Linux",
+    ))
+}
+
+/// Marks the directory `path` as opaque for overlayfs.
+#[cfg(all(target_os = "linux", feature = "xattr"))]
+pub(crate) fn set_overlay_opaque(path: &Path) -> io::Result<()> {
+    xattr::set(path, "trusted.overlay.opaque", b"y")
+}
+
+#[cfg(not(all(target_os = "linux", feature = "xattr")))]
+pub(crate) fn set_overlay_opaque(_path: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "overlayfs opaque directories are only supported on Linux with xattr support",
+    ))
+}
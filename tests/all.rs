@@ -11,7 +11,11 @@ use std::iter::repeat;
 use std::path::{Path, PathBuf};
 
 use filetime::FileTime;
-use tar::{Archive, Builder, Entries, EntryType, Header, HeaderMode};
+use tar::{
+    Archive, Builder, ChangeKind, Difference, DifferenceKind, Entries, EntryType, FindingKind,
+    Header, HeaderMode, LimitError, LimitKind, MetadataChange, MultiVolume, MultiVolumeWriter,
+    PortabilityChecker, PortabilityIssueKind, Streamer, Whiteouts,
+};
 use tempfile::{Builder as TempBuilder, TempDir};
 
 macro_rules! t {
@@ -78,6 +82,475 @@ fn simple_concat() {
     }
 }
 
+#[test]
+fn recover_damaged_headers() {
+    let mut ar = Builder::new(Vec::new());
+    for (name, len) in [("a", 10), ("b", 600), ("c", 10), ("d", 10)].iter() {
+        let mut header = Header::new_gnu();
+        header.set_size(*len as u64);
+        t!(ar.append_data(&mut header, name, &vec![b'x'; *len][..]));
+    }
+    let mut bytes = t!(ar.into_inner());
+    // Damage the headers of "b" and "d", the latter being followed by the end
+    // of the archive.
+    bytes[1024] ^= 1;
+    bytes[3584] ^= 1;
+
+    let mut ar = Archive::new(&bytes[..]);
+    let mut entries = t!(ar.entries());
+    assert_eq!(&*t!(entries.next().unwrap()).path_bytes(), b"a");
+    assert!(entries.next().unwrap().is_err());
+    assert!(entries.next().is_none());
+
+    let mut ar = Archive::new(&bytes[..]);
+    ar.set_recover(true);
+    let names = t!(ar.entries())
+        .map(|e| t!(e).path_bytes().into_owned())
+        .collect::<Vec<_>>();
+    assert_eq!(names, vec![b"a".to_vec(), b"c".to_vec()]);
+
+    let skipped = ar.skipped_regions();
+    assert_eq!(skipped.len(), 2);
+    assert_eq!(skipped[0].offset(), 1024);
+    assert_eq!(skipped[0].length(), 1536);
+    assert_eq!(skipped[0].reason(), "archive header checksum mismatch");
+    assert_eq!(skipped[1].offset(), 3584);
+    assert_eq!(skipped[1].length(), 1024);
+
+    // The skipped regions don't end at blocks of zeros when ignoring them.
+    let mut ar = Archive::new(&bytes[..]);
+    ar.set_recover(true);
+    ar.set_ignore_zeros(true);
+    assert_eq!(t!(ar.entries()).count(), 2);
+    let skipped = ar.skipped_regions();
+    assert_eq!(skipped[1].offset(), 3584);
+    assert_eq!(skipped[1].length(), bytes.len() as u64 - 3584);
+}
+
+fn findings(bytes: &[u8]) -> Vec<(FindingKind, u64, Option<Vec<u8>>)> {
+    let mut ar = Archive::new(bytes);
+    let report = t!(ar.validate());
+    assert_eq!(report.is_valid(), report.findings().is_empty());
+    report
+        .findings()
+        .iter()
+        .map(|f| (f.kind(), f.offset(), f.path_bytes().map(|p| p.to_vec())))
+        .collect()
+}
+
+#[test]
+fn validate_fixtures() {
+    assert_eq!(findings(tar!("simple.tar")), vec![]);
+    assert_eq!(findings(tar!("pax.tar")), vec![]);
+    assert_eq!(findings(tar!("sparse.tar")), vec![]);
+    assert_eq!(findings(tar!("7z_long_path.tar")), vec![]);
+
+    // The archive is padded to records of 10240 bytes, the last of which is
+    // cut short.
+    assert_eq!(
+        findings(tar!("simple_missing_last_header.tar")),
+        vec![(FindingKind::IncompleteRecord, 0, None)]
+    );
+}
+
+#[test]
+fn validate_members() {
+    fn raw_header(name: &[u8], entry_type: EntryType, link: &[u8]) -> Header {
+        let mut header = Header::new_gnu();
+        header.as_old_mut().name[..name.len()].copy_from_slice(name);
+        header.as_old_mut().linkname[..link.len()].copy_from_slice(link);
+        header.set_entry_type(entry_type);
+        header.set_mode(0o644);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_mtime(0);
+        header.set_size(0);
+        header.set_cksum();
+        header
+    }
+
+    let mut ar = Builder::new(Vec::new());
+    let members: &[(&[u8], EntryType, &[u8])] = &[
+        (b"/etc/passwd", EntryType::Regular, b""),
+        (b"a/../../x", EntryType::Regular, b""),
+        (b"file", EntryType::Regular, b""),
+        (b"./file", EntryType::Regular, b""),
+        (b"dir/ok", EntryType::Symlink, b"../file"),
+        (b"dir/bad", EntryType::Symlink, b"../../file"),
+        (b"abs", EntryType::Symlink, b"/etc"),
+        (b"hard", EntryType::Link, b"dir/../../file"),
+    ];
+    for (name, entry_type, link) in members.iter() {
+        t!(ar.append(&raw_header(name, *entry_type, link), io::empty()));
+    }
+
+    // A numeric field which isn't octal.
+    let mut header = raw_header(b"mode", EntryType::Regular, b"");
+    header.as_old_mut().mode = *b"rw-r--r-";
+    header.set_cksum();
+    t!(ar.append(&header, io::empty()));
+
+    // A pax record whose length prefix is wrong.
+    let record = b"99 path=foo\n";
+    let mut header = raw_header(b"pax", EntryType::XHeader, b"");
+    header.set_size(record.len() as u64);
+    header.set_cksum();
+    t!(ar.append(&header, &record[..]));
+    t!(ar.append(
+        &raw_header(b"after_pax", EntryType::Regular, b""),
+        io::empty()
+    ));
+
+    // A damaged header followed by a valid one.
+    let mut header = raw_header(b"damaged", EntryType::Regular, b"");
+    header.as_old_mut().cksum[0] = b'7';
+    t!(ar.append(&header, io::empty()));
+    t!(ar.append(&raw_header(b"last", EntryType::Regular, b""), io::empty()));
+    let bytes = t!(ar.into_inner());
+
+    let path = |p: &[u8]| Some(p.to_vec());
+    assert_eq!(
+        findings(&bytes),
+        vec![
+            (FindingKind::AbsolutePath, 0, path(b"/etc/passwd")),
+            (FindingKind::ParentDirPath, 512, path(b"a/../../x")),
+            (FindingKind::DuplicatePath, 1536, path(b"./file")),
+            (FindingKind::LinkOutsideRoot, 2560, path(b"dir/bad")),
+            (FindingKind::LinkOutsideRoot, 3072, path(b"abs")),
+            (FindingKind::LinkOutsideRoot, 3584, path(b"hard")),
+            (FindingKind::InvalidNumericField, 4096, path(b"mode")),
+            (FindingKind::InvalidPaxRecord, 4608, path(b"pax")),
+            (FindingKind::ChecksumMismatch, 6144, path(b"damaged")),
+        ]
+    );
+}
+
+#[test]
+fn validate_truncated() {
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(1000);
+    header.set_mode(0o644);
+    header.set_uid(0);
+    header.set_gid(0);
+    header.set_mtime(0);
+    t!(ar.append_data(&mut header, "file", &[0; 1000][..]));
+    let bytes = t!(ar.into_inner());
+
+    // Data of the member is missing.
+    assert_eq!(
+        findings(&bytes[..1024]),
+        vec![(FindingKind::Truncated, 0, Some(b"file".to_vec()))]
+    );
+    // Both blocks of zeros are missing.
+    assert_eq!(
+        findings(&bytes[..1536]),
+        vec![(FindingKind::MissingEndOfArchive, 1536, None)]
+    );
+    // One block of zeros is missing.
+    assert_eq!(
+        findings(&bytes[..2048]),
+        vec![(FindingKind::MissingEndOfArchive, 1536, None)]
+    );
+    assert_eq!(findings(&bytes), vec![]);
+}
+
+#[test]
+fn validate_sparse_map() {
+    let bytes = append_pax_sparse(
+        &[
+            ("GNU.sparse.size", b"6144"),
+            ("GNU.sparse.numblocks", b"3"),
+            ("GNU.sparse.map", b"0,6,4096,6"),
+            ("GNU.sparse.name", b"file"),
+        ],
+        b"hello\nworld\n",
+    );
+    let sparse = findings(&bytes)
+        .into_iter()
+        .filter(|f| f.0 == FindingKind::InvalidSparseMap)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        sparse,
+        vec![(FindingKind::InvalidSparseMap, 1024, Some(b"file".to_vec()))]
+    );
+}
+
+#[test]
+fn portability_checker() {
+    let mut ar = Builder::new(Vec::new());
+    let paths = [
+        "dir/Makefile",
+        "DIR/other",
+        "dir/makefile",
+        "caf\u{e9}",
+        "cafe\u{301}",
+        "aux.c",
+        "con",
+        "a:b",
+        "trailing. ",
+        "ok",
+    ];
+    for path in paths.iter() {
+        let mut header = Header::new_gnu();
+        header.set_size(0);
+        t!(ar.append_data(&mut header, path, io::empty()));
+    }
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Symlink);
+    header.set_size(0);
+    t!(ar.append_link(&mut header, "link", "ok"));
+    let long = "a/".repeat(150);
+    let mut header = Header::new_gnu();
+    header.set_size(0);
+    t!(ar.append_data(&mut header, &long, io::empty()));
+    let bytes = t!(ar.into_inner());
+
+    let mut checker = PortabilityChecker::new();
+    let mut ar = Archive::new(&bytes[..]);
+    for entry in t!(ar.entries()) {
+        checker.check_entry(&t!(entry));
+    }
+    let issues = checker
+        .issues()
+        .iter()
+        .map(|i| {
+            (
+                i.kind(),
+                String::from_utf8(i.path_bytes().to_vec()).unwrap(),
+            )
+        })
+        .collect::<Vec<_>>();
+    #[allow(unused_mut)]
+    let mut expected = vec![
+        (PortabilityIssueKind::CaseCollision, "DIR/other".to_string()),
+        (
+            PortabilityIssueKind::CaseCollision,
+            "dir/makefile".to_string(),
+        ),
+        (
+            PortabilityIssueKind::NormalizationCollision,
+            "cafe\u{301}".to_string(),
+        ),
+        (PortabilityIssueKind::ReservedName, "aux.c".to_string()),
+        (PortabilityIssueKind::ReservedName, "con".to_string()),
+        (PortabilityIssueKind::ReservedCharacter, "a:b".to_string()),
+        (
+            PortabilityIssueKind::TrailingDotOrSpace,
+            "trailing. ".to_string(),
+        ),
+        (
+            PortabilityIssueKind::UnsupportedEntryType,
+            "link".to_string(),
+        ),
+        (
+            PortabilityIssueKind::PathTooLong,
+            long.trim_end_matches('/').to_string(),
+        ),
+    ];
+    // Normalization is only compared with the `unicode-normalization` feature.
+    #[cfg(not(feature = "unicode-normalization"))]
+    expected.retain(|i| i.0 != PortabilityIssueKind::NormalizationCollision);
+    assert_eq!(issues, expected);
+}
+
+#[test]
+fn builder_check_portability() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    t!(fs::create_dir(td.path().join("sub")));
+    t!(File::create(td.path().join("sub/nul")));
+
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(0);
+    t!(ar.append_data(&mut header, "Sub", io::empty()));
+    assert!(ar.portability_issues().is_empty());
+
+    ar.check_portability(true);
+    t!(ar.append_dir_all("sub", td.path().join("sub")));
+    let issues = ar
+        .portability_issues()
+        .iter()
+        .map(|i| (i.kind(), i.path_bytes().to_vec()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        issues,
+        vec![(PortabilityIssueKind::ReservedName, b"sub/nul".to_vec())]
+    );
+
+    ar.check_portability(false);
+    assert!(ar.portability_issues().is_empty());
+}
+
+fn limit_error(e: &io::Error) -> (LimitKind, String, u64) {
+    let e = e.get_ref().unwrap().downcast_ref::<LimitError>().unwrap();
+    let path = String::from_utf8(e.path_bytes().to_vec()).unwrap();
+    (e.kind(), path, e.position())
+}
+
+#[test]
+fn limit_metadata_size() {
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(0);
+    t!(ar.append_data(&mut header, "a".repeat(200), io::empty()));
+    let bytes = t!(ar.into_inner());
+
+    let mut ar = Archive::new(&bytes[..]);
+    ar.set_max_metadata_size(201);
+    assert_eq!(t!(ar.entries()).count(), 1);
+
+    let mut ar = Archive::new(&bytes[..]);
+    ar.set_max_metadata_size(200);
+    let err = t!(ar.entries()).next().unwrap().err().unwrap();
+    assert_eq!(
+        limit_error(&err),
+        (LimitKind::MetadataSize, "././@LongLink".to_string(), 0)
+    );
+    // Validation reads the long name into memory as well.
+    let mut ar = Archive::new(&bytes[..]);
+    ar.set_max_metadata_size(201);
+    assert!(ar.validate().is_ok());
+
+    let mut ar = Archive::new(&bytes[..]);
+    ar.set_max_metadata_size(200);
+    let err = ar.validate().err().unwrap();
+    assert_eq!(
+        limit_error(&err),
+        (LimitKind::MetadataSize, "././@LongLink".to_string(), 0)
+    );
+}
+
+#[test]
+fn limit_entries_and_sizes() {
+    let mut ar = Archive::new(tar!("reading_files.tar"));
+    ar.set_max_entries(1);
+    let mut entries = t!(ar.entries());
+    assert!(entries.next().unwrap().is_ok());
+    let err = entries.next().unwrap().err().unwrap();
+    assert_eq!(
+        limit_error(&err),
+        (LimitKind::Entries, "b".to_string(), 1024)
+    );
+
+    let mut ar = Archive::new(tar!("reading_files.tar"));
+    ar.set_max_total_size(43);
+    let mut entries = t!(ar.entries());
+    assert!(entries.next().unwrap().is_ok());
+    let err = entries.next().unwrap().err().unwrap();
+    assert_eq!(
+        limit_error(&err),
+        (LimitKind::TotalSize, "b".to_string(), 1024)
+    );
+
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(0);
+    t!(ar.append_data(&mut header, "a/b/c/", io::empty()));
+    t!(ar.append_data(&mut header, "a/b/c/d", io::empty()));
+    let bytes = t!(ar.into_inner());
+
+    let mut ar = Archive::new(&bytes[..]);
+    ar.set_max_path_depth(3);
+    let mut entries = t!(ar.entries());
+    assert!(entries.next().unwrap().is_ok());
+    let err = entries.next().unwrap().err().unwrap();
+    assert_eq!(
+        limit_error(&err),
+        (LimitKind::PathDepth, "a/b/c/d".to_string(), 512)
+    );
+
+    let mut ar = Archive::new(&bytes[..]);
+    ar.set_max_path_len(6);
+    let mut entries = t!(ar.entries());
+    assert!(entries.next().unwrap().is_ok());
+    let err = entries.next().unwrap().err().unwrap();
+    assert_eq!(
+        limit_error(&err),
+        (LimitKind::PathLength, "a/b/c/d".to_string(), 512)
+    );
+}
+
+#[test]
+fn limit_total_size_pax_sparse() {
+    let mut map_1_0 = b"1\n0\n6\n".to_vec();
+    map_1_0.resize(512, 0);
+    map_1_0.extend_from_slice(b"hello\n");
+    let archives = [
+        append_pax_sparse(
+            &[
+                ("GNU.sparse.size", b"1099511627776"),
+                ("GNU.sparse.numblocks", b"1"),
+                ("GNU.sparse.offset", b"0"),
+                ("GNU.sparse.numbytes", b"6"),
+                ("GNU.sparse.name", b"file"),
+            ],
+            b"hello\n",
+        ),
+        append_pax_sparse(
+            &[
+                ("GNU.sparse.size", b"1099511627776"),
+                ("GNU.sparse.numblocks", b"1"),
+                ("GNU.sparse.map", b"0,6"),
+                ("GNU.sparse.name", b"file"),
+            ],
+            b"hello\n",
+        ),
+        append_pax_sparse(
+            &[
+                ("GNU.sparse.major", b"1"),
+                ("GNU.sparse.minor", b"0"),
+                ("GNU.sparse.name", b"file"),
+                ("GNU.sparse.realsize", b"1099511627776"),
+            ],
+            &map_1_0,
+        ),
+    ];
+    for bytes in archives.iter() {
+        let mut ar = Archive::new(&bytes[..]);
+        ar.set_max_total_size(1 << 20);
+        let err = t!(ar.entries()).next().unwrap().err().unwrap();
+        assert_eq!(limit_error(&err).0, LimitKind::TotalSize);
+
+        let mut ar = Archive::new(&bytes[..]);
+        ar.set_max_total_size(1 << 40);
+        let entry = t!(t!(ar.entries()).next().unwrap());
+        assert_eq!(entry.size(), 1 << 40);
+    }
+}
+
+#[test]
+fn limit_sparse_ratio() {
+    let mut ar = Archive::new(tar!("sparse.tar"));
+    ar.set_max_sparse_ratio(8);
+    let paths = t!(ar.entries())
+        .map(|e| match e {
+            Ok(e) => Ok(e.path_bytes().into_owned()),
+            Err(e) => Err(limit_error(&e)),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        paths,
+        vec![
+            Ok(b"sparse_begin.txt".to_vec()),
+            Ok(b"sparse_end.txt".to_vec()),
+            Err((LimitKind::SparseRatio, "sparse_ext.txt".to_string(), 2048)),
+        ]
+    );
+}
+
+#[test]
+fn limit_unpack() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let mut ar = Archive::new(tar!("reading_files.tar"));
+    ar.set_max_entries(1);
+    let err = ar.unpack(td.path()).err().unwrap();
+    assert_eq!(
+        limit_error(&err),
+        (LimitKind::Entries, "b".to_string(), 1024)
+    );
+}
+
 #[test]
 fn header_impls() {
     let mut ar = Archive::new(Cursor::new(tar!("simple.tar")));
@@ -402,6 +875,252 @@ fn no_xattrs() {
     );
 }
 
+#[test]
+#[cfg(all(target_os = "linux", feature = "xattr"))]
+fn acls() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir_in("/var/tmp"));
+    // Skip if the filesystem doesn't support ACLs.
+    let probe = td.path().join("probe");
+    t!(File::create(&probe));
+    let minimal = [
+        2, 0, 0, 0, 1, 0, 6, 0, 255, 255, 255, 255, 4, 0, 4, 0, 255, 255, 255, 255, 32, 0, 0, 0,
+        255, 255, 255, 255,
+    ];
+    if xattr::set(&probe, "system.posix_acl_access", &minimal).is_err() {
+        return;
+    }
+
+    let access = "user::rwx,user:54321:r-x:54321,group::r-x,mask::r-x,other::---";
+    let default = "user::rwx,group::r-x,other::---";
+    let file_access = "user::rw-,user:54321:r--:54321,group::r--,mask::r--,other::---";
+    let mut ar = Builder::new(Vec::new());
+    append_pax(
+        &mut ar,
+        &[
+            ("SCHILY.acl.access", access.as_bytes()),
+            ("SCHILY.acl.default", default.as_bytes()),
+        ],
+    );
+    let mut header = Header::new_ustar();
+    t!(header.set_path("d"));
+    header.set_entry_type(EntryType::Directory);
+    header.set_mode(0o750);
+    header.set_size(0);
+    header.set_cksum();
+    t!(ar.append(&header, io::empty()));
+    append_pax(&mut ar, &[("SCHILY.acl.access", file_access.as_bytes())]);
+    let mut header = Header::new_ustar();
+    t!(header.set_path("d/f"));
+    header.set_mode(0o640);
+    header.set_size(2);
+    header.set_cksum();
+    t!(ar.append(&header, &b"hi"[..]));
+    let data = t!(ar.into_inner());
+
+    let unpacked = td.path().join("without");
+    t!(Archive::new(&data[..]).unpack(&unpacked));
+    assert_eq!(
+        t!(xattr::get(unpacked.join("d/f"), "system.posix_acl_access")),
+        None
+    );
+
+    let unpacked = td.path().join("with");
+    let mut ar = Archive::new(&data[..]);
+    ar.set_unpack_acls(true);
+    t!(ar.unpack(&unpacked));
+    let acl = t!(xattr::get(unpacked.join("d/f"), "system.posix_acl_access")).unwrap();
+    assert_eq!(acl.len(), 4 + 5 * 8);
+    let acl = t!(xattr::get(unpacked.join("d"), "system.posix_acl_default")).unwrap();
+    assert_eq!(acl.len(), 4 + 3 * 8);
+
+    // Archiving the unpacked files again captures the same ACLs, names of
+    // users which don't exist are left out.
+    let mut ar = Builder::new(Vec::new());
+    ar.capture_acls(true);
+    t!(ar.append_dir("d", unpacked.join("d")));
+    t!(ar.append_path_with_name(unpacked.join("d/f"), "d/f"));
+    let data = t!(ar.into_inner());
+    let mut ar = Archive::new(&data[..]);
+    let mut records = Vec::new();
+    for entry in t!(ar.entries()) {
+        let mut entry = t!(entry);
+        let path = t!(entry.path()).into_owned();
+        for ext in t!(entry.pax_extensions()).unwrap() {
+            let ext = t!(ext);
+            records.push((
+                path.clone(),
+                t!(ext.key()).to_string(),
+                t!(ext.value()).to_string(),
+            ));
+        }
+    }
+    let path = |p: &str| PathBuf::from(p);
+    assert_eq!(
+        records,
+        vec![
+            (
+                path("d"),
+                "SCHILY.acl.access".to_string(),
+                access.replace(":54321:r-x:54321", ":54321:r-x"),
+            ),
+            (
+                path("d"),
+                "SCHILY.acl.default".to_string(),
+                default.to_string(),
+            ),
+            (
+                path("d/f"),
+                "SCHILY.acl.access".to_string(),
+                file_access.replace(":54321:r--:54321", ":54321:r--"),
+            ),
+        ]
+    );
+}
+
+#[test]
+#[cfg(all(unix, feature = "xattr"))]
+fn ownership_xattr() {
+    // If /tmp is a tmpfs, xattr will fail
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir_in("/var/tmp"));
+    let name = "user.containers.override_stat";
+
+    let mut ar = Builder::new(Vec::new());
+    for &(path, entry_type, uid, mode) in &[
+        ("d", EntryType::Directory, 0, 0o755),
+        ("d/f", EntryType::Regular, 1000, 0o4755),
+        ("d/null", EntryType::Char, 0, 0o666),
+    ] {
+        let mut header = Header::new_gnu();
+        t!(header.set_path(path));
+        header.set_entry_type(entry_type);
+        header.set_size(0);
+        header.set_uid(uid);
+        header.set_gid(uid / 10);
+        header.set_mode(mode);
+        if entry_type == EntryType::Char {
+            t!(header.set_device_major(1));
+            t!(header.set_device_minor(3));
+        }
+        header.set_cksum();
+        t!(ar.append(&header, io::empty()));
+    }
+    let data = t!(ar.into_inner());
+
+    let unpacked = td.path().join("unpacked");
+    let mut ar = Archive::new(&data[..]);
+    ar.set_preserve_ownerships(true);
+    ar.set_numeric_owner(true);
+    ar.set_ownership_xattr(name);
+    t!(ar.unpack(&unpacked));
+    let value = |path: &str| t!(xattr::get(unpacked.join(path), name)).unwrap();
+    assert_eq!(value("d"), b"0:0:0755:dir");
+    assert_eq!(value("d/f"), b"1000:100:04755:file");
+    assert_eq!(value("d/null"), b"0:0:0666:char-1-3");
+    assert!(t!(fs::metadata(unpacked.join("d/null"))).is_file());
+
+    let check = |data: &[u8]| {
+        let mut ar = Archive::new(data);
+        let mut entries = t!(ar.entries())
+            .map(|e| {
+                let e = t!(e);
+                let header = e.header();
+                (
+                    t!(e.path()).into_owned(),
+                    header.entry_type(),
+                    t!(header.uid()),
+                    t!(header.gid()),
+                    t!(header.mode()),
+                    t!(header.device_major()),
+                )
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            entries,
+            vec![
+                (
+                    PathBuf::from("d"),
+                    EntryType::Directory,
+                    0,
+                    0,
+                    0o755,
+                    Some(0)
+                ),
+                (
+                    PathBuf::from("d/f"),
+                    EntryType::Regular,
+                    1000,
+                    100,
+                    0o4755,
+                    Some(0)
+                ),
+                (
+                    PathBuf::from("d/null"),
+                    EntryType::Char,
+                    0,
+                    0,
+                    0o666,
+                    Some(1)
+                ),
+            ]
+        );
+    };
+
+    let mut ar = Builder::new(Vec::new());
+    ar.ownership_xattr(name);
+    t!(ar.append_dir_all("d", unpacked.join("d")));
+    check(&t!(ar.into_inner()));
+
+    let mut streamer = Streamer::new();
+    streamer.ownership_xattr(name);
+    t!(streamer.append_dir_all("d", unpacked.join("d")));
+    let mut data = Vec::new();
+    t!(streamer.read_to_end(&mut data));
+    check(&data);
+
+    // Deterministic headers keep their normalized ownership and permissions,
+    // but devices are still restored.
+    let deterministic = |data: &[u8]| {
+        let mut ar = Archive::new(data);
+        let mut entries = t!(ar.entries())
+            .map(|e| {
+                let e = t!(e);
+                let header = e.header();
+                (
+                    t!(e.path()).into_owned(),
+                    header.entry_type(),
+                    t!(header.uid()),
+                    t!(header.gid()),
+                    t!(header.mode()),
+                )
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            entries,
+            vec![
+                (PathBuf::from("d"), EntryType::Directory, 0, 0, 0o755),
+                (PathBuf::from("d/f"), EntryType::Regular, 0, 0, 0o755),
+                (PathBuf::from("d/null"), EntryType::Char, 0, 0, 0o644),
+            ]
+        );
+    };
+
+    let mut ar = Builder::new(Vec::new());
+    ar.mode(HeaderMode::Deterministic);
+    ar.ownership_xattr(name);
+    t!(ar.append_dir_all("d", unpacked.join("d")));
+    deterministic(&t!(ar.into_inner()));
+
+    let mut streamer = Streamer::new();
+    streamer.mode(HeaderMode::Deterministic);
+    streamer.ownership_xattr(name);
+    t!(streamer.append_dir_all("d", unpacked.join("d")));
+    let mut data = Vec::new();
+    t!(streamer.read_to_end(&mut data));
+    deterministic(&data);
+}
+
 #[test]
 fn writing_and_extracting_directories() {
     let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
@@ -868,6 +1587,154 @@ fn pax_linkpath() {
     assert!(link_name.ends_with("ccccccccccccccc"));
 }
 
+/// Encodes the given key/value pairs as the body of a pax extensions entry.
+fn pax_records(records: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for (key, value) in records {
+        // The length prefix counts itself, so grow it until it's stable.
+        let rest = key.len() + value.len() + 3;
+        let mut len = rest + 1;
+        while len != rest + len.to_string().len() {
+            len = rest + len.to_string().len();
+        }
+        data.extend_from_slice(format!("{} {}=", len, key).as_bytes());
+        data.extend_from_slice(value);
+        data.push(b'\n');
+    }
+    data
+}
+
+fn append_pax<W: Write>(ar: &mut Builder<W>, records: &[(&str, &[u8])]) {
+    let data = pax_records(records);
+    let mut header = Header::new_ustar();
+    t!(header.set_path("PaxHeaders/entry"));
+    header.set_size(data.len() as u64);
+    header.set_entry_type(EntryType::XHeader);
+    header.set_cksum();
+    t!(ar.append(&header, &data[..]));
+}
+
+#[test]
+fn pax_metadata_overrides() {
+    let mut ar = Builder::new(Vec::new());
+    append_pax(
+        &mut ar,
+        &[
+            ("mtime", b"1234567890.123456789"),
+            ("atime", b"1234500000.5"),
+            ("uid", b"4000000000"),
+            ("gid", b"3000000"),
+            ("uname", b"a-user-name-that-is-longer-than-32-bytes"),
+            ("gname", b"staff"),
+        ],
+    );
+    let mut header = Header::new_ustar();
+    t!(header.set_path("file"));
+    header.set_size(2);
+    header.set_mtime(1);
+    header.set_uid(1);
+    header.set_gid(1);
+    t!(header.set_username("short"));
+    header.set_cksum();
+    t!(ar.append(&header, &b"hi"[..]));
+    let data = t!(ar.into_inner());
+
+    let mut ar = Archive::new(&data[..]);
+    let mut entries = t!(ar.entries());
+    let entry = t!(entries.next().unwrap());
+    assert_eq!(t!(entry.mtime()), 1234567890);
+    assert_eq!(t!(entry.atime()), Some(1234500000));
+    assert_eq!(t!(entry.uid()), 4000000000);
+    assert_eq!(t!(entry.gid()), 3000000);
+    assert_eq!(
+        entry.username(),
+        Ok(Some("a-user-name-that-is-longer-than-32-bytes"))
+    );
+    assert_eq!(entry.groupname(), Ok(Some("staff")));
+    assert_eq!(t!(entry.header().mtime()), 1);
+    assert!(entries.next().is_none());
+
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let mut ar = Archive::new(&data[..]);
+    t!(ar.unpack(td.path()));
+    let meta = t!(fs::metadata(td.path().join("file")));
+    let mtime = FileTime::from_last_modification_time(&meta);
+    assert_eq!(mtime.unix_seconds(), 1234567890);
+    if mtime.nanoseconds() != 0 {
+        assert_eq!(mtime.nanoseconds(), 123456789);
+    }
+    let atime = FileTime::from_last_access_time(&meta);
+    assert_eq!(atime.unix_seconds(), 1234500000);
+}
+
+#[test]
+fn pax_metadata_defaults_to_header() {
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    t!(header.set_path("file"));
+    header.set_size(0);
+    header.set_mtime(1000);
+    header.set_uid(10);
+    header.set_gid(20);
+    t!(header.set_username("user"));
+    t!(header.set_groupname("group"));
+    header.set_cksum();
+    t!(ar.append(&header, io::empty()));
+    let data = t!(ar.into_inner());
+
+    let mut ar = Archive::new(&data[..]);
+    let entry = t!(t!(ar.entries()).next().unwrap());
+    assert_eq!(t!(entry.mtime()), 1000);
+    assert_eq!(t!(entry.atime()), None);
+    assert_eq!(t!(entry.uid()), 10);
+    assert_eq!(t!(entry.gid()), 20);
+    assert_eq!(entry.username(), Ok(Some("user")));
+    assert_eq!(entry.groupname(), Ok(Some("group")));
+}
+
+#[test]
+fn pax_metadata_malformed() {
+    let mut ar = Builder::new(Vec::new());
+    append_pax(&mut ar, &[("mtime", b"12a"), ("uid", b"-1"), ("gid", b"x")]);
+    let mut header = Header::new_ustar();
+    t!(header.set_path("file"));
+    header.set_size(0);
+    header.set_mtime(1000);
+    header.set_mode(0o644);
+    #[cfg(unix)]
+    unsafe {
+        header.set_uid(libc::getuid().into());
+        header.set_gid(libc::getgid().into());
+    }
+    header.set_cksum();
+    t!(ar.append(&header, io::empty()));
+    let data = t!(ar.into_inner());
+
+    let mut ar = Archive::new(&data[..]);
+    let entry = t!(t!(ar.entries()).next().unwrap());
+    assert!(entry.mtime().is_err());
+    assert!(entry.uid().is_err());
+    assert!(entry.gid().is_err());
+
+    // Unpacking falls back to the mtime, uid and gid of the header.
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let mut ar = Archive::new(&data[..]);
+    ar.set_preserve_ownerships(true);
+    ar.set_numeric_owner(true);
+    t!(ar.unpack(td.path()));
+    let meta = t!(fs::metadata(td.path().join("file")));
+    let mtime = FileTime::from_last_modification_time(&meta);
+    assert_eq!(mtime.unix_seconds(), 1000);
+
+    // So does comparing against the unpacked file.
+    #[cfg(unix)]
+    {
+        let mut ar = Archive::new(&data[..]);
+        ar.set_numeric_owner(true);
+        assert_eq!(t!(ar.compare(td.path(), true)), []);
+    }
+}
+
 #[test]
 fn long_name_trailing_nul() {
     let mut b = Builder::new(Vec::<u8>::new());
@@ -1089,6 +1956,238 @@ fn sparse_with_trailing() {
     assert_eq!(&s[0x100_000..], "1MB through\n");
 }
 
+fn append_pax_sparse(records: &[(&str, &[u8])], data: &[u8]) -> Vec<u8> {
+    let mut ar = Builder::new(Vec::new());
+    append_pax(&mut ar, records);
+    let mut header = Header::new_ustar();
+    t!(header.set_path("GNUSparseFile.0/file"));
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    t!(ar.append(&header, data));
+    t!(ar.into_inner())
+}
+
+fn check_pax_sparse(archive: Vec<u8>) {
+    let mut ar = Archive::new(Cursor::new(&archive[..]));
+    let mut entries = t!(ar.entries());
+    let mut a = t!(entries.next().unwrap());
+    assert_eq!(t!(a.path()), Path::new("file"));
+    assert_eq!(a.size(), 0x1800);
+    let mut s = Vec::new();
+    t!(a.read_to_end(&mut s));
+    assert_eq!(s.len(), 0x1800);
+    assert_eq!(&s[..6], b"hello\n");
+    assert!(s[6..0x1000].iter().all(|b| *b == 0));
+    assert_eq!(&s[0x1000..0x1006], b"world\n");
+    assert!(s[0x1006..].iter().all(|b| *b == 0));
+    assert!(entries.next().is_none());
+
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let mut ar = Archive::new(Cursor::new(&archive[..]));
+    t!(ar.unpack(td.path()));
+    assert!(!td.path().join("GNUSparseFile.0").exists());
+    assert_eq!(t!(fs::read(td.path().join("file"))), s);
+}
+
+#[test]
+fn pax_sparse_0_0() {
+    check_pax_sparse(append_pax_sparse(
+        &[
+            ("GNU.sparse.size", b"6144"),
+            ("GNU.sparse.numblocks", b"2"),
+            ("GNU.sparse.offset", b"0"),
+            ("GNU.sparse.numbytes", b"6"),
+            ("GNU.sparse.offset", b"4096"),
+            ("GNU.sparse.numbytes", b"6"),
+            ("GNU.sparse.name", b"file"),
+        ],
+        b"hello\nworld\n",
+    ));
+}
+
+#[test]
+fn pax_sparse_0_1() {
+    check_pax_sparse(append_pax_sparse(
+        &[
+            ("GNU.sparse.size", b"6144"),
+            ("GNU.sparse.numblocks", b"2"),
+            ("GNU.sparse.map", b"0,6,4096,6"),
+            ("GNU.sparse.name", b"file"),
+        ],
+        b"hello\nworld\n",
+    ));
+}
+
+#[test]
+fn pax_sparse_1_0() {
+    let mut data = b"2\n0\n6\n4096\n6\n".to_vec();
+    data.resize(512, 0);
+    data.extend_from_slice(b"hello\nworld\n");
+    check_pax_sparse(append_pax_sparse(
+        &[
+            ("GNU.sparse.major", b"1"),
+            ("GNU.sparse.minor", b"0"),
+            ("GNU.sparse.name", b"file"),
+            ("GNU.sparse.realsize", b"6144"),
+        ],
+        &data,
+    ));
+}
+
+#[test]
+fn pax_sparse_malformed() {
+    let bad: &[&[(&str, &[u8])]] = &[
+        // map describes more data than is stored
+        &[
+            ("GNU.sparse.size", b"6144"),
+            ("GNU.sparse.map", b"0,6,4096,60"),
+        ],
+        // map extends past the real size
+        &[
+            ("GNU.sparse.size", b"100"),
+            ("GNU.sparse.map", b"0,6,4096,6"),
+        ],
+        // overlapping blocks
+        &[
+            ("GNU.sparse.size", b"6144"),
+            ("GNU.sparse.map", b"4096,6,0,6"),
+        ],
+        // odd number of values
+        &[
+            ("GNU.sparse.size", b"6144"),
+            ("GNU.sparse.map", b"0,6,4096"),
+        ],
+        // wrong block count
+        &[
+            ("GNU.sparse.size", b"6144"),
+            ("GNU.sparse.numblocks", b"3"),
+            ("GNU.sparse.map", b"0,6,4096,6"),
+        ],
+        // missing real size
+        &[("GNU.sparse.map", b"0,6,4096,6")],
+        // unknown version
+        &[("GNU.sparse.major", b"2"), ("GNU.sparse.minor", b"0")],
+    ];
+    for records in bad {
+        let archive = append_pax_sparse(records, b"hello\nworld\n");
+        let mut ar = Archive::new(Cursor::new(&archive[..]));
+        let mut entries = t!(ar.entries());
+        assert!(entries.next().unwrap().is_err());
+    }
+}
+
+fn multi_volume_contents() -> String {
+    (1..=1200).map(|i| format!("{}\n", i)).collect()
+}
+
+#[test]
+fn reading_multi_volume() {
+    let volumes = vec![
+        tar!("multivolume-1.tar"),
+        tar!("multivolume-2.tar"),
+        tar!("multivolume-3.tar"),
+        tar!("multivolume-4.tar"),
+    ];
+    let mut ar = Archive::new(MultiVolume::new(volumes));
+    {
+        let mut entries = t!(ar.entries());
+
+        let a = t!(entries.next().unwrap());
+        assert_eq!(a.header().entry_type(), EntryType::GNUVolumeLabel);
+        assert_eq!(&*a.path_bytes(), b"label Volume 1");
+
+        let mut a = t!(entries.next().unwrap());
+        assert_eq!(&*a.path_bytes(), b"big.txt");
+        let mut s = String::new();
+        t!(a.read_to_string(&mut s));
+        assert_eq!(s, multi_volume_contents());
+
+        let mut a = t!(entries.next().unwrap());
+        assert_eq!(&*a.path_bytes(), b"small.txt");
+        let mut s = String::new();
+        t!(a.read_to_string(&mut s));
+        assert_eq!(s, "small\n");
+
+        assert!(entries.next().is_none());
+    }
+    let labels = (1..=4)
+        .map(|i| format!("label Volume {}", i).into_bytes())
+        .collect::<Vec<_>>();
+    assert_eq!(ar.volume_labels(), labels);
+}
+
+#[test]
+fn reading_single_volume_of_multi_volume() {
+    let mut ar = Archive::new(tar!("multivolume-2.tar"));
+    let mut entries = t!(ar.entries());
+
+    let a = t!(entries.next().unwrap());
+    assert_eq!(a.header().entry_type(), EntryType::GNUVolumeLabel);
+
+    let a = t!(entries.next().unwrap());
+    assert_eq!(a.header().entry_type(), EntryType::GNUMultiVolume);
+    assert_eq!(&*a.path_bytes(), b"big.txt");
+    assert_eq!(t!(a.header().as_gnu().unwrap().offset()), 2048);
+    assert_eq!(a.size(), 2845);
+
+    // A continued member can't be restored on its own.
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let mut ar = Archive::new(tar!("multivolume-3.tar"));
+    t!(ar.unpack(td.path()));
+    assert!(!td.path().join("big.txt").exists());
+    assert_eq!(
+        t!(fs::read_to_string(td.path().join("small.txt"))),
+        "small\n"
+    );
+}
+
+#[test]
+fn multi_volume_out_of_order() {
+    let volumes = vec![tar!("multivolume-1.tar"), tar!("multivolume-3.tar")];
+    let mut ar = Archive::new(MultiVolume::new(volumes));
+    let mut entries = t!(ar.entries());
+    t!(entries.next().unwrap());
+    let mut a = t!(entries.next().unwrap());
+    assert!(a.read_to_end(&mut Vec::new()).is_err());
+}
+
+#[test]
+fn writing_multi_volume() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let dir = td.path().to_path_buf();
+    let contents = multi_volume_contents();
+
+    let mut volumes =
+        MultiVolumeWriter::new(4096, |i| File::create(dir.join(format!("{}.tar", i))));
+    volumes.label("label");
+    let mut ar = Builder::new(volumes);
+    let mut header = Header::new_gnu();
+    header.set_size(contents.len() as u64);
+    t!(ar.append_data(&mut header, "big.txt", contents.as_bytes()));
+    let mut header = Header::new_gnu();
+    header.set_size(6);
+    t!(ar.append_data(&mut header, "small.txt", &b"small\n"[..]));
+    let volumes = t!(ar.into_inner());
+    assert_eq!(volumes.volumes(), 3);
+    drop(volumes);
+
+    let paths = (0..3).map(|i| td.path().join(format!("{}.tar", i)));
+    for path in paths.clone() {
+        assert!(t!(fs::metadata(path)).len() <= 4096);
+    }
+    let files = paths.map(|path| t!(File::open(path)));
+    let mut ar = Archive::new(MultiVolume::new(files));
+    let out = td.path().join("out");
+    t!(ar.unpack(&out));
+    assert_eq!(t!(fs::read_to_string(out.join("big.txt"))), contents);
+    assert_eq!(t!(fs::read_to_string(out.join("small.txt"))), "small\n");
+    let labels = (1..=3)
+        .map(|i| format!("label Volume {}", i).into_bytes())
+        .collect::<Vec<_>>();
+    assert_eq!(ar.volume_labels(), labels);
+}
+
 #[test]
 fn path_separators() {
     let mut ar = Builder::new(Vec::new());
@@ -1443,3 +2542,773 @@ fn ownership_preserving() {
         assert!(ar.unpack(td.path()).is_err());
     }
 }
+
+#[test]
+fn unpack_transactional() {
+    use tar::OverwritePolicy;
+
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let dst = td.path().join("release");
+
+    // The hard link to a missing file fails after `a` was unpacked.
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(1);
+    t!(ar.append_data(&mut header, "a", &b"a"[..]));
+    let mut header = Header::new_gnu();
+    header.set_size(0);
+    header.set_entry_type(EntryType::Link);
+    t!(ar.append_link(&mut header, "b", "missing"));
+    let bytes = t!(ar.into_inner());
+
+    let mut ar = Archive::new(&bytes[..]);
+    ar.set_transactional(true);
+    assert!(ar.unpack(&dst).is_err());
+    assert_eq!(t!(fs::read_dir(td.path())).count(), 0);
+
+    t!(fs::create_dir(&dst));
+    t!(fs::write(dst.join("stale"), "stale"));
+    let mut ar = Archive::new(&bytes[..]);
+    ar.set_transactional(true);
+    assert!(ar.unpack(&dst).is_err());
+    let names = t!(fs::read_dir(td.path()))
+        .map(|e| t!(e).file_name())
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["release"]);
+    assert_eq!(t!(fs::read_to_string(dst.join("stale"))), "stale");
+
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(1);
+    t!(ar.append_data(&mut header, "dir/a", &b"a"[..]));
+    let bytes = t!(ar.into_inner());
+
+    // A destination which isn't empty is refused rather than replaced, even
+    // with an overwrite policy or whiteouts which would keep its files.
+    for &(policy, whiteouts) in &[
+        (OverwritePolicy::Replace, Whiteouts::Keep),
+        (OverwritePolicy::KeepOld, Whiteouts::Keep),
+        (OverwritePolicy::Backup, Whiteouts::Keep),
+        (OverwritePolicy::Replace, Whiteouts::Apply),
+    ] {
+        let mut ar = Archive::new(&bytes[..]);
+        ar.set_transactional(true);
+        ar.set_overwrite_policy(policy);
+        ar.set_whiteouts(whiteouts);
+        assert!(ar.unpack(&dst).is_err());
+        let names = t!(fs::read_dir(td.path()))
+            .map(|e| t!(e).file_name())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["release"]);
+        assert_eq!(t!(fs::read_to_string(dst.join("stale"))), "stale");
+        assert!(!dst.join("dir").exists());
+    }
+
+    // An empty one is replaced.
+    t!(fs::remove_file(dst.join("stale")));
+    let mut ar = Archive::new(&bytes[..]);
+    ar.set_transactional(true);
+    t!(ar.unpack(&dst));
+    let names = t!(fs::read_dir(td.path()))
+        .map(|e| t!(e).file_name())
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["release"]);
+    assert_eq!(t!(fs::read_to_string(dst.join("dir/a"))), "a");
+
+    // So is a missing one.
+    t!(fs::remove_dir_all(&dst));
+    let mut ar = Archive::new(&bytes[..]);
+    ar.set_transactional(true);
+    t!(ar.unpack(&dst));
+    assert_eq!(t!(fs::read_to_string(dst.join("dir/a"))), "a");
+
+    // The staging directory of a long name stays within `NAME_MAX`.
+    let dst = td.path().join("r".repeat(250));
+    let mut ar = Archive::new(&bytes[..]);
+    ar.set_transactional(true);
+    t!(ar.unpack(&dst));
+    assert_eq!(t!(fs::read_to_string(dst.join("dir/a"))), "a");
+}
+
+#[test]
+fn unpack_whiteouts() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let lower = |dst: &Path| {
+        for path in &["a/x", "a/y", "b/z", "b/c/w", "c", "keep"] {
+            let path = dst.join(path);
+            t!(fs::create_dir_all(path.parent().unwrap()));
+            t!(fs::write(path, "lower"));
+        }
+    };
+
+    let mut ar = Builder::new(Vec::new());
+    let mut append = |path: &str, entry_type: EntryType| {
+        let mut header = Header::new_gnu();
+        header.set_entry_type(entry_type);
+        header.set_size(0);
+        header.set_mode(0o755);
+        t!(ar.append_data(&mut header, path, io::empty()));
+    };
+    append("a/.wh.x", EntryType::Regular);
+    append("b/", EntryType::Directory);
+    append("b/new", EntryType::Regular);
+    append("b/.wh..wh..opq", EntryType::Regular);
+    append(".wh.c", EntryType::Regular);
+    append(".wh.missing", EntryType::Regular);
+    append("d", EntryType::Regular);
+    append(".wh.d", EntryType::Regular);
+    let bytes = t!(ar.into_inner());
+
+    let dst = td.path().join("apply");
+    lower(&dst);
+    let mut ar = Archive::new(&bytes[..]);
+    ar.set_whiteouts(Whiteouts::Apply);
+    t!(ar.unpack(&dst));
+    let mut paths = Vec::new();
+    for entry in t!(fs::read_dir(&dst)) {
+        let entry = t!(entry);
+        paths.push(entry.file_name().into_string().unwrap());
+        if t!(entry.file_type()).is_dir() {
+            for child in t!(fs::read_dir(entry.path())) {
+                let child = t!(child).file_name().into_string().unwrap();
+                paths.push(format!("{}/{}", paths.last().unwrap(), child));
+            }
+        }
+    }
+    paths.sort();
+    assert_eq!(paths, ["a", "a/y", "b", "b/new", "d", "keep"]);
+
+    // `.wh..` and `.wh...` don't hide the directory or its parent, they're
+    // unpacked as regular files.
+    for name in &[".wh..", ".wh..."] {
+        let dst = td.path().join("dots").join("dst");
+        lower(&dst);
+        let mut header = Header::new_gnu();
+        header.set_size(0);
+        header.set_entry_type(EntryType::Regular);
+        t!(header.set_path(format!("a/{}", name)));
+        header.set_cksum();
+        let mut ar = Builder::new(Vec::new());
+        t!(ar.append(&header, io::empty()));
+        let bytes = t!(ar.into_inner());
+        let mut ar = Archive::new(&bytes[..]);
+        ar.set_whiteouts(Whiteouts::Apply);
+        t!(ar.unpack(&dst));
+        assert!(dst.join("a/x").is_file());
+        assert!(dst.join("a").join(name).is_file());
+    }
+
+    // Whiteouts are unpacked as they are by default.
+    let dst = td.path().join("keep");
+    t!(Archive::new(&bytes[..]).unpack(&dst));
+    assert!(dst.join("a/.wh.x").is_file());
+    assert!(dst.join("b/.wh..wh..opq").is_file());
+
+    // Creating overlayfs whiteouts requires privileges.
+    #[cfg(all(target_os = "linux", feature = "xattr"))]
+    {
+        use std::os::unix::prelude::*;
+
+        if unsafe { libc::getuid() } != 0 {
+            return;
+        }
+        let td = t!(TempBuilder::new().prefix("tar-rs").tempdir_in("/var/tmp"));
+        let dst = td.path().join("overlay");
+        let mut ar = Archive::new(&bytes[..]);
+        ar.set_whiteouts(Whiteouts::Overlay);
+        t!(ar.unpack(&dst));
+        for path in &["a/x", "c", "missing"] {
+            let meta = t!(fs::symlink_metadata(dst.join(path)));
+            assert!(meta.file_type().is_char_device());
+            assert_eq!(meta.rdev(), 0);
+        }
+        assert!(!dst.join("a/.wh.x").exists());
+        assert!(dst.join("d").is_file());
+        let opaque = t!(xattr::get(dst.join("b"), "trusted.overlay.opaque"));
+        assert_eq!(opaque.as_deref(), Some(&b"y"[..]));
+        assert!(dst.join("b/new").is_file());
+    }
+}
+
+#[test]
+fn append_squashed() {
+    fn layer(entries: &[(&str, EntryType, &str)]) -> Archive<Cursor<Vec<u8>>> {
+        let mut ar = Builder::new(Vec::new());
+        for &(path, entry_type, data) in entries {
+            let mut header = Header::new_gnu();
+            header.set_entry_type(entry_type);
+            header.set_mode(0o644);
+            if entry_type == EntryType::Link {
+                header.set_size(0);
+                t!(ar.append_link(&mut header, path, data));
+            } else {
+                header.set_size(data.len() as u64);
+                t!(ar.append_data(&mut header, path, data.as_bytes()));
+            }
+        }
+        Archive::new(Cursor::new(t!(ar.into_inner())))
+    }
+    let long = format!("etc/{}", "x".repeat(120));
+    let layers = || {
+        vec![
+            layer(&[
+                ("etc/", EntryType::Directory, ""),
+                ("etc/passwd", EntryType::Regular, "root"),
+                ("etc/hosts", EntryType::Regular, "lower"),
+                ("usr/bin/ls", EntryType::Regular, "ls"),
+                ("var/cache/x", EntryType::Regular, "x"),
+                ("data/a", EntryType::Regular, "lower"),
+                ("data/b", EntryType::Link, "data/a"),
+                ("data/c", EntryType::Link, "data/a"),
+            ]),
+            layer(&[
+                ("etc/hosts", EntryType::Regular, "upper"),
+                ("usr/bin/.wh.ls", EntryType::Regular, ""),
+                ("var/cache/", EntryType::Directory, ""),
+                ("var/cache/.wh..wh..opq", EntryType::Regular, ""),
+                ("var/cache/y", EntryType::Regular, "y"),
+                ("data/a", EntryType::Regular, "upper"),
+                ("data/d", EntryType::Link, "data/a"),
+            ]),
+            layer(&[(&long, EntryType::Regular, "long")]),
+        ]
+    };
+
+    let check = |data: &[u8]| {
+        let mut ar = Archive::new(data);
+        let mut entries = Vec::new();
+        for entry in t!(ar.entries()) {
+            let mut entry = t!(entry);
+            let path = t!(entry.path()).to_str().unwrap().to_string();
+            let link = t!(entry.link_name()).map(|l| l.to_str().unwrap().to_string());
+            let mut data = String::new();
+            t!(entry.read_to_string(&mut data));
+            entries.push((path, entry.header().entry_type(), link, data));
+        }
+        let entry = |path: &str, entry_type, link: Option<&str>, data: &str| {
+            let link = link.map(String::from);
+            (path.to_string(), entry_type, link, data.to_string())
+        };
+        assert_eq!(
+            entries,
+            [
+                entry("etc/", EntryType::Directory, None, ""),
+                entry("etc/passwd", EntryType::Regular, None, "root"),
+                entry("data/b", EntryType::Regular, None, "lower"),
+                entry("data/c", EntryType::Link, Some("data/b"), ""),
+                entry("etc/hosts", EntryType::Regular, None, "upper"),
+                entry("var/cache/", EntryType::Directory, None, ""),
+                entry("var/cache/y", EntryType::Regular, None, "y"),
+                entry("data/a", EntryType::Regular, None, "upper"),
+                entry("data/d", EntryType::Link, Some("data/a"), ""),
+                entry(&long, EntryType::Regular, None, "long"),
+            ]
+        );
+    };
+
+    let mut ar = Builder::new(Vec::new());
+    t!(ar.append_squashed(layers()));
+    check(&t!(ar.into_inner()));
+
+    let mut ar = Streamer::new();
+    t!(ar.append_squashed(layers()));
+    let mut data = Vec::new();
+    t!(ar.read_to_end(&mut data));
+    check(&data);
+}
+
+#[test]
+#[cfg(unix)]
+fn compare() {
+    use std::os::unix::prelude::*;
+
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let root = td.path().join("root");
+    let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+
+    let mut ar = Builder::new(Vec::new());
+    let mut append = |path: &str, entry_type: EntryType, mode: u32, data: &str| {
+        let mut header = Header::new_gnu();
+        header.set_entry_type(entry_type);
+        header.set_mode(mode);
+        header.set_uid(uid.into());
+        header.set_gid(gid.into());
+        header.set_mtime(1_000_000);
+        if entry_type.is_symlink() || entry_type.is_hard_link() {
+            header.set_size(0);
+            t!(ar.append_link(&mut header, path, data));
+        } else {
+            header.set_size(data.len() as u64);
+            t!(ar.append_data(&mut header, path, data.as_bytes()));
+        }
+    };
+    append("dir/", EntryType::Directory, 0o755, "");
+    append("dir/file", EntryType::Regular, 0o644, "hello");
+    append("dir/link", EntryType::Symlink, 0o777, "file");
+    append("dir/hard", EntryType::Link, 0o644, "dir/file");
+    let bytes = t!(ar.into_inner());
+
+    t!(Archive::new(&bytes[..]).unpack(&root));
+    let compare = |contents: bool| {
+        let mut ar = Archive::new(&bytes[..]);
+        ar.set_numeric_owner(true);
+        t!(ar.compare(&root, contents))
+    };
+    assert_eq!(compare(true), []);
+
+    let file = root.join("dir/file");
+    t!(fs::write(&file, "hellO"));
+    t!(fs::set_permissions(
+        &file,
+        fs::Permissions::from_mode(0o600)
+    ));
+    t!(fs::remove_file(root.join("dir/link")));
+    t!(fs::remove_file(root.join("dir/hard")));
+    t!(fs::write(root.join("dir/hard"), "hello"));
+    let mtime = FileTime::from_unix_time(1_000_000, 0);
+    for path in &["dir", "dir/file", "dir/hard"] {
+        t!(filetime::set_file_mtime(root.join(path), mtime));
+    }
+
+    let kinds = |differences: Vec<Difference>| {
+        differences
+            .into_iter()
+            .map(|d| {
+                (
+                    d.path().strip_prefix(&root).unwrap().to_path_buf(),
+                    d.kind().clone(),
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+    let not_linked = DifferenceKind::NotLinked {
+        target: root.join("dir/file"),
+    };
+    let mode = DifferenceKind::Mode {
+        archive: 0o644,
+        fs: 0o600,
+    };
+    assert_eq!(
+        kinds(compare(false)),
+        [
+            (PathBuf::from("dir/file"), mode.clone()),
+            (PathBuf::from("dir/link"), DifferenceKind::Missing),
+            (PathBuf::from("dir/hard"), not_linked.clone()),
+        ]
+    );
+    let differences = compare(true);
+    assert_eq!(
+        differences[0].to_string(),
+        format!("{}: contents differ", file.display())
+    );
+    assert_eq!(
+        kinds(differences),
+        [
+            (PathBuf::from("dir/file"), DifferenceKind::Contents),
+            (PathBuf::from("dir/file"), mode),
+            (PathBuf::from("dir/link"), DifferenceKind::Missing),
+            (PathBuf::from("dir/hard"), not_linked),
+        ]
+    );
+
+    // The type and symlink target are compared as well.
+    t!(fs::remove_file(&file));
+    t!(fs::create_dir(&file));
+    t!(std::os::unix::fs::symlink(
+        "elsewhere",
+        root.join("dir/link")
+    ));
+    let differences = kinds(compare(false));
+    assert!(matches!(
+        differences[0],
+        (_, DifferenceKind::Type { archive: EntryType::Regular, fs }) if fs.is_dir()
+    ));
+    assert_eq!(
+        differences[1],
+        (
+            PathBuf::from("dir/link"),
+            DifferenceKind::LinkTarget {
+                archive: PathBuf::from("file"),
+                fs: PathBuf::from("elsewhere"),
+            }
+        )
+    );
+
+    // Entries below a directory replaced by a file are missing.
+    t!(fs::remove_dir_all(root.join("dir")));
+    t!(fs::write(root.join("dir"), "dir"));
+    let differences = kinds(compare(false));
+    assert!(matches!(
+        differences[0],
+        (_, DifferenceKind::Type { archive: EntryType::Directory, fs }) if fs.is_file()
+    ));
+    assert_eq!(
+        differences[1..],
+        [
+            (PathBuf::from("dir/file"), DifferenceKind::Missing),
+            (PathBuf::from("dir/link"), DifferenceKind::Missing),
+            (PathBuf::from("dir/hard"), DifferenceKind::Missing),
+        ]
+    );
+}
+
+#[test]
+fn diff() {
+    fn archive(entries: &[(&str, u32, u64, &str, Option<&str>)]) -> Vec<u8> {
+        let mut ar = Builder::new(Vec::new());
+        for &(path, mode, mtime, data, xattr) in entries {
+            if let Some(xattr) = xattr {
+                let record = format!(" SCHILY.xattr.user.tag={}\n", xattr);
+                let record = format!("{}{}", record.len() + 2, record);
+                let mut header = Header::new_ustar();
+                header.set_entry_type(EntryType::XHeader);
+                header.set_size(record.len() as u64);
+                t!(ar.append_data(&mut header, "pax", record.as_bytes()));
+            }
+            let mut header = Header::new_gnu();
+            header.set_mode(mode);
+            header.set_uid(0);
+            header.set_gid(0);
+            header.set_mtime(mtime);
+            header.set_size(data.len() as u64);
+            t!(ar.append_data(&mut header, path, data.as_bytes()));
+        }
+        t!(ar.into_inner())
+    }
+    let old = archive(&[
+        ("same", 0o644, 1, "same", None),
+        ("removed", 0o644, 1, "gone", None),
+        ("content", 0o644, 1, "before", None),
+        ("mode", 0o644, 1, "mode", None),
+        ("both", 0o644, 1, "both", None),
+        ("xattr", 0o644, 1, "xattr", Some("a")),
+        ("dup", 0o644, 1, "first", None),
+        ("dup", 0o644, 1, "second", None),
+    ]);
+    let new = archive(&[
+        ("./same", 0o644, 1, "same", None),
+        ("added", 0o644, 1, "new", None),
+        ("content", 0o644, 1, "after!", None),
+        ("mode", 0o600, 1, "mode", None),
+        ("both", 0o600, 2, "both", None),
+        ("xattr", 0o644, 1, "xattr", Some("b")),
+        ("dup", 0o644, 1, "changed", None),
+        ("dup", 0o644, 1, "second", None),
+    ]);
+
+    let changes = t!(Archive::new(&old[..]).diff(&mut Archive::new(&new[..])));
+    let summary = changes
+        .iter()
+        .map(|c| (c.path().to_str().unwrap(), c.kind(), c.metadata().to_vec()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        summary,
+        [
+            ("added", ChangeKind::Added, vec![]),
+            (
+                "both",
+                ChangeKind::Metadata,
+                vec![MetadataChange::Mode, MetadataChange::Mtime]
+            ),
+            ("content", ChangeKind::Content, vec![]),
+            ("mode", ChangeKind::Metadata, vec![MetadataChange::Mode]),
+            ("removed", ChangeKind::Removed, vec![]),
+            ("xattr", ChangeKind::Metadata, vec![MetadataChange::Xattrs]),
+        ]
+    );
+
+    let changes = t!(Archive::new(&new[..]).diff(&mut Archive::new(&old[..])));
+    assert_eq!(changes[0].kind(), ChangeKind::Removed);
+    assert_eq!(changes[0].path(), Path::new("added"));
+}
+
+#[test]
+fn new_append() {
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(3);
+    t!(ar.append_data(&mut header, "a", &b"aaa"[..]));
+    let long = "d/".repeat(60) + "b";
+    t!(ar.append_data(&mut header, &long, &b"bbb"[..]));
+    let mut bytes = t!(ar.into_inner());
+    // Padding up to a record, like GNU tar writes.
+    bytes.resize(10240, 0);
+
+    let mut ar = t!(Builder::new_append(Cursor::new(bytes)));
+    t!(ar.append_data(&mut header, "c", &b"ccc"[..]));
+    let bytes = t!(ar.into_inner()).into_inner();
+
+    let mut ar = Archive::new(&bytes[..]);
+    let entries = t!(ar.entries())
+        .map(|e| {
+            let mut e = t!(e);
+            let mut data = String::new();
+            t!(e.read_to_string(&mut data));
+            (t!(e.path()).into_owned(), data)
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        entries,
+        [
+            (PathBuf::from("a"), "aaa".to_string()),
+            (PathBuf::from(&long), "bbb".to_string()),
+            (PathBuf::from("c"), "ccc".to_string()),
+        ]
+    );
+
+    // An empty file is an empty archive.
+    let mut ar = t!(Builder::new_append(Cursor::new(Vec::new())));
+    t!(ar.append_data(&mut header, "c", &b"ccc"[..]));
+    let bytes = t!(ar.into_inner()).into_inner();
+    assert_eq!(bytes.len(), 512 * 2 + 1024);
+
+    // The data of the last member is cut off.
+    let mut bytes = bytes;
+    bytes.truncate(600);
+    assert!(Builder::new_append(Cursor::new(bytes)).is_err());
+}
+
+#[test]
+fn delete() {
+    let long = "d/".repeat(60) + "long";
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(3);
+    t!(ar.append_data(&mut header, "a", &b"aaa"[..]));
+    t!(ar.append_data(&mut header, &long, &b"lll"[..]));
+    let record = " SCHILY.xattr.user.tag=x\n";
+    let record = format!("{}{}", record.len() + 2, record);
+    let mut pax = Header::new_ustar();
+    pax.set_entry_type(EntryType::XHeader);
+    pax.set_size(record.len() as u64);
+    t!(ar.append_data(&mut pax, "pax", record.as_bytes()));
+    t!(ar.append_data(&mut header, "p", &b"ppp"[..]));
+    t!(ar.append_data(&mut header, "b", &b"bbb"[..]));
+    let bytes = t!(ar.into_inner());
+
+    fn contents(bytes: &[u8]) -> Vec<(String, String, bool)> {
+        let mut ar = Archive::new(bytes);
+        t!(ar.entries())
+            .map(|e| {
+                let mut e = t!(e);
+                let mut data = String::new();
+                t!(e.read_to_string(&mut data));
+                let pax = t!(e.pax_extensions()).is_some();
+                (t!(e.path()).to_str().unwrap().to_string(), data, pax)
+            })
+            .collect()
+    }
+    fn remove<R: Read>(e: &tar::Entry<R>) -> bool {
+        let path = t!(e.path());
+        path.starts_with("d") || path == Path::new("b")
+    }
+    let expected = [
+        ("a".to_string(), "aaa".to_string(), false),
+        ("p".to_string(), "ppp".to_string(), true),
+    ];
+
+    let copy = t!(Archive::new(&bytes[..]).delete_into(Vec::new(), remove));
+    assert_eq!(contents(&copy), expected);
+    assert_eq!(copy.len(), 512 * 6 + 1024);
+
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let path = td.path().join("a.tar");
+    t!(t!(File::create(&path)).write_all(&bytes));
+    let file = t!(fs::OpenOptions::new().read(true).write(true).open(&path));
+    t!(Archive::new(file).delete(remove));
+    let in_place = t!(fs::read(&path));
+    assert_eq!(in_place, copy);
+
+    // Nothing removed leaves the file alone.
+    let file = t!(fs::OpenOptions::new().read(true).write(true).open(&path));
+    t!(Archive::new(file).delete(|_| false));
+    assert_eq!(t!(fs::read(&path)), copy);
+
+    // A file truncated in its last member is left alone as well.
+    let truncated = &bytes[..bytes.len() - 1024 - 256];
+    t!(fs::write(&path, truncated));
+    let file = t!(fs::OpenOptions::new().read(true).write(true).open(&path));
+    assert!(Archive::new(file).delete(remove).is_err());
+    assert_eq!(t!(fs::read(&path)), truncated);
+}
+
+#[test]
+fn update() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let src = td.path().join("src");
+    t!(fs::create_dir_all(src.join("dir")));
+    t!(fs::write(src.join("a"), "a"));
+    t!(fs::write(src.join("dir/b"), "b"));
+    for path in &["a", "dir/b", "dir", ""] {
+        t!(filetime::set_file_mtime(
+            src.join(path),
+            FileTime::from_unix_time(1_000_000, 0)
+        ));
+    }
+
+    fn paths(bytes: &[u8]) -> Vec<String> {
+        let mut ar = Archive::new(bytes);
+        let mut paths = t!(ar.entries())
+            .map(|e| t!(t!(e).path()).to_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        paths.sort();
+        paths
+    }
+
+    let mut ar = Builder::new(Cursor::new(Vec::new()));
+    t!(ar.update("root", &src));
+    let bytes = t!(ar.into_inner()).into_inner();
+    assert_eq!(paths(&bytes), ["root/", "root/a", "root/dir", "root/dir/b"]);
+
+    // Nothing changed.
+    let mut ar = Builder::new(Cursor::new(bytes.clone()));
+    t!(ar.update("root", &src));
+    assert_eq!(t!(ar.into_inner()).into_inner(), bytes);
+
+    // A newer file and a new one.
+    t!(filetime::set_file_mtime(
+        src.join("a"),
+        FileTime::from_unix_time(2_000_000, 0)
+    ));
+    t!(fs::write(src.join("c"), "c"));
+    let mut ar = Builder::new(Cursor::new(bytes));
+    t!(ar.update("root", &src));
+    let bytes = t!(ar.into_inner()).into_inner();
+    assert_eq!(
+        paths(&bytes),
+        [
+            "root/",
+            "root/",
+            "root/a",
+            "root/a",
+            "root/c",
+            "root/dir",
+            "root/dir/b"
+        ]
+    );
+
+    // The last copy of a path counts.
+    let mut ar = Builder::new(Cursor::new(bytes.clone()));
+    t!(ar.update("root/a", src.join("a")));
+    assert_eq!(t!(ar.into_inner()).into_inner(), bytes);
+}
+
+#[test]
+#[cfg(unix)]
+fn plan_unpack() {
+    use tar::{OverwritePolicy, PlannedAction};
+
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    t!(fs::write(td.path().join("existing"), "old"));
+
+    let mut ar = Builder::new(Vec::new());
+    let mut append = |path: &[u8], entry_type: EntryType, link: &str, data: &[u8]| {
+        let mut header = Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(1000);
+        header.set_entry_type(entry_type);
+        // Written directly so that paths with `..` can be created.
+        header.as_old_mut().name[..path.len()].copy_from_slice(path);
+        if !link.is_empty() {
+            t!(header.set_link_name(link));
+        }
+        header.set_cksum();
+        t!(ar.append(&header, data));
+    };
+    append(b"dir/", EntryType::Directory, "", b"");
+    append(b"dir/new", EntryType::Regular, "", b"new");
+    append(b"existing", EntryType::Regular, "", b"12345");
+    append(b"../evil", EntryType::Regular, "", b"evil");
+    append(b"link", EntryType::Symlink, "/etc", b"");
+    append(b"link/passwd", EntryType::Regular, "", b"root");
+    append(b"inner", EntryType::Symlink, "dir", b"");
+    append(b"inner/x", EntryType::Regular, "", b"xx");
+    append(b"hard", EntryType::Link, "dir/new", b"");
+    append(b"hard2", EntryType::Link, "missing", b"");
+    append(b"dir/new", EntryType::Regular, "", b"new");
+    let bytes = t!(ar.into_inner());
+
+    let mut ar = Archive::new(&bytes[..]);
+    let plan = t!(ar.plan_unpack(td.path()));
+    let actions = plan
+        .entries()
+        .iter()
+        .map(|e| {
+            let dst = e
+                .dst()
+                .map(|d| d.strip_prefix(td.path()).unwrap().to_path_buf());
+            (
+                String::from_utf8_lossy(e.path_bytes()).into_owned(),
+                dst,
+                e.action(),
+            )
+        })
+        .collect::<Vec<_>>();
+    let some = |p: &str| Some(PathBuf::from(p));
+    assert_eq!(
+        actions,
+        [
+            (
+                "dir/new".to_string(),
+                some("dir/new"),
+                PlannedAction::Create
+            ),
+            (
+                "existing".to_string(),
+                some("existing"),
+                PlannedAction::Overwrite
+            ),
+            ("../evil".to_string(), None, PlannedAction::SkipTraversal),
+            ("link".to_string(), some("link"), PlannedAction::Create),
+            (
+                "link/passwd".to_string(),
+                some("link/passwd"),
+                PlannedAction::RejectOutside
+            ),
+            ("inner".to_string(), some("inner"), PlannedAction::Create),
+            (
+                "inner/x".to_string(),
+                some("inner/x"),
+                PlannedAction::Create
+            ),
+            ("hard".to_string(), some("hard"), PlannedAction::Create),
+            (
+                "hard2".to_string(),
+                some("hard2"),
+                PlannedAction::MissingLinkTarget
+            ),
+            (
+                "dir/new".to_string(),
+                some("dir/new"),
+                PlannedAction::Overwrite
+            ),
+            ("dir/".to_string(), some("dir"), PlannedAction::Existing),
+        ]
+    );
+    assert_eq!(plan.entries()[4].link_name(), None);
+    assert_eq!(plan.entries()[3].link_name(), Some(Path::new("/etc")));
+    assert_eq!(plan.total_size(), 3 + 5 + 2 + 3);
+    assert!(plan.would_fail());
+
+    // Nothing was written.
+    let names = t!(fs::read_dir(td.path()))
+        .map(|e| t!(e).file_name())
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["existing"]);
+
+    let mut ar = Archive::new(&bytes[..]);
+    ar.set_overwrite_policy(OverwritePolicy::KeepOld);
+    let plan = t!(ar.plan_unpack(td.path()));
+    assert_eq!(plan.entries()[1].action(), PlannedAction::Keep);
+    assert_eq!(plan.entries()[9].action(), PlannedAction::Keep);
+
+    // Transactional unpacks need an empty destination.
+    ar = Archive::new(&bytes[..]);
+    ar.set_transactional(true);
+    assert!(ar.plan_unpack(td.path()).is_err());
+    let empty = td.path().join("empty");
+    t!(fs::create_dir(&empty));
+    let plan = t!(ar.plan_unpack(&empty));
+    assert_eq!(plan.entries()[1].action(), PlannedAction::Create);
+}
@@ -0,0 +1,9 @@
+#!/bin/sh
+set -eu
+seq 1 1200 > big.txt
+echo small > small.txt
+tar --format=gnu -b 1 -c -M -L 3 -V label \
+  -f multivolume-1.tar -f multivolume-2.tar \
+  -f multivolume-3.tar -f multivolume-4.tar \
+  big.txt small.txt < /dev/null
+rm big.txt small.txt
@@ -0,0 +1,200 @@
+#![cfg(feature = "async")]
+
+extern crate tar;
+extern crate tempfile;
+extern crate tokio;
+
+use std::fs;
+use std::io::prelude::*;
+
+use tar::{Archive, AsyncArchive, AsyncBuilder, AsyncStreamer, EntryType, Header};
+use tempfile::Builder as TempBuilder;
+use tokio::io::AsyncReadExt;
+
+macro_rules! t {
+    ($e:expr) => {
+        match $e {
+            Ok(v) => v,
+            Err(e) => panic!("{} returned {}", stringify!($e), e),
+        }
+    };
+}
+
+macro_rules! tar {
+    ($e:expr) => {
+        &include_bytes!(concat!("archives/", $e))[..]
+    };
+}
+
+type Listing = Vec<(Vec<u8>, u64, Option<Vec<u8>>, Vec<u8>)>;
+
+fn sync_listing(bytes: &[u8]) -> Listing {
+    let mut ar = Archive::new(bytes);
+    let mut ret = Vec::new();
+    for entry in t!(ar.entries()) {
+        let mut entry = t!(entry);
+        let mut data = Vec::new();
+        t!(entry.read_to_end(&mut data));
+        let link = entry.link_name_bytes().map(|l| l.into_owned());
+        ret.push((entry.path_bytes().into_owned(), entry.size(), link, data));
+    }
+    ret
+}
+
+async fn async_listing(bytes: &[u8]) -> Listing {
+    let mut ar = AsyncArchive::new(bytes);
+    let mut ret = Vec::new();
+    while let Some(mut entry) = t!(ar.next_entry().await) {
+        let mut data = Vec::new();
+        t!(entry.read_to_end(&mut data).await);
+        let link = entry.link_name_bytes().map(|l| l.into_owned());
+        ret.push((entry.path_bytes().into_owned(), entry.size(), link, data));
+    }
+    ret
+}
+
+#[tokio::test]
+async fn reading_matches_sync() {
+    let archives = [
+        tar!("reading_files.tar"),
+        tar!("directory.tar"),
+        tar!("link.tar"),
+        tar!("pax.tar"),
+        tar!("pax_size.tar"),
+        tar!("7z_long_path.tar"),
+        tar!("sparse.tar"),
+        tar!("sparse-1.tar"),
+    ];
+    for bytes in archives.iter() {
+        let expected = sync_listing(bytes);
+        assert!(!expected.is_empty());
+        assert_eq!(async_listing(bytes).await, expected);
+    }
+}
+
+#[tokio::test]
+async fn reading_skips_unread_data() {
+    let mut ar = AsyncArchive::new(tar!("reading_files.tar"));
+
+    let entry = t!(ar.next_entry().await).unwrap();
+    assert_eq!(&*entry.path_bytes(), b"a");
+    drop(entry);
+
+    let mut entry = t!(ar.next_entry().await).unwrap();
+    assert_eq!(&*entry.path_bytes(), b"b");
+    assert_eq!(entry.raw_header_position(), 512 * 2);
+    let mut s = String::new();
+    t!(entry.read_to_string(&mut s).await);
+    assert_eq!(s, "b\nb\nb\nb\nb\nb\nb\nb\nb\nb\nb\n");
+    drop(entry);
+
+    assert!(t!(ar.next_entry().await).is_none());
+}
+
+#[tokio::test]
+async fn reading_truncated_entry() {
+    let bytes = tar!("reading_files.tar");
+    let mut ar = AsyncArchive::new(&bytes[..512 + 10]);
+    let mut entry = t!(ar.next_entry().await).unwrap();
+    let mut data = Vec::new();
+    assert!(entry.read_to_end(&mut data).await.is_err());
+}
+
+#[tokio::test]
+async fn reading_bad_checksum() {
+    let mut bytes = tar!("reading_files.tar").to_vec();
+    bytes[0] ^= 1;
+    let mut ar = AsyncArchive::new(&bytes[..]);
+    assert!(ar.next_entry().await.is_err());
+    assert!(t!(ar.next_entry().await).is_none());
+}
+
+fn fill_dir(dir: &std::path::Path) {
+    t!(fs::create_dir(dir.join("sub")));
+    t!(fs::write(dir.join("sub/file"), "file contents\n"));
+    t!(fs::write(dir.join("big"), vec![7; 1500]));
+}
+
+async fn build_with_builder(dir: &std::path::Path) -> Vec<u8> {
+    let mut ar = AsyncBuilder::new(Vec::new());
+    let long = "a".repeat(150);
+    let mut header = Header::new_gnu();
+    header.set_size(5);
+    header.set_mtime(0);
+    t!(ar.append_data(&mut header, &long, &b"hello"[..]).await);
+
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Symlink);
+    header.set_size(0);
+    header.set_mtime(0);
+    t!(ar.append_link(&mut header, "link", &long).await);
+
+    t!(ar.append_dir_all("dir", dir).await);
+    t!(ar.into_inner().await)
+}
+
+#[tokio::test]
+async fn builder_round_trip() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    fill_dir(td.path());
+    let bytes = build_with_builder(td.path()).await;
+
+    let long = "a".repeat(150);
+    let listing = sync_listing(&bytes);
+    assert_eq!(listing[0].0, long.as_bytes());
+    assert_eq!(listing[0].3, b"hello");
+    assert_eq!(listing[1].0, b"link");
+    assert_eq!(listing[1].2.as_deref(), Some(long.as_bytes()));
+
+    let files = listing
+        .iter()
+        .filter(|e| e.1 > 0)
+        .map(|e| (String::from_utf8(e.0.clone()).unwrap(), e.3.clone()))
+        .collect::<Vec<_>>();
+    assert!(files.contains(&("dir/sub/file".to_string(), b"file contents\n".to_vec())));
+    assert!(files.contains(&("dir/big".to_string(), vec![7; 1500])));
+
+    // The archive is terminated by two blocks of zeros.
+    assert_eq!(bytes.len() % 512, 0);
+    assert!(bytes[bytes.len() - 1024..].iter().all(|b| *b == 0));
+}
+
+#[tokio::test]
+async fn streamer_matches_builder() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    fill_dir(td.path());
+    let expected = build_with_builder(td.path()).await;
+
+    let mut ar = AsyncStreamer::new();
+    let long = "a".repeat(150);
+    let mut header = Header::new_gnu();
+    header.set_size(5);
+    header.set_mtime(0);
+    t!(ar.append_data(&mut header, &long, &b"hello"[..]));
+
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Symlink);
+    header.set_size(0);
+    header.set_mtime(0);
+    t!(ar.append_link(&mut header, "link", &long));
+
+    t!(ar.append_dir_all("dir", td.path()).await);
+
+    let mut bytes = Vec::new();
+    t!(ar.read_to_end(&mut bytes).await);
+    assert_eq!(bytes, expected);
+}
+
+#[tokio::test]
+async fn streamer_file_changed_size() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let path = td.path().join("file");
+    t!(fs::write(&path, "long contents"));
+
+    let mut ar = AsyncStreamer::new();
+    t!(ar.append_path_with_name(&path, "file").await);
+    t!(fs::write(&path, "short"));
+
+    let mut bytes = Vec::new();
+    assert!(ar.read_to_end(&mut bytes).await.is_err());
+}
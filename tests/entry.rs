@@ -1,3 +1,4 @@
+extern crate filetime;
 extern crate tar;
 extern crate tempfile;
 
@@ -377,3 +378,583 @@ fn modify_symlink_just_created() {
     t!(t!(File::open(&test)).read_to_end(&mut contents));
     assert_eq!(contents.len(), 0);
 }
+
+#[test]
+#[cfg(unix)]
+fn unpack_rewritten_paths() {
+    use std::os::unix::fs::MetadataExt;
+
+    let mut ar = tar::Builder::new(Vec::new());
+    let mut append = |path: &str, entry_type: tar::EntryType, link: Option<&str>| {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(0);
+        header.set_mode(0o755);
+        header.set_entry_type(entry_type);
+        match link {
+            Some(link) => t!(ar.append_link(&mut header, path, link)),
+            None => t!(ar.append_data(&mut header, path, &[][..])),
+        }
+    };
+    append("project-1.2.3/", tar::EntryType::Directory, None);
+    append("project-1.2.3/src/a.rs", tar::EntryType::Regular, None);
+    append("project-1.2.3/docs/x.md", tar::EntryType::Regular, None);
+    append("project-1.2.3/a.bak", tar::EntryType::Regular, None);
+    append(
+        "project-1.2.3/link",
+        tar::EntryType::Link,
+        Some("project-1.2.3/docs/x.md"),
+    );
+    let bytes = t!(ar.into_inner());
+
+    let td = t!(Builder::new().prefix("tar").tempdir());
+    let mut ar = tar::Archive::new(&bytes[..]);
+    ar.set_strip_components(1);
+    ar.map_path_prefix("docs", "share/doc");
+    ar.map_path_prefix("src", "");
+    ar.set_path_rewriter(|path| match path.extension() {
+        Some(ext) if ext == "bak" => None,
+        _ => Some(path.to_path_buf()),
+    });
+    t!(ar.unpack(td.path()));
+
+    let mut found = t!(std::fs::read_dir(td.path()))
+        .map(|e| t!(e).file_name().into_string().unwrap())
+        .collect::<Vec<_>>();
+    found.sort();
+    assert_eq!(found, ["a.rs", "link", "share"]);
+    t!(File::open(td.path().join("share/doc/x.md")));
+    let link = t!(td.path().join("link").metadata());
+    let target = t!(td.path().join("share/doc/x.md").metadata());
+    assert_eq!(link.ino(), target.ino());
+
+    // A rewritten entry is skipped by `unpack_in` like any other.
+    let mut ar = tar::Archive::new(&bytes[..]);
+    ar.set_strip_components(3);
+    for entry in t!(ar.entries()) {
+        let mut entry = t!(entry);
+        assert!(!t!(entry.unpack_in(td.path())));
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn unpack_beneath(bytes: &[u8], dst: &std::path::Path) -> std::io::Result<()> {
+    let mut ar = tar::Archive::new(bytes);
+    ar.set_unpack_beneath(true);
+    ar.unpack(dst)
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn unpack_beneath_entries() {
+    let td = t!(Builder::new().prefix("tar").tempdir());
+    let mut ar = tar::Builder::new(Vec::new());
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(0);
+    header.set_entry_type(tar::EntryType::Directory);
+    header.set_mode(0o755);
+    t!(ar.append_data(&mut header, "d", &[][..]));
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(3);
+    header.set_mode(0o644);
+    header.set_mtime(1_000_000);
+    t!(ar.append_data(&mut header, "d/e/f", &b"foo"[..]));
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(0);
+    header.set_entry_type(tar::EntryType::Symlink);
+    t!(ar.append_link(&mut header, "l", "d"));
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(0);
+    header.set_entry_type(tar::EntryType::Link);
+    t!(ar.append_link(&mut header, "h", "d/e/f"));
+
+    // Absolute hard link targets inside the destination are accepted.
+    let mut header = tar::Header::new_gnu();
+    header.set_size(0);
+    header.set_entry_type(tar::EntryType::Link);
+    t!(ar.append_link(&mut header, "a", td.path().join("d/e/f")));
+
+    let bytes = t!(ar.into_inner());
+    t!(unpack_beneath(&bytes, td.path()));
+
+    let mut contents = String::new();
+    t!(t!(File::open(td.path().join("h"))).read_to_string(&mut contents));
+    assert_eq!(contents, "foo");
+    assert!(td.path().join("d").is_dir());
+    assert_eq!(
+        t!(td.path().join("l").read_link()),
+        std::path::Path::new("d")
+    );
+    let mtime = t!(t!(td.path().join("d/e/f").metadata()).modified());
+    let mtime = t!(mtime.duration_since(std::time::UNIX_EPOCH));
+    assert_eq!(mtime.as_secs(), 1_000_000);
+    t!(td.path().join("a").metadata());
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn unpack_beneath_stays_inside() {
+    let td = t!(Builder::new().prefix("tar").tempdir());
+    let outside = td.path().join("outside");
+    create_dir(&outside).unwrap();
+    let test = td.path().join("test");
+    t!(File::create(&test));
+
+    // A symlink to a parent directory, or an absolute one, can't be used to
+    // create files outside of the destination.
+    for target in [std::path::Path::new(".."), outside.as_path()].iter() {
+        let mut ar = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(0);
+        header.set_entry_type(tar::EntryType::Symlink);
+        t!(ar.append_link(&mut header, "symlink", target));
+        let mut header = tar::Header::new_gnu();
+        header.set_size(0);
+        t!(ar.append_data(&mut header, "symlink/foo", &[][..]));
+        let bytes = t!(ar.into_inner());
+
+        let dir = td.path().join("dir");
+        assert!(unpack_beneath(&bytes, &dir).is_err());
+        assert!(!td.path().join("foo").exists());
+        assert!(!outside.join("foo").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // Neither can hard links, or entries replacing a symlink just created.
+    let mut ar = tar::Builder::new(Vec::new());
+    let mut header = tar::Header::new_gnu();
+    header.set_size(0);
+    header.set_entry_type(tar::EntryType::Link);
+    t!(ar.append_link(&mut header, "foo", "../test"));
+    let bytes = t!(ar.into_inner());
+    assert!(unpack_beneath(&bytes, &td.path().join("dir")).is_err());
+
+    let mut ar = tar::Builder::new(Vec::new());
+    let mut header = tar::Header::new_gnu();
+    header.set_size(0);
+    header.set_entry_type(tar::EntryType::Symlink);
+    t!(ar.append_link(&mut header, "foo", "../test"));
+    let mut header = tar::Header::new_gnu();
+    header.set_size(1);
+    t!(ar.append_data(&mut header, "foo", &b"x"[..]));
+    let bytes = t!(ar.into_inner());
+    t!(unpack_beneath(&bytes, &td.path().join("dir")));
+
+    let mut contents = Vec::new();
+    t!(t!(File::open(&test)).read_to_end(&mut contents));
+    assert_eq!(contents.len(), 0);
+    assert_eq!(t!(std::fs::read(td.path().join("dir/foo"))), b"x");
+}
+
+#[derive(Debug, PartialEq)]
+enum MemNode {
+    Dir,
+    File(Vec<u8>),
+    Symlink(std::path::PathBuf),
+    Fifo,
+}
+
+#[derive(Default)]
+struct MemTree {
+    nodes: std::collections::BTreeMap<std::path::PathBuf, MemNode>,
+    modes: std::collections::BTreeMap<std::path::PathBuf, u32>,
+    owners: std::collections::BTreeMap<std::path::PathBuf, (u64, u64)>,
+}
+
+impl tar::UnpackTarget for MemTree {
+    fn create_dir(&mut self, path: &std::path::Path) -> std::io::Result<()> {
+        self.nodes.insert(path.to_path_buf(), MemNode::Dir);
+        Ok(())
+    }
+
+    fn create_file(
+        &mut self,
+        path: &std::path::Path,
+        data: &mut dyn Read,
+        _overwrite: bool,
+    ) -> std::io::Result<()> {
+        let mut contents = Vec::new();
+        data.read_to_end(&mut contents)?;
+        self.nodes
+            .insert(path.to_path_buf(), MemNode::File(contents));
+        Ok(())
+    }
+
+    fn symlink(
+        &mut self,
+        target: &std::path::Path,
+        path: &std::path::Path,
+        _overwrite: bool,
+    ) -> std::io::Result<()> {
+        self.nodes
+            .insert(path.to_path_buf(), MemNode::Symlink(target.to_path_buf()));
+        Ok(())
+    }
+
+    fn hard_link(
+        &mut self,
+        target: &std::path::Path,
+        path: &std::path::Path,
+    ) -> std::io::Result<()> {
+        let contents = match self.nodes.get(target) {
+            Some(MemNode::File(contents)) => contents.clone(),
+            _ => return Err(std::io::ErrorKind::NotFound.into()),
+        };
+        self.nodes
+            .insert(path.to_path_buf(), MemNode::File(contents));
+        Ok(())
+    }
+
+    fn mknod(
+        &mut self,
+        path: &std::path::Path,
+        entry_type: tar::EntryType,
+        _mode: u32,
+        _major: u32,
+        _minor: u32,
+    ) -> std::io::Result<()> {
+        assert_eq!(entry_type, tar::EntryType::Fifo);
+        self.nodes.insert(path.to_path_buf(), MemNode::Fifo);
+        Ok(())
+    }
+
+    fn set_permissions(&mut self, path: &std::path::Path, mode: u32) -> std::io::Result<()> {
+        self.modes.insert(path.to_path_buf(), mode);
+        Ok(())
+    }
+
+    fn set_ownership(&mut self, path: &std::path::Path, uid: u64, gid: u64) -> std::io::Result<()> {
+        self.owners.insert(path.to_path_buf(), (uid, gid));
+        Ok(())
+    }
+}
+
+#[test]
+fn unpack_into_target() {
+    use std::path::PathBuf;
+
+    let mut ar = tar::Builder::new(Vec::new());
+    let mut append = |path: &[u8], entry_type: tar::EntryType, link: &str, data: &[u8]| {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o4750);
+        header.set_entry_type(entry_type);
+        // Written directly so that paths with `..` can be created.
+        header.as_old_mut().name[..path.len()].copy_from_slice(path);
+        if !link.is_empty() {
+            t!(header.set_link_name(link));
+        }
+        header.set_cksum();
+        t!(ar.append(&header, data));
+    };
+    append(b"/dir/", tar::EntryType::Directory, "", b"");
+    append(b"./dir/sub/file", tar::EntryType::Regular, "", b"hello");
+    append(b"dir/link", tar::EntryType::Symlink, "sub/file", b"");
+    append(b"hard", tar::EntryType::Link, "/dir/sub/file", b"");
+    append(b"fifo", tar::EntryType::Fifo, "", b"");
+    append(b"../outside", tar::EntryType::Regular, "", b"nope");
+    let bytes = t!(ar.into_inner());
+
+    let mut tree = MemTree::default();
+    let mut ar = tar::Archive::new(&bytes[..]);
+    t!(ar.unpack_into(&mut tree));
+
+    let nodes = tree.nodes.iter().collect::<Vec<_>>();
+    assert_eq!(
+        nodes,
+        [
+            (&PathBuf::from("dir"), &MemNode::Dir),
+            (
+                &PathBuf::from("dir/link"),
+                &MemNode::Symlink("sub/file".into())
+            ),
+            (&PathBuf::from("dir/sub"), &MemNode::Dir),
+            (
+                &PathBuf::from("dir/sub/file"),
+                &MemNode::File(b"hello".to_vec())
+            ),
+            (&PathBuf::from("fifo"), &MemNode::Fifo),
+            (&PathBuf::from("hard"), &MemNode::File(b"hello".to_vec())),
+        ]
+    );
+    assert_eq!(tree.modes[&PathBuf::from("dir")], 0o750);
+    assert_eq!(tree.modes[&PathBuf::from("dir/sub/file")], 0o750);
+
+    // Hard links can't point outside of the target either.
+    let mut ar = tar::Builder::new(Vec::new());
+    let mut header = tar::Header::new_gnu();
+    header.set_size(0);
+    header.set_entry_type(tar::EntryType::Link);
+    t!(header.set_path("hard"));
+    t!(header.set_link_name("../file"));
+    header.set_cksum();
+    t!(ar.append(&header, &[][..]));
+    let bytes = t!(ar.into_inner());
+
+    let mut tree = MemTree::default();
+    let mut ar = tar::Archive::new(&bytes[..]);
+    let mut entries = t!(ar.entries());
+    let mut entry = t!(entries.next().unwrap());
+    assert!(entry.unpack_into(&mut tree).is_err());
+    assert!(tree.nodes.is_empty());
+}
+
+#[test]
+fn unpack_overwrite_policies() {
+    use tar::OverwritePolicy;
+
+    let mut ar = tar::Builder::new(Vec::new());
+    let mut header = tar::Header::new_gnu();
+    header.set_size(3);
+    header.set_mode(0o644);
+    header.set_mtime(1000);
+    t!(ar.append_data(&mut header, "file", &b"new"[..]));
+    let bytes = t!(ar.into_inner());
+
+    let unpack = |dst: &std::path::Path, policy: OverwritePolicy, beneath: bool| {
+        let mut ar = tar::Archive::new(&bytes[..]);
+        ar.set_overwrite_policy(policy);
+        if beneath {
+            #[cfg(target_os = "linux")]
+            ar.set_unpack_beneath(true);
+        }
+        ar.unpack(dst)
+    };
+    let read = |path: std::path::PathBuf| t!(std::fs::read_to_string(path));
+
+    for &beneath in &[false, cfg!(target_os = "linux")] {
+        let td = t!(Builder::new().prefix("tar").tempdir());
+        let file = td.path().join("file");
+        let old = |mtime: i64| {
+            t!(std::fs::write(&file, "old"));
+            let mtime = filetime::FileTime::from_unix_time(mtime, 0);
+            t!(filetime::set_file_mtime(&file, mtime));
+        };
+
+        old(2000);
+        assert!(unpack(td.path(), OverwritePolicy::Error, beneath).is_err());
+        assert_eq!(read(file.clone()), "old");
+
+        t!(unpack(td.path(), OverwritePolicy::KeepOld, beneath));
+        assert_eq!(read(file.clone()), "old");
+
+        t!(unpack(td.path(), OverwritePolicy::KeepNewer, beneath));
+        assert_eq!(read(file.clone()), "old");
+        old(500);
+        t!(unpack(td.path(), OverwritePolicy::KeepNewer, beneath));
+        assert_eq!(read(file.clone()), "new");
+
+        old(2000);
+        t!(unpack(td.path(), OverwritePolicy::Backup, beneath));
+        t!(unpack(td.path(), OverwritePolicy::Backup, beneath));
+        assert_eq!(read(file.clone()), "new");
+        assert_eq!(read(td.path().join("file.~1~")), "old");
+        assert_eq!(read(td.path().join("file.~2~")), "new");
+
+        t!(std::fs::remove_file(td.path().join("file.~1~")));
+        t!(std::fs::remove_file(td.path().join("file.~2~")));
+        old(2000);
+        t!(unpack(td.path(), OverwritePolicy::Atomic, beneath));
+        assert_eq!(read(file.clone()), "new");
+        let names = t!(std::fs::read_dir(td.path()))
+            .map(|e| t!(e).file_name())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["file"]);
+    }
+
+    // Hard links follow the policy as well.
+    let mut ar = tar::Builder::new(Vec::new());
+    t!(ar.append_data(&mut header, "src", &b"src"[..]));
+    header.set_size(0);
+    header.set_entry_type(tar::EntryType::Link);
+    t!(ar.append_link(&mut header, "link", "src"));
+    let bytes = t!(ar.into_inner());
+    for &beneath in &[false, cfg!(target_os = "linux")] {
+        for &policy in &[
+            OverwritePolicy::Error,
+            OverwritePolicy::Replace,
+            OverwritePolicy::KeepOld,
+            OverwritePolicy::KeepNewer,
+            OverwritePolicy::Backup,
+            OverwritePolicy::Atomic,
+        ] {
+            let td = t!(Builder::new().prefix("tar").tempdir());
+            let link = td.path().join("link");
+            t!(std::fs::write(&link, "old"));
+            let mtime = filetime::FileTime::from_unix_time(2000, 0);
+            t!(filetime::set_file_mtime(&link, mtime));
+
+            let mut ar = tar::Archive::new(&bytes[..]);
+            ar.set_overwrite_policy(policy);
+            if beneath {
+                #[cfg(target_os = "linux")]
+                ar.set_unpack_beneath(true);
+            }
+            let ret = ar.unpack(td.path());
+            match policy {
+                OverwritePolicy::Error => assert!(ret.is_err()),
+                _ => t!(ret),
+            }
+            let expected = match policy {
+                OverwritePolicy::Error | OverwritePolicy::KeepOld | OverwritePolicy::KeepNewer => {
+                    "old"
+                }
+                _ => "src",
+            };
+            assert_eq!(read(link.clone()), expected, "{:?}", policy);
+            if policy == OverwritePolicy::Backup {
+                assert_eq!(read(td.path().join("link.~1~")), "old");
+            }
+        }
+    }
+
+    // The temporary name stays within `NAME_MAX` for long names.
+    let long = "l".repeat(250);
+    let mut ar = tar::Builder::new(Vec::new());
+    header.set_entry_type(tar::EntryType::Regular);
+    header.set_size(3);
+    t!(ar.append_data(&mut header, &long, &b"new"[..]));
+    let bytes = t!(ar.into_inner());
+    for &beneath in &[false, cfg!(target_os = "linux")] {
+        let td = t!(Builder::new().prefix("tar").tempdir());
+        let mut ar = tar::Archive::new(&bytes[..]);
+        ar.set_overwrite_policy(OverwritePolicy::Atomic);
+        if beneath {
+            #[cfg(target_os = "linux")]
+            ar.set_unpack_beneath(true);
+        }
+        t!(ar.unpack(td.path()));
+        assert_eq!(read(td.path().join(&long)), "new");
+    }
+}
+
+struct Names;
+
+impl tar::OwnerResolver for Names {
+    fn uid(&self, name: &str) -> Option<u64> {
+        match name {
+            "alice" => Some(1001),
+            _ => None,
+        }
+    }
+
+    fn gid(&self, name: &str) -> Option<u64> {
+        match name {
+            "staff" => Some(50),
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn unpack_owner_names() {
+    use std::path::PathBuf;
+
+    let mut ar = tar::Builder::new(Vec::new());
+    for &(path, uname, gname) in &[
+        ("known", "alice", "staff"),
+        ("unknown", "bob", "wheel"),
+        ("unnamed", "", ""),
+    ] {
+        let mut header = tar::Header::new_ustar();
+        t!(header.set_path(path));
+        header.set_size(0);
+        header.set_uid(7);
+        header.set_gid(8);
+        t!(header.set_username(uname));
+        t!(header.set_groupname(gname));
+        header.set_cksum();
+        t!(ar.append(&header, &[][..]));
+    }
+    let bytes = t!(ar.into_inner());
+
+    let mut tree = MemTree::default();
+    let mut ar = tar::Archive::new(&bytes[..]);
+    ar.set_preserve_ownerships(true);
+    ar.set_owner_resolver(Names);
+    t!(ar.unpack_into(&mut tree));
+    assert_eq!(tree.owners[&PathBuf::from("known")], (1001, 50));
+    assert_eq!(tree.owners[&PathBuf::from("unknown")], (7, 8));
+    assert_eq!(tree.owners[&PathBuf::from("unnamed")], (7, 8));
+
+    let mut tree = MemTree::default();
+    let mut ar = tar::Archive::new(&bytes[..]);
+    ar.set_preserve_ownerships(true);
+    ar.set_numeric_owner(true);
+    ar.set_owner_resolver(Names);
+    t!(ar.unpack_into(&mut tree));
+    assert_eq!(tree.owners[&PathBuf::from("known")], (7, 8));
+}
+
+#[test]
+#[cfg(unix)]
+fn system_owner_resolver() {
+    use tar::OwnerResolver;
+
+    let resolver = tar::SystemOwnerResolver;
+    assert_eq!(resolver.uid("root"), Some(0));
+    assert_eq!(resolver.uid("no-such-user-for-tar-rs"), None);
+    assert_eq!(resolver.gid("no-such-group-for-tar-rs"), None);
+}
+
+#[test]
+fn unpack_id_maps() {
+    use std::path::PathBuf;
+
+    let mut ar = tar::Builder::new(Vec::new());
+    for &(path, id) in &[("root", 0), ("user", 1000), ("outside", 70000)] {
+        let mut header = tar::Header::new_gnu();
+        t!(header.set_path(path));
+        header.set_size(0);
+        header.set_uid(id);
+        header.set_gid(id);
+        header.set_cksum();
+        t!(ar.append(&header, &[][..]));
+    }
+    let bytes = t!(ar.into_inner());
+
+    let mut map = tar::IdMap::new();
+    map.add_range(0, 100000, 65536);
+    assert_eq!(map.map(0), Some(100000));
+    assert_eq!(map.map(65535), Some(165535));
+    assert_eq!(map.map(65536), None);
+
+    // Unmapped ids fail by default.
+    let mut tree = MemTree::default();
+    let mut ar = tar::Archive::new(&bytes[..]);
+    ar.set_preserve_ownerships(true);
+    ar.set_numeric_owner(true);
+    ar.set_uid_map(map.clone());
+    let err = ar.unpack_into(&mut tree).unwrap_err();
+    assert!(
+        format!("{:?}", err).contains("uid 70000 of outside is not mapped"),
+        "{:?}",
+        err
+    );
+    assert_eq!(tree.owners[&PathBuf::from("root")], (100000, 0));
+    assert_eq!(tree.owners[&PathBuf::from("user")], (101000, 1000));
+
+    map.set_unmapped(tar::UnmappedId::Fallback(65534));
+    let mut tree = MemTree::default();
+    let mut ar = tar::Archive::new(&bytes[..]);
+    ar.set_preserve_ownerships(true);
+    ar.set_numeric_owner(true);
+    ar.set_uid_map(map.clone());
+    ar.set_gid_map(map.clone());
+    t!(ar.unpack_into(&mut tree));
+    assert_eq!(tree.owners[&PathBuf::from("user")], (101000, 101000));
+    assert_eq!(tree.owners[&PathBuf::from("outside")], (65534, 65534));
+
+    // Maps can be set on single entries as well.
+    let mut tree = MemTree::default();
+    let mut ar = tar::Archive::new(&bytes[..]);
+    ar.set_preserve_ownerships(true);
+    ar.set_numeric_owner(true);
+    let mut entry = t!(t!(ar.entries()).next().unwrap());
+    entry.set_gid_map(map);
+    t!(entry.unpack_into(&mut tree));
+    assert_eq!(tree.owners[&PathBuf::from("root")], (0, 100000));
+}